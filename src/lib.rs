@@ -27,162 +27,969 @@ extern crate rustc;
 #[macro_use]
 extern crate rustc_plugin;
 
-#[macro_use]
-extern crate lazy_static;
-
 use std::ops::Deref;
-use std::sync::Mutex;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 
 use rustc_plugin::Registry;
 
+use syntax::ptr::P;
 use syntax::parse::parser::Parser;
-use syntax::ext::base::SyntaxExtension::IdentTT;
-use syntax::ext::base::{ExtCtxt, MacResult, DummyResult};
+use syntax::ext::base::SyntaxExtension::{IdentTT, NormalTT};
+use syntax::ext::base::{ExtCtxt, MacResult, MacEager, DummyResult};
+use syntax::util::small_vector::SmallVector;
 use syntax::codemap::Span;
-use syntax::parse::token::{intern, Eof, Token, BinOpToken, DelimToken};
+use syntax::print::pprust;
+use syntax::attr::{self, AttrMetaMethods};
+use syntax::parse::token::{intern, keywords, Eof, Token, BinOpToken, DelimToken};
 use syntax::ast::*;
 
 use system::*;
 
-mod system;
+pub mod system;
 mod util;
 
-lazy_static! {
-    pub static ref DEFINED_SYSTEMS: Mutex<HashMap<String, SystemInfo>> = Mutex::new(HashMap::new());
+/// An error found while checking a system definition, held on to rather than emitted straight away
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub note: Option<(Span, String)>
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: &str) -> Diagnostic {
+        Diagnostic {
+            span: span,
+            message: message.to_owned(),
+            note: None
+        }
+    }
+
+    pub fn with_note(mut self, span: Span, note: &str) -> Diagnostic {
+        self.note = Some((span, note.to_owned()));
+        self
+    }
+
+    pub fn emit(&self, ctx: &ExtCtxt) {
+        match self.note {
+            Some((span, ref note)) => ctx.struct_span_err(self.span, &self.message).span_note(span, note).emit(),
+            None => ctx.span_err(self.span, &self.message)
+        }
+    }
+}
+
+thread_local! {
+    pub static DEFINED_SYSTEMS: RefCell<HashMap<String, SystemInfo>> = RefCell::new(HashMap::new());
+
+    // Systems whose definitions were rejected, so that objects implemented for them do not report them as undefined
+    pub static FAILED_SYSTEMS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
 }
 
 #[plugin_registrar]
 pub fn plugin_registrar(reg: &mut Registry) {
     reg.register_syntax_extension(intern("handlers_define_system"), IdentTT(Box::new(define_system_macro), None, false));
 
+    reg.register_syntax_extension(intern("handlers_define_systems"), NormalTT(Box::new(define_systems_macro), None, false));
+
     reg.register_syntax_extension(intern("handlers_impl_object"), IdentTT(Box::new(impl_object_macro), None, false));
 }
 
 fn define_system_macro<'a>(ctx: &'a mut ExtCtxt, macro_span: Span, ident: Ident, tts: Vec<TokenTree>) -> Box<MacResult + 'a> {
-    let name = ident.name.as_str().deref().to_owned();
+    match define_system(ctx, macro_span, ident, tts) {
+        Some(items) => MacEager::items(SmallVector::many(items)),
+        None => DummyResult::any(macro_span)
+    }
+}
 
-    let mut systems = DEFINED_SYSTEMS.lock().unwrap();
-    if let Some(ref system) = systems.get(&name) {
-        ctx.struct_span_err(macro_span, &format!("Redefinition of system '{}'", name))
-            .span_note(system.span, "Previous definition was at:")
-            .emit();
+fn define_systems_macro<'a>(ctx: &'a mut ExtCtxt, macro_span: Span, tts: &[TokenTree]) -> Box<MacResult + 'a> {
+    let mut parser = ctx.new_parser_from_tts(tts);
+    let mut items = Vec::new();
 
-        return DummyResult::any(macro_span);
+    loop {
+        if parser.check(&Eof) {
+            break
+        }
+
+        let span = parser.span;
+
+        if !eat_modifier(&mut parser, "system") {
+            ctx.span_err(span, "Expected a system definition, e.g. 'system <name> { .. }'");
+            return DummyResult::any(macro_span);
+        }
+
+        let name = match parser.parse_ident() {
+            Ok(ident) => ident,
+
+            Err(mut err) => {
+                err.emit();
+                return DummyResult::any(macro_span);
+            }
+        };
+
+        match parser.parse_token_tree() {
+            Ok(TokenTree::Delimited(_, ref tts)) => match define_system(ctx, span, name, tts.tts.clone()) {
+                Some(system_items) => items.extend(system_items),
+                None => return DummyResult::any(macro_span)
+            },
+
+            Ok(_) => {
+                ctx.span_err(span, &format!("Expected a delimited definition for system '{}'", name));
+                return DummyResult::any(macro_span);
+            },
+
+            Err(mut err) => {
+                err.emit();
+                return DummyResult::any(macro_span);
+            }
+        }
     }
 
-    let mut system = SystemInfo::new(ident, macro_span);
-    let mut parser = ctx.new_parser_from_tts(&tts);
+    MacEager::items(SmallVector::many(items))
+}
 
-    if parser.check(&Eof) {
-        ctx.span_err(macro_span, "Expected list of handler definitions");
-        return DummyResult::any(macro_span);
+fn define_system(ctx: &mut ExtCtxt, macro_span: Span, ident: Ident, tts: Vec<TokenTree>) -> Option<Vec<P<Item>>> {
+    match parse_system(ctx, macro_span, ident, tts) {
+        Ok(system) => {
+            let items = system.generate_items();
+            DEFINED_SYSTEMS.with(|systems| systems.borrow_mut().insert(system.name.name.as_str().deref().to_owned(), system));
+            Some(items)
+        },
+
+        Err(errors) => {
+            for error in errors.iter() {
+                error.emit(ctx);
+            }
+
+            FAILED_SYSTEMS.with(|systems| systems.borrow_mut().insert(ident.name.as_str().deref().to_owned()));
+            None
+        }
     }
+}
+
+// Parsing and validation are kept apart from generating any code, so that the checks can be exercised on their own.
+// Every error the plugin finds itself is given back as a diagnostic, but those from libsyntax's parser are emitted as
+// soon as they are found, so a definition with a syntax error may give back none of its own
+pub fn parse_system(ctx: &mut ExtCtxt, macro_span: Span, ident: Ident, tts: Vec<TokenTree>) -> Result<SystemInfo, Vec<Diagnostic>> {
+    let name = ident.name.as_str().deref().to_owned();
+
+    let previous = DEFINED_SYSTEMS.with(|systems| systems.borrow().get(&name).map(|system| system.span));
+    if let Some(span) = previous {
+        return Err(vec![
+            Diagnostic::new(macro_span, &format!("Redefinition of system '{}'", name))
+                .with_note(span, "Previous definition was at:")
+        ]);
+    }
+
+    let mut system = SystemInfo::new(ident, macro_span);
+    let mut parser = ctx.new_parser_from_tts(&tts);
+    let mut errors = Vec::new();
 
     if parser.check(&Token::BinOp(BinOpToken::Star)) {
         parser.expect(&Token::BinOp(BinOpToken::Star)).unwrap();
 
         if let Err(mut err) = parser.expect(&Token::Colon) {
             err.emit();
-            return DummyResult::any(macro_span);
+            return Err(errors);
+        }
+
+        loop {
+            if parser.check(&Token::Semi) {
+                parser.expect(&Token::Semi).unwrap();
+                break;
+            }
+
+            if let Token::Lifetime(_) = parser.token {
+                match parse_lifetime_requirement(&mut parser, &mut errors) {
+                    Some(lifetime) => system.add_lifetime_requirement(lifetime),
+                    None => return Err(errors)
+                }
+            } else {
+                match parser.parse_ident() {
+                    Ok(ident) => system.add_requirement(ident, parser.last_span),
+                    Err(mut err) => {
+                        err.emit();
+                        return Err(errors);
+                    }
+                };
+            }
+
+            if !parser.check(&Token::Comma) {
+                if parser.check(&Token::Semi) {
+                    parser.expect(&Token::Semi).unwrap();
+                }
+
+                break;
+            }
+
+            parser.expect(&Token::Comma).unwrap();
+        }
+    }
+
+    loop {
+        if parser.check(&Eof) {
+            break
+        }
+
+        if eat_modifier(&mut parser, "batch") {
+            match parse_batch_definition(ctx, &mut parser, &mut errors) {
+                Some(batch) => system.add_batch(batch),
+                None => return Err(errors)
+            }
+
+            continue
+        }
+
+        if eat_modifier(&mut parser, "phase") {
+            match parse_batch_definition(ctx, &mut parser, &mut errors) {
+                Some(phase) => system.add_phase(phase),
+                None => return Err(errors)
+            }
+
+            continue
+        }
+
+        if is_system_option(&parser) {
+            if !parse_system_option(ctx, &mut parser, &mut system, &mut errors) {
+                return Err(errors)
+            }
+
+            continue
+        }
+
+        match parse_handler_definition(ctx, &mut parser, &mut errors) {
+            Some(handler) => if cfg_enabled(ctx, &handler.attrs, &mut errors) {
+                system.add_handler(handler)
+            } else {
+                system.disabled_handlers.push(handler.name)
+            },
+
+            None => return Err(errors)
+        }
+    }
+
+    errors.extend(validate_system(&system, macro_span));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    system.add_context_args();
+    Ok(system)
+}
+
+// Checks everything that refers to something else in the definition, so that mistakes are reported here rather than as
+// confusing errors in the generated code
+pub fn validate_system(system: &SystemInfo, macro_span: Span) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+
+    let generated = system.generated_names();
+    for (i, handler) in system.handlers.iter().enumerate() {
+        let trait_name = handler.trait_name(system);
+        if generated.iter().any(|name| name.name == trait_name.name) {
+            errors.push(Diagnostic::new(macro_span, &format!("Handler trait '{}' collides with a type generated for system '{}'", trait_name, system.name)));
+        }
+
+        if system.handlers[..i].iter().any(|other| other.name.name == handler.name.name) {
+            errors.push(Diagnostic::new(macro_span, &format!("Handler '{}' is defined more than once", handler.name)));
+        }
+    }
+
+    for batch in system.batches.iter().chain(system.phases.iter()) {
+        if let Err(msg) = system.batch_args(batch) {
+            errors.push(Diagnostic::new(batch.span, &msg));
+        }
+    }
+
+    // Problems within a single phase have already been reported
+    if system.phases.iter().all(|phase| system.batch_args(phase).is_ok()) && !system.phases.is_empty() {
+        let phases = system.phases_batch();
+        if let Err(msg) = system.batch_args(&phases) {
+            errors.push(Diagnostic::new(phases.span, &msg));
+        }
+    }
+
+    // Parallel signals hand each object to rayon by reference, so they can neither consume nor fold over them
+    for function in system.handlers.iter().flat_map(|handler| handler.fns.iter()).filter(|function| function.parallel) {
+        if function.consuming {
+            errors.push(Diagnostic::new(macro_span, &format!("Signal '{}' cannot be both parallel and consuming", function.source_name)));
+        }
+
+        if function.fold {
+            errors.push(Diagnostic::new(macro_span, &format!("Parallel signal '{}' cannot be folded", function.source_name)));
+        }
+    }
+
+    if system.storage != Storage::Box {
+        for handler in system.handlers.iter() {
+            if handler.exclusive {
+                errors.push(Diagnostic::new(macro_span, &format!("Exclusive handler '{}' requires Box storage", handler.name)));
+            }
+
+            for function in handler.fns.iter().filter(|function| function.parallel || function.consuming) {
+                errors.push(Diagnostic::new(macro_span, &format!("Parallel or consuming signal '{}' requires Box storage", function.source_name)));
+            }
+        }
+
+        if system.clone_fn.is_some() {
+            errors.push(Diagnostic::new(macro_span, "clone_fn requires Box storage, as shared objects cannot be copied"));
+        }
+    }
+
+    if system.no_std {
+        if system.storage == Storage::Arc {
+            errors.push(Diagnostic::new(macro_span, "Arc storage requires std"));
+        }
+
+        for handler in system.handlers.iter() {
+            for function in handler.fns.iter().filter(|function| function.parallel) {
+                errors.push(Diagnostic::new(macro_span, &format!("Parallel signal '{}' requires std", function.source_name)));
+            }
+        }
+    }
+
+    if system.commands {
+        for handler in system.handlers.iter() {
+            for function in handler.fns.iter().filter(|function| function.parallel) {
+                errors.push(Diagnostic::new(macro_span, &format!("Parallel signal '{}' cannot be used with commands", function.source_name)));
+            }
+        }
+
+        if system.dispatch_trait {
+            errors.push(Diagnostic::new(macro_span, "dispatch_trait cannot be used with commands, as the trait has nowhere to queue them"));
+        }
+    }
+
+    for handler in system.disjoint.iter().flat_map(|handlers| handlers.iter()) {
+        // Handlers left out by cfg are simply not checked
+        let defined = system.handlers.iter().map(|other| other.name).chain(system.disabled_handlers.iter().cloned())
+            .any(|other| other.name == handler.name);
+        if !defined {
+            errors.push(Diagnostic::new(macro_span, &format!("disjoint refers to undefined handler '{}'", handler)));
+        }
+    }
+
+    for handler in system.combinations.iter().flat_map(|handlers| handlers.iter()) {
+        if !system.handlers.iter().map(|other| other.name).chain(system.disabled_handlers.iter().cloned()).any(|other| other.name == handler.name) {
+            errors.push(Diagnostic::new(macro_span, &format!("combine refers to undefined handler '{}'", handler)));
+        }
+    }
+
+    if system.from_object && system.fallible_add() {
+        errors.push(Diagnostic::new(macro_span, "from_object cannot be used with capacity, can_add_fn or disjoint, as the object could be refused"));
+    }
+
+    if system.storage != Storage::Box && !system.combinations.is_empty() {
+        errors.push(Diagnostic::new(macro_span, "combine requires Box storage"));
+    }
+
+    if system.context.is_some() {
+        for function in system.handlers.iter().flat_map(|handler| handler.fns.iter()) {
+            if function.parallel {
+                errors.push(Diagnostic::new(macro_span, &format!("Parallel signal '{}' cannot take the context, as it cannot be shared between threads", function.source_name)));
+            }
+
+            if function.args.iter().any(|arg| &*arg.name.name.as_str() == "context") {
+                errors.push(Diagnostic::new(macro_span, &format!("Signal '{}' already has an argument named 'context'", function.source_name)));
+            }
+        }
+    }
+
+    if system.userdata {
+        for function in system.handlers.iter().flat_map(|handler| handler.fns.iter()) {
+            if function.parallel {
+                errors.push(Diagnostic::new(macro_span, &format!("Parallel signal '{}' cannot take the userdata, as it cannot be shared between threads", function.source_name)));
+            }
+
+            if function.args.iter().any(|arg| &*arg.name.name.as_str() == "data") {
+                errors.push(Diagnostic::new(macro_span, &format!("Signal '{}' already has an argument named 'data'", function.source_name)));
+            }
+        }
+    }
+
+    let mut methods: Vec<(Ident, Span)> = Vec::new();
+    for function in system.handlers.iter().flat_map(|handler| handler.fns.iter()) {
+        methods.push((function.source_name, macro_span));
+    }
+
+    for batch in system.batches.iter().chain(system.phases.iter()) {
+        methods.push((batch.name, batch.span));
+    }
+
+    for name in system.generated_method_names() {
+        methods.push((name, macro_span));
+    }
+
+    for (i, &(name, span)) in methods.iter().enumerate() {
+        if methods[..i].iter().any(|&(other, _)| other.name == name.name) {
+            errors.push(Diagnostic::new(span, &format!("'{}' is used as the name of more than one signal, batch, phase or accumulator accessor", name)));
+        }
+    }
+
+    let accumulators = system.accumulators();
+    for function in system.handlers.iter().flat_map(|handler| handler.fns.iter()) {
+        if let (Some(field), Some(ty)) = (function.accumulate, function.ret.as_ref()) {
+            let expected = accumulators.iter().find(|&&(name, _)| name.name == field.name).map(|&(_, ref ty)| pprust::ty_to_string(ty));
+            if expected != Some(pprust::ty_to_string(ty)) {
+                errors.push(Diagnostic::new(macro_span, &format!("Signal '{}' accumulates into '{}' with a different type to other signals", function.source_name, field)));
+            }
+        }
+    }
+
+    errors
+}
+
+fn impl_object_macro<'a>(ctx: &'a mut ExtCtxt, macro_span: Span, ident: Ident, tts: Vec<TokenTree>) -> Box<MacResult + 'a> {
+    let name = ident.name.as_str().deref().to_owned();
+
+    let system = if let Some(system) = DEFINED_SYSTEMS.with(|systems| systems.borrow().get(&name).cloned()) {
+        system
+    } else {
+        // The system's own errors have already been reported
+        if !FAILED_SYSTEMS.with(|systems| systems.borrow().contains(&name)) {
+            ctx.span_err(macro_span, &format!("Implementing object for undefined system '{}'", name));
+        }
+
+        return DummyResult::any(macro_span);
+    };
+
+    let mut parser = ctx.new_parser_from_tts(&tts);
+
+    let obj = match parser.parse_ident() {
+        Ok(ident) => ident,
+
+        Err(mut err) => {
+            err.emit();
+            return DummyResult::any(macro_span);
+        }
+    };
+
+    if let Err(mut err) = parser.expect(&Token::Colon) {
+        err.emit();
+        return DummyResult::any(macro_span);
+    }
+
+    let mut impls = Vec::new();
+    let mut forwards = Vec::new();
+
+    loop {
+        if parser.check(&Eof) {
+            break
+        }
+
+        let span = parser.span;
+
+        let handler = match parser.parse_ident() {
+            Ok(ident) => format!("{}", ident),
+
+            Err(mut err) => {
+                err.emit();
+                return DummyResult::any(macro_span);
+            }
+        };
+
+        if parser.eat(&Token::FatArrow) {
+            let field = match parser.parse_ident() {
+                Ok(ident) => ident,
+
+                Err(mut err) => {
+                    err.emit();
+                    return DummyResult::any(macro_span);
+                }
+            };
+
+            match system.find_handler(&handler) {
+                Some(info) => if info.unsafety == Unsafety::Unsafe {
+                    ctx.span_err(span, "Unsafe handlers cannot be forwarded");
+                    return DummyResult::any(macro_span);
+                } else if info.fns.iter().any(|function| function.ret.is_some() || function.consuming || function.single || function.reads) {
+                    ctx.span_err(span, "Signals that return values, consume objects, are single or are read-only cannot be forwarded");
+                    return DummyResult::any(macro_span);
+                },
+
+                None => {
+                    ctx.span_err(span, &format!("Forwarding undefined handler '{}'", handler));
+                    return DummyResult::any(macro_span);
+                }
+            }
+
+            forwards.push((handler.clone(), field));
+        } else if system.find_handler(&handler).is_none() {
+            // Objects may list handlers that are configured out of this build
+            if !system.disabled_handlers.iter().any(|name| format!("{}", name) == handler) {
+                ctx.span_err(span, &format!("Implementing undefined handler '{}'", handler));
+            }
+        }
+
+        impls.push(handler);
+
+        if !parser.check(&Token::Comma) {
+            break
+        } else {
+            parser.expect(&Token::Comma).unwrap();
+        }
+    }
+
+    system.generate_object_impl(obj, &impls, &forwards)
+}
+
+const SYSTEM_OPTIONS: &'static [&'static str] = &[
+    "prefix_handlers",
+    "module",
+    "id_fn",
+    "can_add_fn",
+    "disjoint",
+    "clone_fn",
+    "cleanup_fn",
+    "sort_key",
+    "handler_bounds",
+    "storage",
+    "downcast",
+    "counted",
+    "commands",
+    "dispatch_trait",
+    "signals_trait",
+    "builder",
+    "from_object",
+    "priority",
+    "debug_checks",
+    "capacity",
+    "combine",
+    "context",
+    "userdata",
+    "no_std"
+];
+
+fn is_system_option(parser: &Parser) -> bool {
+    match parser.token {
+        Token::Ident(ident) => {
+            let name = ident.name.as_str();
+
+            SYSTEM_OPTIONS.iter().any(|option| *option == &*name)
+                && parser.look_ahead(1, |token| *token == Token::Semi || *token == Token::Colon)
+        },
+
+        _ => false
+    }
+}
+
+fn parse_system_option(ctx: &mut ExtCtxt, parser: &mut Parser, system: &mut SystemInfo, errors: &mut Vec<Diagnostic>) -> bool {
+    let span = parser.span;
+
+    let name = match parser.parse_ident() {
+        Ok(ident) => ident.name.as_str(),
+
+        Err(mut err) => {
+            err.emit();
+            return false
+        }
+    };
+
+    match &*name {
+        "prefix_handlers" => system.prefix_handlers = true,
+        "module" => system.module = true,
+        "downcast" => system.downcast = true,
+        "counted" => system.counted = true,
+        "commands" => system.commands = true,
+        "dispatch_trait" => system.dispatch_trait = true,
+        "signals_trait" => system.signals_trait = true,
+        "builder" => system.builder = true,
+        "from_object" => system.from_object = true,
+        "userdata" => system.userdata = true,
+        "priority" => system.priority = true,
+        "debug_checks" => system.debug_checks = true,
+        "no_std" => system.no_std = true,
+
+        "id_fn" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            match parser.parse_ident() {
+                Ok(ident) => system.id_fn = Some(ident),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+        },
+
+        "cleanup_fn" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            match parser.parse_ident() {
+                Ok(ident) => system.cleanup_fn = Some(ident),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+        },
+
+        "sort_key" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            let key_fn = match parser.parse_ident() {
+                Ok(ident) => ident,
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            };
+
+            if let Err(mut err) = parser.expect(&Token::RArrow) {
+                err.emit();
+                return false
+            }
+
+            match parser.parse_ty() {
+                Ok(ty) => system.sort_key = Some((key_fn, ty)),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+        },
+
+        "can_add_fn" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            match parser.parse_ident() {
+                Ok(ident) => system.can_add_fn = Some(ident),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+        },
+
+        "clone_fn" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            match parser.parse_ident() {
+                Ok(ident) => system.clone_fn = Some(ident),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+        },
+
+        "handler_bounds" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            loop {
+                if let Token::Lifetime(_) = parser.token {
+                    match parse_lifetime_requirement(parser, errors) {
+                        Some(lifetime) => system.add_handler_lifetime_requirement(lifetime),
+                        None => return false
+                    }
+                } else {
+                    match parser.parse_ident() {
+                        Ok(ident) => system.add_handler_requirement(ident, parser.last_span),
+
+                        Err(mut err) => {
+                            err.emit();
+                            return false
+                        }
+                    }
+                }
+
+                if !parser.check(&Token::Comma) {
+                    break
+                }
+
+                parser.expect(&Token::Comma).unwrap();
+            }
+        },
+
+        "disjoint" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            let mut handlers = Vec::new();
+
+            loop {
+                match parser.parse_ident() {
+                    Ok(ident) => handlers.push(ident),
+
+                    Err(mut err) => {
+                        err.emit();
+                        return false
+                    }
+                }
+
+                if !parser.check(&Token::Comma) {
+                    break
+                }
+
+                parser.expect(&Token::Comma).unwrap();
+            }
+
+            if handlers.len() < 2 {
+                errors.push(Diagnostic::new(span, "disjoint needs at least two handlers"));
+                return false
+            }
+
+            system.disjoint.push(handlers);
+        },
+
+        "combine" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            let mut handlers = Vec::new();
+
+            match parser.parse_token_tree() {
+                Ok(TokenTree::Delimited(_, ref tts)) => {
+                    let mut combine_parser = ctx.new_parser_from_tts(&tts.tts);
+
+                    loop {
+                        match combine_parser.parse_ident() {
+                            Ok(ident) => handlers.push(ident),
+
+                            Err(mut err) => {
+                                err.emit();
+                                return false
+                            }
+                        }
+
+                        if !combine_parser.check(&Token::Comma) {
+                            break
+                        }
+
+                        combine_parser.expect(&Token::Comma).unwrap();
+                    }
+                },
+
+                Ok(ref tt) => {
+                    errors.push(Diagnostic::new(tt.get_span(), "Expected delimited list of handlers"));
+                    return false
+                },
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+
+            if handlers.len() < 2 {
+                errors.push(Diagnostic::new(span, "combine needs at least two handlers"));
+                return false
+            }
+
+            system.combinations.push(handlers);
+        },
+
+        "capacity" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            match parser.parse_expr() {
+                Ok(expr) => system.capacity = Some(expr),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+        },
+
+        "context" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            match parser.parse_ty() {
+                Ok(ty) => system.context = Some(ty),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            }
+        },
+
+        "storage" => {
+            if let Err(mut err) = parser.expect(&Token::Colon) {
+                err.emit();
+                return false
+            }
+
+            let span = parser.span;
+
+            let storage = match parser.parse_ident() {
+                Ok(ident) => ident.name.as_str(),
+
+                Err(mut err) => {
+                    err.emit();
+                    return false
+                }
+            };
+
+            system.storage = match &*storage {
+                "Box" => Storage::Box,
+                "Rc" => Storage::Rc,
+                "Arc" => Storage::Arc,
+
+                _ => {
+                    errors.push(Diagnostic::new(span, &format!("Unknown storage type '{}', expected Box, Rc or Arc", storage)));
+                    return false
+                }
+            };
+        },
+
+        _ => {
+            errors.push(Diagnostic::new(span, &format!("Unknown system option '{}'", name)));
+            return false
+        }
+    }
+
+    if let Err(mut err) = parser.expect(&Token::Semi) {
+        err.emit();
+        return false
+    }
+
+    true
+}
+
+fn parse_batch_definition(ctx: &mut ExtCtxt, parser: &mut Parser, errors: &mut Vec<Diagnostic>) -> Option<BatchInfo> {
+    let span = parser.span;
+
+    let name = match parser.parse_ident() {
+        Ok(ident) => ident,
+
+        Err(mut err) => {
+            err.emit();
+            return None
         }
+    };
 
-        loop {
-            if parser.check(&Token::Semi) {
-                parser.expect(&Token::Semi).unwrap();
-                break;
-            }
+    let mut signals = Vec::new();
 
-            match parser.parse_ident() {
-                Ok(ident) => system.add_requirement(ident),
-                Err(mut err) => {
-                    err.emit();
-                    return DummyResult::any(macro_span);
+    match parser.parse_token_tree() {
+        Ok(TokenTree::Delimited(_, ref tts)) => {
+            let mut batch_parser = ctx.new_parser_from_tts(&tts.tts);
+
+            loop {
+                if batch_parser.check(&Eof) {
+                    break
                 }
-            };
 
-            if !parser.check(&Token::Comma) {
-                if parser.check(&Token::Semi) {
-                    parser.expect(&Token::Semi).unwrap();
+                match batch_parser.parse_ident() {
+                    Ok(ident) => signals.push(ident),
+
+                    Err(mut err) => {
+                        err.emit();
+                        return None
+                    }
                 }
 
-                break;
+                if !batch_parser.check(&Token::Semi) {
+                    break
+                } else {
+                    batch_parser.expect(&Token::Semi).unwrap();
+                }
             }
+        },
 
-            parser.expect(&Token::Comma).unwrap();
-        }
-    }
-
-    loop {
-        match parse_handler_definition(ctx, &mut parser) {
-            Some(handler) => system.add_handler(handler),
-            None => break
-        }
+        Ok(ref tt) => {
+            errors.push(Diagnostic::new(tt.get_span(), "Expected delimited list of signals"));
+            return None
+        },
 
-        if parser.check(&Eof) {
-            break
+        Err(mut err) => {
+            err.emit();
+            return None
         }
     }
 
-    let result = system.generate_ast();
-    systems.insert(name, system);
-    result
+    Some(BatchInfo {
+        name: name,
+        span: span,
+        signals: signals
+    })
 }
 
-fn impl_object_macro<'a>(ctx: &'a mut ExtCtxt, macro_span: Span, ident: Ident, tts: Vec<TokenTree>) -> Box<MacResult + 'a> {
-    let name = ident.name.as_str().deref().to_owned();
+fn cfg_enabled(ctx: &ExtCtxt, attrs: &Vec<Attribute>, errors: &mut Vec<Diagnostic>) -> bool {
+    attrs.iter().filter(|attr| attr.check_name("cfg")).all(|attr| match attr.meta_item_list() {
+        Some(items) if items.len() == 1 => attr::cfg_matches(&ctx.cfg(), &items[0], &mut attr::DummyCfgDiag),
 
-    let systems = DEFINED_SYSTEMS.lock().unwrap();
-    let system = if let Some(system) = systems.get(&name) {
-        system
-    } else {
-        ctx.span_err(macro_span, &format!("Implementing object for undefined system '{}'", name));
-        return DummyResult::any(macro_span);
-    };
+        _ => {
+            errors.push(Diagnostic::new(attr.span, "Expected a single cfg predicate"));
+            false
+        }
+    })
+}
 
-    let mut parser = ctx.new_parser_from_tts(&tts);
+fn parse_lifetime_requirement(parser: &mut Parser, errors: &mut Vec<Diagnostic>) -> Option<Lifetime> {
+    match parser.parse_lifetime() {
+        Ok(lifetime) => {
+            if &*lifetime.name.as_str() != "'static" {
+                errors.push(Diagnostic::new(lifetime.span, &format!("Lifetime requirement '{}' must be 'static, as systems are not generic over lifetimes", lifetime.name)));
+            }
 
-    let obj = match parser.parse_ident() {
-        Ok(ident) => ident,
+            Some(lifetime)
+        },
 
         Err(mut err) => {
             err.emit();
-            return DummyResult::any(macro_span);
+            None
         }
-    };
-
-    if let Err(mut err) = parser.expect(&Token::Colon) {
-        err.emit();
-        return DummyResult::any(macro_span);
     }
+}
 
-    let mut impls = Vec::new();
+fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser, errors: &mut Vec<Diagnostic>) -> Option<HandlerInfo> {
+    let attrs = match parser.parse_outer_attributes() {
+        Ok(attrs) => attrs,
 
-    loop {
-        if parser.check(&Eof) {
-            break
+        Err(mut err) => {
+            err.emit();
+            return None
         }
+    };
 
-        match parser.parse_ident() {
-            Ok(ident) => impls.push(format!("{}", ident)),
-
-            Err(mut err) => {
-                err.emit();
-                return DummyResult::any(macro_span);
-            }
-        }
+    let mut exclusive = false;
+    let mut unsafety = Unsafety::Normal;
 
-        if !parser.check(&Token::Comma) {
-            break
+    loop {
+        if eat_modifier(parser, "exclusive") {
+            exclusive = true;
+        } else if eat_modifier(parser, "unsafe") {
+            unsafety = Unsafety::Unsafe;
         } else {
-            parser.expect(&Token::Comma).unwrap();
+            break
         }
     }
 
-    system.generate_object_impl(obj, &impls)
-}
-
-fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<HandlerInfo> {
     let mut handler = match parser.parse_ident() {
         Ok(ident) => HandlerInfo::new(ident),
 
@@ -192,6 +999,10 @@ fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
         }
     };
 
+    handler.exclusive = exclusive;
+    handler.unsafety = unsafety;
+    handler.attrs = attrs;
+
     if parser.check(&Token::Colon) {
         parser.expect(&Token::Colon).unwrap();
 
@@ -200,14 +1011,21 @@ fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
                 break
             }
 
-            match parser.parse_ident() {
-                Ok(ident) => handler.add_requirement(ident),
-
-                Err(mut err) => {
-                    err.emit();
-                    return None
+            if let Token::Lifetime(_) = parser.token {
+                match parse_lifetime_requirement(parser, errors) {
+                    Some(lifetime) => handler.add_lifetime_requirement(lifetime),
+                    None => return None
                 }
-            };
+            } else {
+                match parser.parse_ident() {
+                    Ok(ident) => handler.add_requirement(ident, parser.last_span),
+
+                    Err(mut err) => {
+                        err.emit();
+                        return None
+                    }
+                };
+            }
 
             if !parser.check(&Token::Comma) {
                 break
@@ -222,7 +1040,7 @@ fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
             let mut handler_parser = ctx.new_parser_from_tts(&tts.tts);
 
             if handler_parser.check(&Eof) {
-                ctx.span_err(span, "Expected delimited list of handler functions");
+                errors.push(Diagnostic::new(span, "Expected delimited list of handler functions"));
                 return None
             }
 
@@ -231,10 +1049,37 @@ fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
                     break
                 }
 
-                match parse_handler_function_definition(ctx, &mut handler_parser) {
-                    Some(function) => handler.add_function(function),
-                    None => ()
-                };
+                if is_handler_option(&handler_parser, "feature") {
+                    handler_parser.bump();
+                    handler_parser.expect(&Token::Colon).unwrap();
+
+                    // The feature becomes a cfg attribute, which is checked along with any others the handler has
+                    match handler_parser.parse_str() {
+                        Ok((feature, _)) => handler.attrs.push(util::create_cfg_feature_attr(feature)),
+
+                        Err(mut err) => {
+                            err.emit();
+                            return None
+                        }
+                    }
+                } else if is_handler_option(&handler_parser, "order_by") {
+                    handler_parser.bump();
+                    handler_parser.expect(&Token::Colon).unwrap();
+
+                    match handler_parser.parse_ident() {
+                        Ok(key) => handler.order_by = Some(key),
+
+                        Err(mut err) => {
+                            err.emit();
+                            return None
+                        }
+                    }
+                } else {
+                    match parse_handler_function_definition(ctx, &mut handler_parser, errors) {
+                        Some(function) => handler.add_function(function),
+                        None => ()
+                    };
+                }
 
                 if !handler_parser.check(&Token::Semi) {
                     break
@@ -245,7 +1090,7 @@ fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
         },
 
         Ok(ref tt) => {
-            ctx.span_err(tt.get_span(), "Expected delimited list of handler functions");
+            errors.push(Diagnostic::new(tt.get_span(), "Expected delimited list of handler functions"));
             return None
         },
 
@@ -258,7 +1103,64 @@ fn parse_handler_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
     Some(handler)
 }
 
-fn parse_handler_function_definition(ctx: &mut ExtCtxt, parser: &mut Parser) -> Option<HandlerFnInfo> {
+fn is_handler_option(parser: &Parser, name: &str) -> bool {
+    match parser.token {
+        Token::Ident(ident) => &*ident.name.as_str() == name && parser.look_ahead(1, |token| *token == Token::Colon),
+        _ => false
+    }
+}
+
+fn eat_modifier(parser: &mut Parser, name: &str) -> bool {
+    let is_modifier = match parser.token {
+        Token::Ident(ident) => &*ident.name.as_str() == name && parser.look_ahead(1, |token| token.is_ident()),
+        _ => false
+    };
+
+    if is_modifier {
+        parser.bump();
+    }
+
+    is_modifier
+}
+
+fn parse_handler_function_definition(ctx: &mut ExtCtxt, parser: &mut Parser, errors: &mut Vec<Diagnostic>) -> Option<HandlerFnInfo> {
+    let docs = match parser.parse_outer_attributes() {
+        Ok(attrs) => attrs,
+
+        Err(mut err) => {
+            err.emit();
+            return None
+        }
+    };
+
+    for attr in docs.iter().filter(|attr| !attr.check_name("doc")) {
+        errors.push(Diagnostic::new(attr.span, "Only doc comments can be attached to signals"));
+    }
+
+    let span = parser.span;
+
+    let mut parallel = false;
+    let mut consuming = false;
+    let mut fold = false;
+    let mut single = false;
+    let mut reads = false;
+
+    loop {
+        if eat_modifier(parser, "par") {
+            parallel = true;
+        } else if eat_modifier(parser, "move") {
+            consuming = true;
+        } else if eat_modifier(parser, "fold") {
+            fold = true;
+        } else if eat_modifier(parser, "single") {
+            single = true;
+        } else if eat_modifier(parser, "reads") {
+            reads = true;
+        } else {
+            break
+        }
+    }
+
     let source = match parser.parse_ident() {
         Ok(ident) => ident,
 
@@ -268,17 +1170,63 @@ fn parse_handler_function_definition(ctx: &mut ExtCtxt, parser: &mut Parser) ->
         }
     };
 
+    let mut lifetimes = Vec::new();
+
+    // Lifetime parameters keep the handler trait object safe, unlike type parameters
+    if parser.eat(&Token::Lt) {
+        while !parser.eat(&Token::Gt) {
+            match parser.token {
+                Token::Lifetime(_) => match parser.parse_lifetime() {
+                    Ok(lifetime) => lifetimes.push(lifetime),
+
+                    Err(mut err) => {
+                        err.emit();
+                        return None
+                    }
+                },
+
+                _ => {
+                    errors.push(Diagnostic::new(parser.span, "Signals can only be generic over lifetimes, as handler traits must be object safe"));
+                    return None
+                }
+            }
+
+            if !parser.check(&Token::Gt) {
+                if let Err(mut err) = parser.expect(&Token::Comma) {
+                    err.emit();
+                    return None
+                }
+            }
+        }
+    }
+
+    let mut receiver = None;
+
     let args = match parser.parse_token_tree() {
         Ok(TokenTree::Delimited(_, ref tts)) => {
             let mut arg_parser = ctx.new_parser_from_tts(&tts.tts);
             let mut args = Vec::new();
 
+            // An explicit &self or &mut self gives the receiver of the slot
+            if arg_parser.eat(&Token::BinOp(BinOpToken::And)) {
+                receiver = Some(if arg_parser.eat_keyword(keywords::Mut) { Mutability::Mutable } else { Mutability::Immutable });
+
+                if let Err(mut err) = arg_parser.expect_keyword(keywords::SelfValue) {
+                    err.emit();
+                    return None
+                }
+
+                if arg_parser.check(&Token::Comma) {
+                    arg_parser.expect(&Token::Comma).unwrap();
+                }
+            }
+
             loop {
                 if arg_parser.check(&Eof) {
                     break
                 }
 
-                match parse_handler_function_arg(ctx, &mut arg_parser) {
+                match parse_handler_function_arg(ctx, &mut arg_parser, source, args.len()) {
                     Some(arg) => args.push(arg),
                     None => ()
                 }
@@ -294,7 +1242,7 @@ fn parse_handler_function_definition(ctx: &mut ExtCtxt, parser: &mut Parser) ->
         },
 
         Ok(ref tt) => {
-            ctx.span_err(tt.get_span(), "Expected function argument list");
+            errors.push(Diagnostic::new(tt.get_span(), "Expected function argument list"));
             return None
         },
 
@@ -304,6 +1252,19 @@ fn parse_handler_function_definition(ctx: &mut ExtCtxt, parser: &mut Parser) ->
         }
     };
 
+    let ret = if parser.eat(&Token::RArrow) {
+        match parser.parse_ty() {
+            Ok(ty) => Some(ty),
+
+            Err(mut err) => {
+                err.emit();
+                return None
+            }
+        }
+    } else {
+        None
+    };
+
     if let Err(mut err) = parser.expect(&Token::FatArrow) {
         err.emit();
         return None
@@ -318,12 +1279,9 @@ fn parse_handler_function_definition(ctx: &mut ExtCtxt, parser: &mut Parser) ->
         }
     };
 
-    Some(HandlerFnInfo::new(source, dest, args))
-}
-
-fn parse_handler_function_arg(_: &mut ExtCtxt, parser: &mut Parser) -> Option<HandlerFnArg> {
-    let name = match parser.parse_ident() {
-        Ok(ident) => ident,
+    // Only the signal's lifetimes can be bounded, as it has no type parameters
+    let where_clause = match parser.parse_where_clause() {
+        Ok(where_clause) => where_clause,
 
         Err(mut err) => {
             err.emit();
@@ -331,21 +1289,154 @@ fn parse_handler_function_arg(_: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
         }
     };
 
-    if let Err(mut err) = parser.expect(&Token::Colon) {
-        err.emit();
+    let gate = if parser.eat_keyword(keywords::If) {
+        match parser.parse_ident() {
+            Ok(ident) => Some(ident),
+
+            Err(mut err) => {
+                err.emit();
+                return None
+            }
+        }
+    } else {
+        None
+    };
+
+    let accumulate = if parser.eat_keyword(keywords::In) {
+        match parser.parse_ident() {
+            Ok(ident) => Some(ident),
+
+            Err(mut err) => {
+                err.emit();
+                return None
+            }
+        }
+    } else {
+        None
+    };
+
+    let body = if parser.check(&Token::OpenDelim(DelimToken::Brace)) {
+        match parser.parse_block() {
+            Ok(block) => Some(block),
+
+            Err(mut err) => {
+                err.emit();
+                return None
+            }
+        }
+    } else {
+        None
+    };
+
+    match receiver {
+        Some(Mutability::Immutable) => reads = true,
+
+        Some(Mutability::Mutable) => if reads {
+            errors.push(Diagnostic::new(span, "Read-only signals cannot take &mut self"));
+            return None
+        },
+
+        None => ()
+    }
+
+    let mut function = HandlerFnInfo::new(source, dest, args, ret);
+    function.parallel = parallel;
+    function.consuming = consuming;
+    function.fold = fold;
+    function.single = single;
+    function.reads = reads;
+    function.gate = gate;
+    function.accumulate = accumulate;
+    function.body = body;
+    function.docs = docs.into_iter().filter(|attr| attr.check_name("doc")).collect();
+    function.lifetimes = lifetimes;
+    function.where_predicates = where_clause.predicates;
+
+    let signal_errors = validate_signal(&function, span);
+    if !signal_errors.is_empty() {
+        errors.extend(signal_errors);
         return None
     }
 
-    let ptr = if parser.check(&Token::BinOp(BinOpToken::And)) {
-        parser.expect(&Token::BinOp(BinOpToken::And)).unwrap();
+    Some(function)
+}
+
+// Checks that a signal's modifiers, return type and default arguments can be used together
+pub fn validate_signal(function: &HandlerFnInfo, span: Span) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+
+    if function.accumulate.is_some() && (function.ret.is_none() || function.fold) {
+        errors.push(Diagnostic::new(span, "Only signals that return values without folding can accumulate into a field"));
+    }
+
+    if function.ret.is_some() && (function.parallel || function.consuming) {
+        errors.push(Diagnostic::new(span, "Parallel and consuming signals cannot return values"));
+    }
+
+    if function.fold && function.ret.is_none() {
+        errors.push(Diagnostic::new(span, "Folded signals must return a value"));
+    }
+
+    if function.gate.is_some() && (function.parallel || function.consuming || function.single) {
+        errors.push(Diagnostic::new(span, "Only signals that are dispatched one object at a time can be gated"));
+    }
+
+    if function.reads && (function.parallel || function.consuming) {
+        errors.push(Diagnostic::new(span, "Read-only signals cannot be parallel or consuming"));
+    }
+
+    if function.single && (function.parallel || function.consuming || function.fold || function.accumulate.is_some()) {
+        errors.push(Diagnostic::new(span, "Single signals cannot be parallel, consuming, folded or accumulated"));
+    }
+
+    if let Some(first) = function.args.iter().position(|arg| arg.default.is_some()) {
+        if function.single {
+            errors.push(Diagnostic::new(span, "Single signals cannot have default arguments"));
+        }
+
+        if function.args[first..].iter().any(|arg| arg.default.is_none()) {
+            errors.push(Diagnostic::new(span, "Only trailing arguments can have default values"));
+        }
+
+        if function.fold {
+            errors.push(Diagnostic::new(span, "Folded signals cannot have default arguments"));
+        }
+    }
+
+    errors
+}
+
+fn parse_handler_function_arg(_: &mut ExtCtxt, parser: &mut Parser, signal: Ident, index: usize) -> Option<HandlerFnArg> {
+    let is_binding = parser.token.is_ident() && parser.look_ahead(1, |token| *token == Token::Colon);
+
+    // Pattern arguments are bound to a generated name, which is what gets forwarded to each slot
+    let (name, pat) = if is_binding {
+        match parser.parse_ident() {
+            Ok(ident) => (ident, None),
 
-        parser.parse_mutability().ok()
+            Err(mut err) => {
+                err.emit();
+                return None
+            }
+        }
     } else {
-        None
+        match parser.parse_pat() {
+            Ok(pat) => (util::field_ident(&format!("{}_{}", signal, index)), Some(pat)),
+
+            Err(mut err) => {
+                err.emit();
+                return None
+            }
+        }
     };
 
-    let ty = match parser.parse_ident() {
-        Ok(ident) => ident,
+    if let Err(mut err) = parser.expect(&Token::Colon) {
+        err.emit();
+        return None
+    }
+
+    let ty = match parser.parse_ty() {
+        Ok(ty) => ty,
 
         Err(mut err) => {
             err.emit();
@@ -353,5 +1444,19 @@ fn parse_handler_function_arg(_: &mut ExtCtxt, parser: &mut Parser) -> Option<Ha
         }
     };
 
-    Some(HandlerFnArg::new(name, ty, ptr))
+    let mut arg = HandlerFnArg::new(name, ty);
+    arg.pat = pat;
+
+    if parser.eat(&Token::Eq) {
+        match parser.parse_expr() {
+            Ok(expr) => arg.default = Some(expr),
+
+            Err(mut err) => {
+                err.emit();
+                return None
+            }
+        }
+    }
+
+    Some(arg)
 }
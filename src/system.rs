@@ -19,39 +19,105 @@
 use syntax::ast::*;
 use syntax::ptr::P;
 use syntax::codemap::Span;
+use syntax::attr::AttrMetaMethods;
+use syntax::print::pprust;
 use syntax::ext::base::{MacResult, MacEager};
 use syntax::util::small_vector::SmallVector;
 use syntax::parse::token::{str_to_ident, InternedString};
 
 use ::util;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Storage {
+    Box,
+    Rc,
+    Arc
+}
+
 #[derive(Debug, Clone)]
 pub struct SystemInfo {
     pub name: Ident,
     pub span: Span,
     pub reqs: Vec<Ident>,
-    pub handlers: Vec<HandlerInfo>
+    pub req_spans: Vec<Span>,
+    pub lifetime_reqs: Vec<Lifetime>,
+    pub handler_reqs: Vec<Ident>,
+    pub handler_req_spans: Vec<Span>,
+    pub handler_lifetime_reqs: Vec<Lifetime>,
+    pub handlers: Vec<HandlerInfo>,
+    pub disabled_handlers: Vec<Ident>,
+    pub prefix_handlers: bool,
+    pub module: bool,
+    pub id_fn: Option<Ident>,
+    pub can_add_fn: Option<Ident>,
+    pub disjoint: Vec<Vec<Ident>>,
+    pub combinations: Vec<Vec<Ident>>,
+    pub clone_fn: Option<Ident>,
+    pub cleanup_fn: Option<Ident>,
+    pub sort_key: Option<(Ident, P<Ty>)>,
+    pub storage: Storage,
+    pub downcast: bool,
+    pub counted: bool,
+    pub commands: bool,
+    pub dispatch_trait: bool,
+    pub signals_trait: bool,
+    pub builder: bool,
+    pub from_object: bool,
+    pub priority: bool,
+    pub debug_checks: bool,
+    pub no_std: bool,
+    pub capacity: Option<P<Expr>>,
+    pub context: Option<P<Ty>>,
+    pub userdata: bool,
+    pub batches: Vec<BatchInfo>,
+    pub phases: Vec<BatchInfo>
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchInfo {
+    pub name: Ident,
+    pub span: Span,
+    pub signals: Vec<Ident>
 }
 
 #[derive(Debug, Clone)]
 pub struct HandlerInfo {
     pub name: Ident,
     pub reqs: Vec<Ident>,
-    pub fns: Vec<HandlerFnInfo>
+    pub req_spans: Vec<Span>,
+    pub lifetime_reqs: Vec<Lifetime>,
+    pub fns: Vec<HandlerFnInfo>,
+    pub exclusive: bool,
+    pub unsafety: Unsafety,
+    pub order_by: Option<Ident>,
+    pub attrs: Vec<Attribute>
 }
 
 #[derive(Debug, Clone)]
 pub struct HandlerFnInfo {
     pub source_name: Ident,
     pub dest_name: Ident,
-    pub args: Vec<HandlerFnArg>
+    pub args: Vec<HandlerFnArg>,
+    pub ret: Option<P<Ty>>,
+    pub parallel: bool,
+    pub consuming: bool,
+    pub fold: bool,
+    pub single: bool,
+    pub reads: bool,
+    pub gate: Option<Ident>,
+    pub accumulate: Option<Ident>,
+    pub body: Option<P<Block>>,
+    pub docs: Vec<Attribute>,
+    pub lifetimes: Vec<Lifetime>,
+    pub where_predicates: Vec<WherePredicate>
 }
 
 #[derive(Debug, Clone)]
 pub struct HandlerFnArg {
     pub name: Ident,
-    pub ty: Ident,
-    pub ptr: Option<Mutability>
+    pub pat: Option<P<Pat>>,
+    pub ty: P<Ty>,
+    pub default: Option<P<Expr>>
 }
 
 impl SystemInfo {
@@ -60,18 +126,156 @@ impl SystemInfo {
             name: name,
             span: span,
             reqs: Vec::new(),
-            handlers: Vec::new()
+            req_spans: Vec::new(),
+            lifetime_reqs: Vec::new(),
+            handler_reqs: Vec::new(),
+            handler_req_spans: Vec::new(),
+            handler_lifetime_reqs: Vec::new(),
+            handlers: Vec::new(),
+            disabled_handlers: Vec::new(),
+            prefix_handlers: false,
+            module: false,
+            id_fn: None,
+            can_add_fn: None,
+            disjoint: Vec::new(),
+            combinations: Vec::new(),
+            clone_fn: None,
+            cleanup_fn: None,
+            sort_key: None,
+            storage: Storage::Box,
+            downcast: false,
+            counted: false,
+            commands: false,
+            dispatch_trait: false,
+            signals_trait: false,
+            builder: false,
+            from_object: false,
+            priority: false,
+            debug_checks: false,
+            no_std: false,
+            capacity: None,
+            context: None,
+            userdata: false,
+            batches: Vec::new(),
+            phases: Vec::new()
         }
     }
 
-    pub fn add_requirement(&mut self, req: Ident) {
+    pub fn add_requirement(&mut self, req: Ident, span: Span) {
         self.reqs.push(req);
+        self.req_spans.push(span);
+    }
+
+    pub fn add_lifetime_requirement(&mut self, lifetime: Lifetime) {
+        self.lifetime_reqs.push(lifetime);
+    }
+
+    pub fn add_handler_requirement(&mut self, req: Ident, span: Span) {
+        self.handler_reqs.push(req);
+        self.handler_req_spans.push(span);
+    }
+
+    pub fn add_handler_lifetime_requirement(&mut self, lifetime: Lifetime) {
+        self.handler_lifetime_reqs.push(lifetime);
     }
 
     pub fn add_handler(&mut self, handler: HandlerInfo) {
         self.handlers.push(handler);
     }
 
+    pub fn add_batch(&mut self, batch: BatchInfo) {
+        self.batches.push(batch);
+    }
+
+    pub fn add_phase(&mut self, phase: BatchInfo) {
+        self.phases.push(phase);
+    }
+
+    // Every phase's signals in declaration order, which run_phases fires as one batch
+    pub fn phases_batch(&self) -> BatchInfo {
+        BatchInfo {
+            name: str_to_ident("run_phases"),
+            span: self.span,
+            signals: self.phases.iter().flat_map(|phase| phase.signals.iter().cloned()).collect()
+        }
+    }
+
+    pub fn find_signal(&self, name: Ident) -> Option<&HandlerFnInfo> {
+        self.handlers.iter()
+            .flat_map(|handler| handler.fns.iter())
+            .find(|function| function.source_name.name == name.name)
+    }
+
+    pub fn accumulators(&self) -> Vec<(Ident, P<Ty>)> {
+        let mut fields: Vec<(Ident, P<Ty>)> = Vec::new();
+
+        for function in self.handlers.iter().flat_map(|handler| handler.fns.iter()) {
+            if let (Some(field), Some(ty)) = (function.accumulate, function.ret.as_ref()) {
+                if !fields.iter().any(|&(name, _)| name.name == field.name) {
+                    fields.push((field, ty.clone()));
+                }
+            }
+        }
+
+        fields
+    }
+
+    pub fn batch_args(&self, batch: &BatchInfo) -> Result<Vec<HandlerFnArg>, String> {
+        let mut args: Vec<HandlerFnArg> = Vec::new();
+
+        for signal in batch.signals.iter() {
+            let function = match self.find_signal(*signal) {
+                Some(function) => function,
+                None => return Err(format!("Batch '{}' refers to undefined signal '{}'", batch.name, signal))
+            };
+
+            if function.fold {
+                return Err(format!("Folded signal '{}' cannot be batched", signal));
+            }
+
+            if function.single {
+                return Err(format!("Single signal '{}' cannot be batched", signal));
+            }
+
+            for arg in function.args.iter() {
+                match args.iter().position(|existing| existing.name.name == arg.name.name) {
+                    Some(i) => if pprust::ty_to_string(&args[i].ty) != pprust::ty_to_string(&arg.ty) {
+                        return Err(format!("Argument '{}' has different types in the signals of batch '{}'", arg.name, batch.name));
+                    },
+
+                    None => {
+                        let mut arg = arg.clone();
+                        arg.default = None;
+                        args.push(arg);
+                    }
+                }
+            }
+        }
+
+        Ok(args)
+    }
+
+    // The context and userdata are passed along like any other argument, so every signal simply gains them as its first ones
+    pub fn add_context_args(&mut self) {
+        let mut leading = Vec::new();
+
+        if let Some(ref ty) = self.context {
+            leading.push(HandlerFnArg::new(str_to_ident("context"), P(util::mut_ref_ty(ty.clone()))));
+        }
+
+        if self.userdata {
+            leading.push(HandlerFnArg::new(str_to_ident("data"), P(self.any_ty(Mutability::Mutable))));
+        }
+
+        for handler in self.handlers.iter_mut() {
+            for func in handler.fns.iter_mut() {
+                for (i, arg) in leading.iter().enumerate() {
+                    func.args.insert(i, arg.clone());
+                }
+            }
+        }
+    }
+
     fn object_name(&self) -> Ident {
         util::ident_append(self.name, str_to_ident("Object"))
     }
@@ -80,21 +284,373 @@ impl SystemInfo {
         util::ident_append(self.name, str_to_ident("Index"))
     }
 
+    fn object_ty(&self) -> Ty {
+        let object = util::ty_from_ident(self.object_name());
+
+        match self.storage {
+            Storage::Box => self.box_ty(object),
+
+            Storage::Rc => util::global_path_param_ty(
+                self.std_path(&["rc", "Rc"]),
+                util::global_path_param_ty(self.std_path(&["cell", "RefCell"]), object)
+            ),
+
+            Storage::Arc => util::global_path_param_ty(
+                self.std_path(&["sync", "Arc"]),
+                util::global_path_param_ty(self.std_path(&["sync", "Mutex"]), object)
+            )
+        }
+    }
+
+    fn object_access(&self, object: P<Expr>, mutability: Mutability) -> Expr {
+        match (self.storage, mutability) {
+            (Storage::Box, _) => object.unwrap(),
+
+            // object.borrow()
+            (Storage::Rc, Mutability::Immutable) => util::create_method_call(str_to_ident("borrow"), object, Vec::new()),
+
+            // object.borrow_mut()
+            (Storage::Rc, Mutability::Mutable) => util::create_method_call(str_to_ident("borrow_mut"), object, Vec::new()),
+
+            // object.lock().unwrap()
+            (Storage::Arc, _) => util::create_method_call(
+                str_to_ident("unwrap"),
+                P(util::create_method_call(str_to_ident("lock"), object, Vec::new())),
+                Vec::new()
+            )
+        }
+    }
+
+    fn std_path(&self, names: &[&str]) -> Vec<Ident> {
+        let krate = if !self.no_std {
+            "std"
+        } else {
+            match names[0] {
+                "boxed" | "rc" | "sync" => "alloc",
+                "vec" => "collections",
+                _ => "core"
+            }
+        };
+
+        let mut path = vec![str_to_ident(krate)];
+        path.extend(names.iter().map(|name| str_to_ident(name)));
+        path
+    }
+
+    fn vec_ty(&self, ty: Ty) -> Ty {
+        if self.no_std {
+            util::global_path_param_ty(self.std_path(&["vec", "Vec"]), ty)
+        } else {
+            util::param_ty_from_ident(str_to_ident("Vec"), ty)
+        }
+    }
+
+    fn box_ty(&self, ty: Ty) -> Ty {
+        if self.no_std {
+            util::global_path_param_ty(self.std_path(&["boxed", "Box"]), ty)
+        } else {
+            util::param_ty_from_ident(str_to_ident("Box"), ty)
+        }
+    }
+
+    fn box_self_ty(&self) -> Ty {
+        self.box_ty(util::ty_from_ident(str_to_ident("Self")))
+    }
+
+    fn vec_new(&self) -> Expr {
+        if self.no_std {
+            util::create_call(P(util::create_path_expr(self.std_path(&["vec", "Vec", "new"]))), Vec::new())
+        } else {
+            util::vec_new()
+        }
+    }
+
+    fn any_ty(&self, mutability: Mutability) -> Ty {
+        let any = P(util::global_path_ty(self.std_path(&["any", "Any"])));
+
+        match mutability {
+            Mutability::Mutable => util::mut_ref_ty(any),
+            Mutability::Immutable => util::ref_ty(any)
+        }
+    }
+
+    fn snapshot_name(&self) -> Ident {
+        util::ident_append(self.name, str_to_ident("Snapshot"))
+    }
+
+    fn num_handlers_name(&self) -> Ident {
+        util::const_ident(self.name, "_NUM_HANDLERS")
+    }
+
+    fn command_name(&self) -> Ident {
+        util::ident_append(self.name, str_to_ident("Command"))
+    }
+
+    fn dispatch_name(&self) -> Ident {
+        util::ident_append(self.name, str_to_ident("Dispatch"))
+    }
+
+    fn signals_name(&self) -> Ident {
+        util::ident_append(self.name, str_to_ident("Signals"))
+    }
+
+    fn builder_name(&self) -> Ident {
+        util::ident_append(self.name, str_to_ident("Builder"))
+    }
+
+    // Handler iterators hand out plain references, so shared storage does not get them
+    fn iter_names(&self) -> Vec<Ident> {
+        if self.storage == Storage::Box {
+            let mut names: Vec<Ident> = self.handlers.iter().map(|handler| handler.iter_name(self)).collect();
+            names.extend(self.generated_combinations().iter().map(|handlers| self.combined_iter_name(handlers)));
+            names
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Combinations that include a handler left out by cfg are left out along with it
+    fn generated_combinations(&self) -> Vec<Vec<&HandlerInfo>> {
+        if self.storage != Storage::Box {
+            return Vec::new()
+        }
+
+        self.combinations.iter()
+            .filter(|names| names.iter().all(|name| self.handlers.iter().any(|handler| handler.name.name == name.name)))
+            .map(|names| names.iter()
+                .filter_map(|name| self.handlers.iter().find(|handler| handler.name.name == name.name))
+                .collect())
+            .collect()
+    }
+
+    fn combined_iter_name(&self, handlers: &[&HandlerInfo]) -> Ident {
+        let names: Vec<String> = handlers.iter().map(|handler| format!("{}", handler.trait_name(self))).collect();
+        str_to_ident(&format!("{}Iter", names.join("")))
+    }
+
+    pub fn generated_names(&self) -> Vec<Ident> {
+        let mut names = vec![self.name, self.object_name(), self.idx_name(), self.snapshot_name()];
+        names.extend(self.iter_names());
+        if self.commands {
+            names.push(self.command_name());
+        }
+
+        if self.dispatch_trait {
+            names.push(self.dispatch_name());
+        }
+
+        if self.signals_trait {
+            names.push(self.signals_name());
+        }
+
+        if self.builder {
+            names.push(self.builder_name());
+        }
+
+        names
+    }
+
+    // Methods named after something in the definition, which could clash with a signal, batch or phase
+    pub fn generated_method_names(&self) -> Vec<Ident> {
+        self.accumulators().into_iter()
+            .flat_map(|(field, _)| vec![field, util::ident_append(str_to_ident("take_"), field)])
+            .collect()
+    }
+
+    fn module_name(&self) -> Ident {
+        util::mod_ident(self.name)
+    }
+
+    fn qualified_path(&self, name: Ident) -> Vec<Ident> {
+        if self.module {
+            vec![self.module_name(), name]
+        } else {
+            vec![name]
+        }
+    }
+
+    fn qualified_ty(&self, name: Ident) -> Ty {
+        util::path_ty(self.qualified_path(name))
+    }
+
+    fn leading_args(&self, name: &str, qualified: bool) -> Vec<Arg> {
+        if !self.commands {
+            return Vec::new()
+        }
+
+        let command_ty = if qualified {
+            self.qualified_ty(self.command_name())
+        } else {
+            util::ty_from_ident(self.command_name())
+        };
+
+        // commands: &mut Vec<SystemCommand>
+        vec![util::create_arg(
+            str_to_ident(name),
+            P(util::mut_ref_ty(P(self.vec_ty(command_ty))))
+        )]
+    }
+
+    fn dispatch_args(&self, func: &HandlerFnInfo) -> Vec<P<Expr>> {
+        let mut args = Vec::new();
+
+        if self.commands {
+            // &mut self.commands
+            args.push(P(util::create_addr_of_expr(
+                Mutability::Mutable,
+                P(util::create_self_field_expr(util::field_ident("commands")))
+            )));
+        }
+
+        args.extend(func.args.iter().map(|arg| P(util::create_var_expr(arg.name))));
+        args
+    }
+
+    fn wrap_commands(&self, mut stmts: Vec<Stmt>, result: Option<P<Expr>>) -> (Vec<Stmt>, Option<P<Expr>>) {
+        if !self.commands {
+            return (stmts, result)
+        }
+
+        // let result = ..;
+        let result = result.map(|result| {
+            stmts.push(util::create_let_stmt(str_to_ident("result"), Some(result)));
+            P(util::create_var_expr(str_to_ident("result")))
+        });
+
+        // self.apply_commands();
+        stmts.push(util::create_stmt(P(util::create_method_call(
+            str_to_ident("apply_commands"),
+            P(util::create_var_expr(str_to_ident("self"))),
+            Vec::new()
+        ))));
+
+        (stmts, result)
+    }
+
+    // Signals that only borrow the system cannot mark it, but still check that nothing else is running.
+    // The enabled check is kept apart so the system is only marked once the signal is known to dispatch
+    fn wrap_dispatching(&self, enabled_check: Stmt, stmts: Vec<Stmt>, result: Option<P<Expr>>, shared: bool) -> (Vec<Stmt>, Option<P<Expr>>) {
+        if !self.debug_checks {
+            let mut wrapped = vec![enabled_check];
+            wrapped.extend(stmts);
+            return (wrapped, result)
+        }
+
+        let dispatching = |value: &str| util::create_stmt(P(util::create_assign_expr(
+            P(util::create_self_field_expr(util::field_ident("dispatching"))),
+            P(util::create_var_expr(str_to_ident(value)))
+        )));
+
+        let mut wrapped = vec![
+            // self.check_not_dispatching();
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("check_not_dispatching"),
+                P(util::create_var_expr(str_to_ident("self"))),
+                Vec::new()
+            ))),
+
+            enabled_check
+        ];
+
+        if shared {
+            wrapped.extend(stmts);
+            return (wrapped, result)
+        }
+
+        // self.dispatching = true;
+        wrapped.push(dispatching("true"));
+        wrapped.extend(stmts);
+
+        // let result = ..;
+        let result = result.map(|result| {
+            wrapped.push(util::create_let_stmt(str_to_ident("result"), Some(result)));
+            P(util::create_var_expr(str_to_ident("result")))
+        });
+
+        // self.dispatching = false;
+        wrapped.push(dispatching("false"));
+
+        (wrapped, result)
+    }
+
     fn generate_object_trait(&self) -> Item {
         let mut fns = Vec::new();
 
         for handler in self.handlers.iter() {
-            fns.push(handler.generate_as_self());
-            fns.push(handler.generate_as_self_mut());
+            fns.push(handler.generate_as_self(self));
+            fns.push(handler.generate_as_self_mut(self));
+
+            if handler.is_consumable() {
+                fns.push(handler.generate_into_self(self));
+            }
+        }
+
+        if self.uses_handler_mask() {
+            fns.push(util::create_trait_method(
+                str_to_ident("handler_mask"),
+                Vec::new(),
+                Some(P(util::ty_from_ident(str_to_ident("u64"))))
+            ));
+        }
+
+        fns.push(util::create_trait_method(
+            str_to_ident("type_name"),
+            Vec::new(),
+            Some(P(util::static_ref_ty(P(util::ty_from_ident(str_to_ident("str"))))))
+        ));
+
+        if self.downcast {
+            fns.push(util::create_trait_method(
+                str_to_ident("as_any"),
+                Vec::new(),
+                Some(P(self.any_ty(Mutability::Immutable)))
+            ));
+
+            fns.push(util::create_mut_trait_method(
+                str_to_ident("as_any_mut"),
+                Vec::new(),
+                Some(P(self.any_ty(Mutability::Mutable)))
+            ));
         }
 
         util::create_trait(
             self.object_name(),
             &self.reqs,
+            &self.lifetime_reqs,
             &fns
         )
     }
 
+    fn generate_requirement_check(&self) -> Item {
+        // fn _assert_system_requirements<T: Req, ..>() {}
+        let mut ty_params = Vec::new();
+
+        let system_reqs = self.reqs.iter().zip(self.req_spans.iter());
+        let shared_reqs = self.handler_reqs.iter().zip(self.handler_req_spans.iter());
+        let handler_reqs = self.handlers.iter().flat_map(|handler| handler.reqs.iter().zip(handler.req_spans.iter()));
+
+        for (i, (req, span)) in system_reqs.chain(shared_reqs).chain(handler_reqs).enumerate() {
+            ty_params.push(util::create_ty_param(
+                str_to_ident(&format!("T{}", i)),
+                vec![util::trait_bound(*req, *span)]
+            ));
+        }
+
+        let mut item = util::create_fn(
+            util::ident_append(
+                util::ident_append(str_to_ident("_assert_"), self.module_name()),
+                str_to_ident("_requirements")
+            ),
+            util::create_generics(ty_params),
+            Vec::new(),
+            P(util::create_block(Vec::new(), None))
+        );
+
+        item.attrs = vec![util::create_list_attr(InternedString::new("allow"), vec![InternedString::new("dead_code")])];
+
+        item
+    }
+
     fn generate_idx_struct(&self) -> Item {
         let mut item = util::create_tuple_struct(
             self.idx_name(),
@@ -111,688 +667,5487 @@ impl SystemInfo {
         item
     }
 
-    fn generate_struct(&self) -> Item {
+    fn generate_snapshot_struct(&self) -> Item {
         let mut fields = vec![
             util::create_struct_field(
-                str_to_ident("objects"), 
-                P(util::param_ty_from_ident(
-                    str_to_ident("Vec"),
-                    util::param_ty_from_ident(
-                        str_to_ident("Box"),
-                        util::ty_from_ident(self.object_name())
-                    )
-                ))
-            ),
-
-            util::create_struct_field(
-                str_to_ident("idxs"), 
-                P(util::param_ty_from_ident(
-                    str_to_ident("Vec"),
-                    util::param_ty_from_ident(
-                        str_to_ident("Option"),
-                        util::ty_from_ident(str_to_ident("usize")),
-                    )
-                ))
-            ),
+                str_to_ident("handles"),
+                P(util::ty_from_ident(str_to_ident("usize")))
+            )
         ];
 
         for handler in self.handlers.iter() {
             fields.push(util::create_struct_field(
                 util::idxs_ident(handler.name),
-                P(util::param_ty_from_ident(
-                    str_to_ident("Vec"),
-                    util::ty_from_ident(str_to_ident("usize"))
-                ))
+                P(self.vec_ty(util::ty_from_ident(str_to_ident("usize"))))
             ));
         }
 
-        util::create_struct(self.name, fields)
+        let mut item = util::create_struct(self.snapshot_name(), fields);
+        item.attrs = vec![util::create_derive(vec![InternedString::new("Clone")])];
+
+        item
     }
 
-    fn generate_fn_new_impl(&self) -> ImplItem {
-        let mut fields = vec![
-            util::create_field(
+    fn generate_builder_struct(&self) -> Item {
+        // pub struct SystemBuilder { objects: Vec<Box<SystemObject>> }
+        util::create_struct(self.builder_name(), vec![
+            util::create_struct_field(
                 str_to_ident("objects"),
-                P(util::vec_new())
-            ),
-            util::create_field(
-                str_to_ident("idxs"),
-                P(util::vec_new())
-            ),
-        ];
-
-        for handler in self.handlers.iter() {
-            fields.push(util::create_field(
-                util::idxs_ident(handler.name),
-                P(util::vec_new())
-            ));
-        }
+                P(self.vec_ty(self.object_ty()))
+            )
+        ])
+    }
 
-        util::impl_static_method(
+    fn generate_builder_impl(&self) -> Item {
+        let new = util::impl_static_method(
             str_to_ident("new"),
             Vec::new(),
-            Some(P(util::ty_from_ident(self.name))),
+            Some(P(util::ty_from_ident(self.builder_name()))),
             P(util::create_block(
                 Vec::new(),
-                Some(P(util::create_struct_expr(self.name, fields)))
+                Some(P(util::create_struct_expr(self.builder_name(), vec![
+                    util::create_field(str_to_ident("objects"), P(self.vec_new()))
+                ])))
             ))
-        )
-    }
+        );
 
-    fn generate_fn_add_impl(&self) -> ImplItem {
-        let mut stmts = vec![
-            // let idx = self.idxs.len();
-            util::create_let_stmt(
-                str_to_ident("idx"),
-                Some(P(util::create_method_call(
-                    str_to_ident("len"),
-                    P(util::create_self_field_expr(str_to_ident("idxs"))),
-                    Vec::new()
-                )))
-            ),
+        // self.objects.push(object); self
+        let add = util::impl_value_method(
+            str_to_ident("add"),
+            Mutability::Mutable,
+            vec![util::create_arg(str_to_ident("object"), P(self.object_ty()))],
+            Some(P(util::ty_from_ident(self.builder_name()))),
+            P(util::create_block(
+                vec![util::create_stmt(P(util::create_method_call(
+                    str_to_ident("push"),
+                    P(util::create_self_field_expr(str_to_ident("objects"))),
+                    vec![P(util::create_var_expr(str_to_ident("object")))]
+                )))],
+                Some(P(util::create_var_expr(str_to_ident("self"))))
+            ))
+        );
 
-            // self.idxs.push(Some(self.objects.len()));
-            util::create_stmt(P(util::create_method_call(
-                str_to_ident("push"),
-                P(util::create_self_field_expr(str_to_ident("idxs"))),
-                vec![P(util::create_call(
-                    P(util::create_var_expr(str_to_ident("Some"))),
-                    vec![P(util::create_method_call(
-                        str_to_ident("len"),
-                        P(util::create_self_field_expr(str_to_ident("objects"))),
-                        Vec::new()
-                    ))]
-                ))]
-            ))),
+        // system.add(object)
+        let add_call = util::create_method_call(
+            str_to_ident("add"),
+            P(util::create_var_expr(str_to_ident("system"))),
+            vec![P(util::create_var_expr(str_to_ident("object")))]
+        );
 
-            // self.objects.push(object);
-            util::create_stmt(P(util::create_method_call(
-                str_to_ident("push"),
-                P(util::create_self_field_expr(str_to_ident("objects"))),
-                vec![P(util::create_var_expr(str_to_ident("object")))]
-            ))),
+        let (call, ret, result) = if self.fallible_add() {
+            (
+                // if let Err(object) = system.add(object) { return Err(object) }
+                util::create_stmt(P(util::create_if_let_expr(
+                    P(util::create_tuple_struct_pat(
+                        str_to_ident("Err"),
+                        vec![str_to_ident("object")]
+                    )),
+                    P(add_call),
+                    P(util::create_return_block(Some(P(util::create_call(
+                        P(util::create_var_expr(str_to_ident("Err"))),
+                        vec![P(util::create_var_expr(str_to_ident("object")))]
+                    ))))),
+                    None
+                ))),
 
-            // let object = self.objects.last().unwrap();
-            util::create_let_stmt(
-                str_to_ident("object"),
-                Some(P(util::create_method_call(
-                    str_to_ident("unwrap"),
-                    P(util::create_method_call(
-                        str_to_ident("last"),
+                // Result<System, Box<SystemObject>>
+                util::params_ty_from_ident(
+                    str_to_ident("Result"),
+                    vec![util::ty_from_ident(self.name), self.object_ty()]
+                ),
+
+                // Ok(system)
+                util::create_call(
+                    P(util::create_var_expr(str_to_ident("Ok"))),
+                    vec![P(util::create_var_expr(str_to_ident("system")))]
+                )
+            )
+        } else {
+            (
+                util::create_stmt(P(add_call)),
+                util::ty_from_ident(self.name),
+                util::create_var_expr(str_to_ident("system"))
+            )
+        };
+
+        let build = util::impl_value_method(
+            str_to_ident("build"),
+            Mutability::Immutable,
+            Vec::new(),
+            Some(P(ret)),
+            P(util::create_block(
+                vec![
+                    // let mut system = System { .. };
+                    util::create_let_mut_stmt(
+                        str_to_ident("system"),
+                        Some(P(self.new_expr()))
+                    ),
+
+                    // for object in self.objects { .. }
+                    util::create_stmt(P(util::create_for_expr(
+                        str_to_ident("object"),
                         P(util::create_self_field_expr(str_to_ident("objects"))),
-                        Vec::new()
-                    )),
-                    Vec::new()
+                        P(util::create_block(vec![call], None))
+                    )))
+                ],
+                Some(P(result))
+            ))
+        );
+
+        util::create_impl(self.builder_name(), None, vec![new, add, build])
+    }
+
+    fn generate_num_handlers_const(&self) -> Item {
+        // pub const SYSTEM_NUM_HANDLERS: usize = N;
+        util::create_const(
+            self.num_handlers_name(),
+            P(util::ty_from_ident(str_to_ident("usize"))),
+            P(util::create_num_expr(self.handlers.len() as u64))
+        )
+    }
+
+    fn generate_command_enum(&self) -> Item {
+        util::create_enum(self.command_name(), vec![
+            (str_to_ident("Add"), vec![P(self.object_ty())]),
+            (str_to_ident("Remove"), vec![P(util::ty_from_ident(self.idx_name()))])
+        ])
+    }
+
+    fn generate_struct(&self) -> Item {
+        let mut fields = vec![
+            util::create_struct_field(
+                util::field_ident("objects"), 
+                P(self.vec_ty(self.object_ty()))
+            ),
+
+            util::create_struct_field(
+                util::field_ident("handles"),
+                P(self.vec_ty(util::ty_from_ident(self.idx_name())))
+            ),
+
+            util::create_struct_field(
+                util::field_ident("idxs"), 
+                P(self.vec_ty(util::param_ty_from_ident(
+                    str_to_ident("Option"),
+                    util::ty_from_ident(str_to_ident("usize")),
                 )))
-            )
+            ),
         ];
 
         for handler in self.handlers.iter() {
-            stmts.push(util::create_stmt(P(handler.generate_add_check())));
+            fields.push(util::create_struct_field(
+                util::idxs_ident(handler.name),
+                P(self.vec_ty(util::ty_from_ident(str_to_ident("usize"))))
+            ));
+
+            fields.push(util::create_struct_field(
+                util::enabled_ident(handler.name),
+                P(util::ty_from_ident(str_to_ident("bool")))
+            ));
         }
 
-        util::impl_mut_method(
-            str_to_ident("add"),
-            vec![util::create_arg(
-                str_to_ident("object"), 
-                P(util::param_ty_from_ident(
-                    str_to_ident("Box"),
-                    util::ty_from_ident(self.object_name())
+        if self.uses_handler_mask() {
+            fields.push(util::create_struct_field(
+                util::field_ident("masks"),
+                P(self.vec_ty(util::ty_from_ident(str_to_ident("u64"))))
+            ));
+        }
+
+        if self.priority {
+            fields.push(util::create_struct_field(
+                util::field_ident("priorities"),
+                P(self.vec_ty(util::ty_from_ident(str_to_ident("i32"))))
+            ));
+        }
+
+        for (field, ty) in self.accumulators() {
+            fields.push(util::create_struct_field(field, ty));
+        }
+
+        if self.commands {
+            fields.push(util::create_struct_field(
+                util::field_ident("commands"),
+                P(self.vec_ty(util::ty_from_ident(self.command_name())))
+            ));
+        }
+
+        if self.keeps_rejected() {
+            fields.push(util::create_struct_field(
+                util::field_ident("rejected"),
+                P(self.vec_ty(self.object_ty()))
+            ));
+        }
+
+        if self.debug_checks {
+            fields.push(util::create_struct_field(
+                util::field_ident("dispatching"),
+                P(util::ty_from_ident(str_to_ident("bool")))
+            ));
+        }
+
+        util::create_struct(self.name, fields)
+    }
+
+    fn storage_vec_new(&self) -> Expr {
+        match self.capacity {
+            // ::std::vec::Vec::with_capacity(capacity)
+            Some(ref capacity) => util::create_call(
+                P(util::create_path_expr(self.std_path(&["vec", "Vec", "with_capacity"]))),
+                vec![capacity.clone()]
+            ),
+
+            None => self.vec_new()
+        }
+    }
+
+    fn default_expr(&self) -> Expr {
+        // ::std::default::Default::default()
+        util::create_call(P(util::create_path_expr(self.std_path(&["default", "Default", "default"]))), Vec::new())
+    }
+
+    fn generate_accumulator_impls(&self, field: Ident, ty: P<Ty>) -> Vec<ImplItem> {
+        vec![
+            // fn field(&self) -> &T { &self.field }
+            util::impl_method(
+                field,
+                Vec::new(),
+                Some(P(util::ref_ty(ty.clone()))),
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_addr_of_expr(
+                        Mutability::Immutable,
+                        P(util::create_self_field_expr(field))
+                    )))
                 ))
-            )],
-            Some(P(util::ty_from_ident(self.idx_name()))),
-            P(util::create_block(
-                stmts, 
-                Some(P(util::create_call(
-                    P(util::create_var_expr(self.idx_name())),
-                    vec![P(util::create_var_expr(str_to_ident("idx")))]
-                )))
-            ))
-        )
+            ),
+
+            // fn take_field(&mut self) -> T { ::std::mem::replace(&mut self.field, Default::default()) }
+            util::impl_mut_method(
+                util::ident_append(str_to_ident("take_"), field),
+                Vec::new(),
+                Some(ty),
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_call(
+                        P(util::create_path_expr(self.std_path(&["mem", "replace"]))),
+                        vec![
+                            P(util::create_addr_of_expr(
+                                Mutability::Mutable,
+                                P(util::create_self_field_expr(field))
+                            )),
+                            P(self.default_expr())
+                        ]
+                    )))
+                ))
+            )
+        ]
     }
 
-    fn generate_fn_iter_impl(&self) -> ImplItem {
-        util::impl_method(
-            str_to_ident("iter"),
+    fn new_expr(&self) -> Expr {
+        let mut fields = vec![
+            util::create_field(
+                util::field_ident("objects"),
+                P(self.storage_vec_new())
+            ),
+            util::create_field(
+                util::field_ident("handles"),
+                P(self.storage_vec_new())
+            ),
+            util::create_field(
+                util::field_ident("idxs"),
+                P(self.storage_vec_new())
+            ),
+        ];
+
+        for handler in self.handlers.iter() {
+            fields.push(util::create_field(
+                util::idxs_ident(handler.name),
+                P(self.storage_vec_new())
+            ));
+
+            fields.push(util::create_field(
+                util::enabled_ident(handler.name),
+                P(util::create_var_expr(str_to_ident("true")))
+            ));
+        }
+
+        if self.uses_handler_mask() {
+            fields.push(util::create_field(
+                util::field_ident("masks"),
+                P(self.storage_vec_new())
+            ));
+        }
+
+        if self.priority {
+            fields.push(util::create_field(
+                util::field_ident("priorities"),
+                P(self.storage_vec_new())
+            ));
+        }
+
+        for (field, _) in self.accumulators() {
+            fields.push(util::create_field(field, P(self.default_expr())));
+        }
+
+        if self.commands {
+            fields.push(util::create_field(
+                util::field_ident("commands"),
+                P(self.vec_new())
+            ));
+        }
+
+        if self.keeps_rejected() {
+            fields.push(util::create_field(
+                util::field_ident("rejected"),
+                P(self.vec_new())
+            ));
+        }
+
+        if self.debug_checks {
+            fields.push(util::create_field(
+                util::field_ident("dispatching"),
+                P(util::create_var_expr(str_to_ident("false")))
+            ));
+        }
+
+        util::create_struct_expr(self.name, fields)
+    }
+
+    fn generate_fn_new_impl(&self) -> ImplItem {
+        util::impl_static_method(
+            str_to_ident("new"),
             Vec::new(),
-            Some(P(util::path_param_ty(
-                vec![str_to_ident("std"), str_to_ident("slice"), str_to_ident("Iter")],
-                util::param_ty_from_ident(
-                    str_to_ident("Box"),
-                    util::ty_from_ident(self.object_name())
-                )
-            ))),
+            Some(P(util::ty_from_ident(self.name))),
             P(util::create_block(
                 Vec::new(),
-                Some(P(util::create_method_call(
-                    str_to_ident("iter"),
-                    P(util::create_self_field_expr(str_to_ident("objects"))),
-                    Vec::new()
-                )))
+                Some(P(self.new_expr()))
             ))
         )
     }
 
-    fn generate_fn_iter_mut_impl(&self) -> ImplItem {
-        util::impl_mut_method(
-            str_to_ident("iter_mut"),
+    fn generate_index_impls(&self) -> Vec<Item> {
+        let index = |get: &str| P(util::create_block(
+            Vec::new(),
+
+            // self.get(idx).expect(..)
+            Some(P(util::create_method_call(
+                str_to_ident("expect"),
+                P(util::create_method_call(
+                    str_to_ident(get),
+                    P(util::create_var_expr(str_to_ident("self"))),
+                    vec![P(util::create_var_expr(str_to_ident("idx")))]
+                )),
+                vec![P(util::create_str_expr(InternedString::new("Indexed a handle that has been removed from the system")))]
+            )))
+        ));
+
+        let idx_arg = || vec![util::create_arg(str_to_ident("idx"), P(util::ty_from_ident(self.idx_name())))];
+
+        vec![
+            util::create_param_path_impl(
+                self.name,
+                self.std_path(&["ops", "Index"]),
+                vec![util::ty_from_ident(self.idx_name())],
+                vec![
+                    util::impl_type(str_to_ident("Output"), P(self.object_ty())),
+                    util::impl_method_priv(
+                        str_to_ident("index"),
+                        idx_arg(),
+                        Some(P(util::ref_ty(P(self.object_ty())))),
+                        index("get")
+                    )
+                ]
+            ),
+
+            util::create_param_path_impl(
+                self.name,
+                self.std_path(&["ops", "IndexMut"]),
+                vec![util::ty_from_ident(self.idx_name())],
+                vec![util::impl_mut_method_priv(
+                    str_to_ident("index_mut"),
+                    idx_arg(),
+                    Some(P(util::mut_ref_ty(P(self.object_ty())))),
+                    index("get_mut")
+                )]
+            )
+        ]
+    }
+
+    fn generate_from_object_impl(&self) -> Item {
+        // impl From<Box<SystemObject>> for System { .. }
+        util::create_param_path_impl(
+            self.name,
+            self.std_path(&["convert", "From"]),
+            vec![self.object_ty()],
+            vec![util::impl_static_method_priv(
+                str_to_ident("from"),
+                vec![util::create_arg(str_to_ident("object"), P(self.object_ty()))],
+                Some(P(util::ty_from_ident(self.name))),
+                P(util::create_block(
+                    vec![
+                        // let mut system = System { .. };
+                        util::create_let_mut_stmt(
+                            str_to_ident("system"),
+                            Some(P(self.new_expr()))
+                        ),
+
+                        // system.add(object);
+                        util::create_stmt(P(util::create_method_call(
+                            str_to_ident("add"),
+                            P(util::create_var_expr(str_to_ident("system"))),
+                            vec![P(util::create_var_expr(str_to_ident("object")))]
+                        )))
+                    ],
+                    Some(P(util::create_var_expr(str_to_ident("system"))))
+                ))
+            )]
+        )
+    }
+
+    fn generate_combined_iter_struct(&self, handlers: &[&HandlerInfo]) -> Item {
+        let lifetime = util::create_lifetime("'a");
+
+        // pub struct ABIter<'a> { objects: ::std::slice::Iter<'a, Box<SystemObject>> }
+        util::create_generic_struct(
+            self.combined_iter_name(handlers),
+            util::create_lifetime_generics(&vec![lifetime]),
+            vec![util::create_struct_field(
+                str_to_ident("objects"),
+                P(util::global_path_lifetime_params_ty(
+                    self.std_path(&["slice", "Iter"]),
+                    vec![lifetime],
+                    vec![self.object_ty()]
+                ))
+            )]
+        )
+    }
+
+    fn generate_combined_iter_impl(&self, handlers: &[&HandlerInfo]) -> Item {
+        let lifetime = util::create_lifetime("'a");
+
+        // (&'a A, &'a B, ..)
+        let item_ty = util::tuple_ty(handlers.iter().map(|handler| P(util::lifetime_ref_ty(
+            lifetime,
+            P(util::ty_from_ident(handler.trait_name(self)))
+        ))).collect());
+
+        // return Some((a, b, ..));
+        let found = util::create_return_block(Some(P(util::create_call(
+            P(util::create_var_expr(str_to_ident("Some"))),
+            vec![P(util::create_tuple_expr(
+                handlers.iter().map(|handler| P(util::create_var_expr(util::mod_ident(handler.name)))).collect()
+            ))]
+        ))));
+
+        // if let Some(a) = object.as_a() { if let Some(b) = object.as_b() { .. } }
+        let found = handlers.iter().rev().fold(found, |inner, handler| util::create_block(
+            vec![util::create_stmt(P(util::create_if_let_expr(
+                P(util::create_tuple_struct_pat(
+                    str_to_ident("Some"),
+                    vec![util::mod_ident(handler.name)]
+                )),
+                P(util::create_method_call(
+                    util::as_ident(handler.name),
+                    P(util::create_var_expr(str_to_ident("object"))),
+                    Vec::new()
+                )),
+                P(inner),
+                None
+            )))],
+            None
+        ));
+
+        // Objects missing any of the handlers are skipped
+        // for object in self.objects.by_ref() { .. }
+        let stmts = vec![util::create_stmt(P(util::create_for_expr(
+            str_to_ident("object"),
+            P(util::create_method_call(
+                str_to_ident("by_ref"),
+                P(util::create_self_field_expr(str_to_ident("objects"))),
+                Vec::new()
+            )),
+            P(found)
+        )))];
+
+        // impl<'a> Iterator for ABIter<'a> { .. }
+        util::create_generic_param_path_impl(
+            self.combined_iter_name(handlers),
+            util::create_lifetime_generics(&vec![lifetime]),
+            util::lifetime_ty_from_ident(self.combined_iter_name(handlers), lifetime),
+            self.std_path(&["iter", "Iterator"]),
             Vec::new(),
-            Some(P(util::path_param_ty(
-                vec![str_to_ident("std"), str_to_ident("slice"), str_to_ident("IterMut")],
-                util::param_ty_from_ident(
-                    str_to_ident("Box"),
-                    util::ty_from_ident(self.object_name())
+            vec![
+                util::impl_type(str_to_ident("Item"), P(item_ty.clone())),
+                util::impl_mut_method_priv(
+                    str_to_ident("next"),
+                    Vec::new(),
+                    Some(P(util::param_ty_from_ident(str_to_ident("Option"), item_ty))),
+                    P(util::create_block(stmts, Some(P(util::create_var_expr(str_to_ident("None"))))))
                 )
-            ))),
+            ]
+        )
+    }
+
+    fn generate_fn_combined_iter_impl(&self, handlers: &[&HandlerInfo]) -> ImplItem {
+        let names: Vec<Ident> = handlers.iter().map(|handler| handler.name).collect();
+
+        // ABIter { objects: self.objects.iter() }
+        util::impl_method(
+            util::combined_iter_ident(&names),
+            Vec::new(),
+            Some(P(util::ty_from_ident(self.combined_iter_name(handlers)))),
             P(util::create_block(
                 Vec::new(),
-                Some(P(util::create_method_call(
-                    str_to_ident("iter_mut"),
-                    P(util::create_self_field_expr(str_to_ident("objects"))),
-                    Vec::new()
-                )))
+                Some(P(util::create_struct_expr(self.combined_iter_name(handlers), vec![
+                    util::create_field(str_to_ident("objects"), P(util::create_method_call(
+                        str_to_ident("iter"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
+                    )))
+                ])))
             ))
         )
     }
 
-    fn generate_fn_remove_impl(&self) -> ImplItem {
-        util::impl_mut_method(
-            str_to_ident("remove"),
+    fn generate_fn_with_handler_capacities_impl(&self) -> ImplItem {
+        // let mut system = System { .. };
+        let mut stmts = vec![util::create_let_mut_stmt(
+            str_to_ident("system"),
+            Some(P(self.new_expr()))
+        )];
+
+        // system.handler_idxs.reserve(capacities[i]);
+        stmts.extend(self.handlers.iter().enumerate().map(|(i, handler)| util::create_stmt(P(util::create_method_call(
+            str_to_ident("reserve"),
+            P(util::create_field_expr(util::idxs_ident(handler.name), str_to_ident("system"))),
+            vec![P(util::create_idx_expr(
+                P(util::create_num_expr(i as u64)),
+                P(util::create_var_expr(str_to_ident("capacities")))
+            ))]
+        )))));
+
+        util::impl_static_method(
+            str_to_ident("with_handler_capacities"),
             vec![util::create_arg(
-                str_to_ident("idx"),
-                P(util::ty_from_ident(self.idx_name()))
+                str_to_ident("capacities"),
+                P(util::array_ty(P(util::ty_from_ident(str_to_ident("usize"))), self.handlers.len()))
             )],
-            Some(P(util::param_ty_from_ident(
-                str_to_ident("Option"),
-                util::param_ty_from_ident(
-                    str_to_ident("Box"),
-                    util::ty_from_ident(self.object_name())
-                )
-            ))),
+            Some(P(util::ty_from_ident(self.name))),
             P(util::create_block(
-                Vec::new(),
-                Some(P(util::create_method_call(
-                    str_to_ident("and_then"),
+                stmts,
+                Some(P(util::create_var_expr(str_to_ident("system"))))
+            ))
+        )
+    }
+
+    fn generate_clone_impl(&self, clone_fn: Ident) -> Item {
+        let clone = |field: Ident| P(util::create_method_call(
+            str_to_ident("clone"),
+            P(util::create_self_field_expr(field)),
+            Vec::new()
+        ));
+
+        let mut fields = vec![
+            // self.objects.iter().map(|object| object.clone_fn()).collect()
+            util::create_field(
+                util::field_ident("objects"),
+                P(util::create_method_call(
+                    str_to_ident("collect"),
                     P(util::create_method_call(
-                        str_to_ident("cloned"),
+                        str_to_ident("map"),
                         P(util::create_method_call(
-                            str_to_ident("get"),
-                            P(util::create_self_field_expr(str_to_ident("idxs"))),
-                            vec![P(util::create_tuple_field_expr(
-                                P(util::create_var_expr(str_to_ident("idx"))),
-                                0
-                            ))],
+                            str_to_ident("iter"),
+                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                            Vec::new()
                         )),
-                        Vec::new()
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("object"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_method_call(
+                                    clone_fn,
+                                    P(util::create_var_expr(str_to_ident("object"))),
+                                    Vec::new()
+                                )))
+                            ))
+                        ))]
+                    )),
+                    Vec::new()
+                ))
+            ),
+            util::create_field(util::field_ident("handles"), clone(util::field_ident("handles"))),
+            util::create_field(util::field_ident("idxs"), clone(util::field_ident("idxs"))),
+        ];
+
+        for handler in self.handlers.iter() {
+            fields.push(util::create_field(util::idxs_ident(handler.name), clone(util::idxs_ident(handler.name))));
+            fields.push(util::create_field(
+                util::enabled_ident(handler.name),
+                P(util::create_self_field_expr(util::enabled_ident(handler.name)))
+            ));
+        }
+
+        if self.uses_handler_mask() {
+            fields.push(util::create_field(util::field_ident("masks"), clone(util::field_ident("masks"))));
+        }
+
+        if self.priority {
+            fields.push(util::create_field(util::field_ident("priorities"), clone(util::field_ident("priorities"))));
+        }
+
+        for (field, _) in self.accumulators() {
+            fields.push(util::create_field(field, clone(field)));
+        }
+
+        // Commands are only queued while a signal is running
+        if self.commands {
+            fields.push(util::create_field(
+                util::field_ident("commands"),
+                P(self.vec_new())
+            ));
+        }
+
+        if self.keeps_rejected() {
+            fields.push(util::create_field(
+                util::field_ident("rejected"),
+                P(self.vec_new())
+            ));
+        }
+
+        if self.debug_checks {
+            fields.push(util::create_field(
+                util::field_ident("dispatching"),
+                P(util::create_var_expr(str_to_ident("false")))
+            ));
+        }
+
+        util::create_path_impl(
+            self.name,
+            Some(vec![str_to_ident("Clone")]),
+            vec![util::impl_method_priv(
+                str_to_ident("clone"),
+                Vec::new(),
+                Some(P(util::ty_from_ident(self.name))),
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_struct_expr(self.name, fields)))
+                ))
+            )]
+        )
+    }
+
+    fn for_each_object_generics(&self) -> Generics {
+        // F: FnMut(&mut SystemObject)
+        util::create_generics(vec![util::create_ty_param(
+            str_to_ident("F"),
+            vec![util::fn_bound(
+                str_to_ident("FnMut"),
+                vec![P(util::mut_ref_ty(P(util::ty_from_ident(self.object_name()))))],
+                None
+            )]
+        )])
+    }
+
+    fn generate_dispatch_trait(&self) -> Item {
+        let mut for_each_object = util::create_mut_trait_method(
+            str_to_ident("for_each_object"),
+            vec![util::create_arg(str_to_ident("f"), P(util::ty_from_ident(str_to_ident("F"))))],
+            None
+        );
+
+        if let TraitItemKind::Method(ref mut sig, _) = for_each_object.node {
+            sig.generics = self.for_each_object_generics();
+        }
+
+        let mut fns = vec![for_each_object];
+        for handler in self.handlers.iter() {
+            fns.extend(handler.fns.iter()
+                .filter(|func| func.is_dispatchable())
+                .map(|func| handler.generate_dispatch_signal(self, func)));
+        }
+
+        util::create_trait(self.dispatch_name(), &Vec::new(), &Vec::new(), &fns)
+    }
+
+    // Folded signals are generic over their function and single signals are only sent to one object, so neither fits
+    fn signals_trait_items(&self) -> Vec<(ImplItem, &HandlerFnInfo)> {
+        let mut items = Vec::new();
+        for handler in self.handlers.iter() {
+            items.extend(handler.fns.iter()
+                .filter(|func| !(func.fold || func.single))
+                .map(|func| (handler.generate_signal(self, func), func)));
+        }
+
+        items
+    }
+
+    fn generate_signals_trait(&self) -> Item {
+        let fns: Vec<TraitItem> = self.signals_trait_items().iter().map(|&(ref signal, _)| util::trait_method_from_impl(signal)).collect();
+        util::create_trait(self.signals_name(), &Vec::new(), &Vec::new(), &fns)
+    }
+
+    fn generate_signals_impl(&self) -> Item {
+        // fn signal(&mut self, ..) { self.signal(..) }
+        util::create_path_impl(
+            self.name,
+            Some(vec![self.signals_name()]),
+            self.signals_trait_items().iter().map(|&(ref signal, func)| util::forward_impl_method(
+                signal,
+                func.args.iter().map(|arg| P(util::create_var_expr(arg.name))).collect()
+            )).collect()
+        )
+    }
+
+    fn generate_dispatch_impl(&self) -> Item {
+        let object = P(util::create_var_expr(str_to_ident("object")));
+        let object = match self.storage {
+            // **object
+            Storage::Box => util::create_deref_expr(P(util::create_deref_expr(object))),
+
+            // *object.borrow_mut()
+            _ => util::create_deref_expr(P(self.object_access(object, Mutability::Mutable)))
+        };
+
+        // for object in self.objects.iter_mut() { f(&mut ..); }
+        let body = util::create_stmt(P(util::create_for_expr(
+            str_to_ident("object"),
+            P(util::create_method_call(
+                str_to_ident("iter_mut"),
+                P(util::create_self_field_expr(util::field_ident("objects"))),
+                Vec::new()
+            )),
+            P(util::create_block(
+                vec![util::create_stmt(P(util::create_call(
+                    P(util::create_var_expr(str_to_ident("f"))),
+                    vec![P(util::create_addr_of_expr(Mutability::Mutable, P(object)))]
+                )))],
+                None
+            ))
+        )));
+
+        util::create_path_impl(
+            self.name,
+            Some(vec![self.dispatch_name()]),
+            vec![util::impl_mut_generic_method_priv(
+                str_to_ident("for_each_object"),
+                self.for_each_object_generics(),
+                vec![util::create_mut_arg(str_to_ident("f"), P(util::ty_from_ident(str_to_ident("F"))))],
+                None,
+                P(util::create_block(vec![body], None))
+            )]
+        )
+    }
+
+    fn generate_fn_add_impl(&self) -> ImplItem {
+        let mut stmts = Vec::new();
+
+        if let Some(can_add_fn) = self.can_add_fn {
+            // if !object.can_add() { return Err(object) }
+            stmts.push(util::create_stmt(P(util::create_if_expr(
+                P(util::create_unary_expr(
+                    UnOp::Not,
+                    P(util::create_method_call(
+                        can_add_fn,
+                        P(self.object_access(P(util::create_var_expr(str_to_ident("object"))), Mutability::Immutable)),
+                        Vec::new()
+                    ))
+                )),
+                P(util::create_return_block(Some(P(util::create_call(
+                    P(util::create_var_expr(str_to_ident("Err"))),
+                    vec![P(util::create_var_expr(str_to_ident("object")))]
+                ))))),
+                None
+            ))));
+        }
+
+        for handlers in self.disjoint.iter() {
+            // (object.as_a().is_some() as usize) + (object.as_b().is_some() as usize) + ..
+            let implemented = handlers.iter()
+                .filter(|name| self.handlers.iter().any(|handler| handler.name.name == name.name))
+                .map(|name| P(util::create_cast_expr(
+                    P(util::create_method_call(
+                        str_to_ident("is_some"),
+                        P(util::create_method_call(
+                            util::as_ident(*name),
+                            P(self.object_access(P(util::create_var_expr(str_to_ident("object"))), Mutability::Immutable)),
+                            Vec::new()
+                        )),
+                        Vec::new()
+                    )),
+                    P(util::ty_from_ident(str_to_ident("usize")))
+                )))
+                .fold(None, |sum, count| Some(match sum {
+                    Some(sum) => P(util::create_binop_expr(sum, BinOpKind::Add, count)),
+                    None => count
+                }));
+
+            // if .. > 1 { return Err(object) }
+            if let Some(implemented) = implemented {
+                stmts.push(util::create_stmt(P(util::create_if_expr(
+                    P(util::create_binop_expr(implemented, BinOpKind::Gt, P(util::create_num_expr(1)))),
+                    P(util::create_return_block(Some(P(util::create_call(
+                        P(util::create_var_expr(str_to_ident("Err"))),
+                        vec![P(util::create_var_expr(str_to_ident("object")))]
+                    ))))),
+                    None
+                ))));
+            }
+        }
+
+        if let Some(ref capacity) = self.capacity {
+            // if self.objects.len() >= capacity { return Err(object) }
+            stmts.push(util::create_stmt(P(util::create_if_expr(
+                P(util::create_binop_expr(
+                    P(util::create_method_call(
+                        str_to_ident("len"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
+                    )),
+                    BinOpKind::Ge,
+                    capacity.clone()
+                )),
+                P(util::create_return_block(Some(P(util::create_call(
+                    P(util::create_var_expr(str_to_ident("Err"))),
+                    vec![P(util::create_var_expr(str_to_ident("object")))]
+                ))))),
+                None
+            ))));
+        }
+
+        if let Some((key_fn, _)) = self.sort_key {
+            stmts.extend(self.generate_sorted_add_stmts(key_fn));
+        } else {
+            stmts.extend(self.generate_push_stmts());
+        }
+
+        stmts.extend(self.generate_handler_mask_stmt());
+
+        if self.uses_handler_mask() {
+            // self.masks.push(mask);
+            stmts.push(util::create_stmt(P(util::create_method_call(
+                str_to_ident("push"),
+                P(util::create_self_field_expr(util::field_ident("masks"))),
+                vec![P(util::create_var_expr(str_to_ident("mask")))]
+            ))));
+        }
+
+        if self.priority {
+            // self.priorities.push(priority);
+            stmts.push(util::create_stmt(P(util::create_method_call(
+                str_to_ident("push"),
+                P(util::create_self_field_expr(util::field_ident("priorities"))),
+                vec![P(util::create_var_expr(str_to_ident("priority")))]
+            ))));
+        }
+
+        for (i, handler) in self.handlers.iter().enumerate() {
+            stmts.push(util::create_stmt(P(handler.generate_add_check(self, i, P(util::create_var_expr(str_to_ident("idx")))))));
+        }
+
+        stmts.extend(self.check_bookkeeping_stmt());
+
+        // SystemIndex(idx)
+        let idx = util::create_call(
+            P(util::create_var_expr(self.idx_name())),
+            vec![P(util::create_var_expr(str_to_ident("idx")))]
+        );
+
+        let result = if self.fallible_add() {
+            // Ok(SystemIndex(idx))
+            util::create_call(P(util::create_var_expr(str_to_ident("Ok"))), vec![P(idx)])
+        } else {
+            idx
+        };
+
+        let mut args = vec![util::create_arg(
+            str_to_ident("object"), 
+            P(self.object_ty())
+        )];
+
+        if self.priority {
+            args.push(util::create_arg(str_to_ident("priority"), P(util::ty_from_ident(str_to_ident("i32")))));
+        }
+
+        util::impl_mut_method(
+            str_to_ident(if self.priority { "add_with_priority" } else { "add" }),
+            args,
+            Some(P(self.add_ret_ty())),
+            P(util::create_block(stmts, Some(P(result))))
+        )
+    }
+
+    fn generate_push_stmts(&self) -> Vec<Stmt> {
+        vec![
+            // let idx = self.idxs.len();
+            util::create_let_stmt(
+                str_to_ident("idx"),
+                Some(P(util::create_method_call(
+                    str_to_ident("len"),
+                    P(util::create_self_field_expr(util::field_ident("idxs"))),
+                    Vec::new()
+                )))
+            ),
+
+            // self.idxs.push(Some(self.objects.len()));
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("push"),
+                P(util::create_self_field_expr(util::field_ident("idxs"))),
+                vec![P(util::create_call(
+                    P(util::create_var_expr(str_to_ident("Some"))),
+                    vec![P(util::create_method_call(
+                        str_to_ident("len"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
+                    ))]
+                ))]
+            ))),
+
+            // self.objects.push(object);
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("push"),
+                P(util::create_self_field_expr(util::field_ident("objects"))),
+                vec![P(util::create_var_expr(str_to_ident("object")))]
+            ))),
+
+            // self.handles.push(SystemIndex(idx));
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("push"),
+                P(util::create_self_field_expr(util::field_ident("handles"))),
+                vec![P(util::create_call(
+                    P(util::create_var_expr(self.idx_name())),
+                    vec![P(util::create_var_expr(str_to_ident("idx")))]
+                ))]
+            ))),
+
+            // let object = self.objects.last().unwrap();
+            util::create_let_stmt(
+                str_to_ident("object"),
+                Some(P(util::create_method_call(
+                    str_to_ident("unwrap"),
+                    P(util::create_method_call(
+                        str_to_ident("last"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
+                    )),
+                    Vec::new()
+                )))
+            )
+        ]
+    }
+
+    fn generate_sorted_add_stmts(&self, key_fn: Ident) -> Vec<Stmt> {
+        // other.key() > key
+        let after = P(util::create_binop_expr(
+            self.key_of(key_fn, P(util::create_var_expr(str_to_ident("other")))),
+            BinOpKind::Gt,
+            P(util::create_var_expr(str_to_ident("key")))
+        ));
+
+        // ::std::cmp::Ordering::Greater
+        let ordering = |name: &str| P(util::create_path_expr(self.std_path(&["cmp", "Ordering", name])));
+
+        vec![
+            // let idx = self.idxs.len();
+            util::create_let_stmt(
+                str_to_ident("idx"),
+                Some(P(util::create_method_call(
+                    str_to_ident("len"),
+                    P(util::create_self_field_expr(util::field_ident("idxs"))),
+                    Vec::new()
+                )))
+            ),
+
+            // let key = object.key();
+            util::create_let_stmt(
+                str_to_ident("key"),
+                Some(self.key_of(key_fn, P(util::create_var_expr(str_to_ident("object")))))
+            ),
+
+            // Objects with equal keys stay in the order they were added
+            // let pos = self.objects.binary_search_by(|other| if other.key() > key { Greater } else { Less })
+            //     .unwrap_or_else(|pos| pos);
+            util::create_let_stmt(
+                str_to_ident("pos"),
+                Some(P(util::create_method_call(
+                    str_to_ident("unwrap_or_else"),
+                    P(util::create_method_call(
+                        str_to_ident("binary_search_by"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("other"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_if_expr(
+                                    after,
+                                    P(util::create_block(Vec::new(), Some(ordering("Greater")))),
+                                    Some(P(util::create_block_expr(P(util::create_block(Vec::new(), Some(ordering("Less")))))))
+                                )))
+                            ))
+                        ))]
+                    )),
+                    vec![P(util::create_closure_expr(
+                        vec![util::create_closure_arg(str_to_ident("pos"))],
+                        P(util::create_block(
+                            Vec::new(),
+                            Some(P(util::create_var_expr(str_to_ident("pos"))))
+                        ))
+                    ))]
+                )))
+            ),
+
+            // self.idxs.push(Some(pos));
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("push"),
+                P(util::create_self_field_expr(util::field_ident("idxs"))),
+                vec![P(util::create_call(
+                    P(util::create_var_expr(str_to_ident("Some"))),
+                    vec![P(util::create_var_expr(str_to_ident("pos")))]
+                ))]
+            ))),
+
+            // self.objects.insert(pos, object);
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("insert"),
+                P(util::create_self_field_expr(util::field_ident("objects"))),
+                vec![
+                    P(util::create_var_expr(str_to_ident("pos"))),
+                    P(util::create_var_expr(str_to_ident("object")))
+                ]
+            ))),
+
+            // self.handles.insert(pos, SystemIndex(idx));
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("insert"),
+                P(util::create_self_field_expr(util::field_ident("handles"))),
+                vec![
+                    P(util::create_var_expr(str_to_ident("pos"))),
+                    P(util::create_call(
+                        P(util::create_var_expr(self.idx_name())),
+                        vec![P(util::create_var_expr(str_to_ident("idx")))]
+                    ))
+                ]
+            ))),
+
+            self.generate_renumber_stmt(P(util::create_var_expr(str_to_ident("pos")))),
+
+            // let object = &self.objects[pos];
+            util::create_let_stmt(
+                str_to_ident("object"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_idx_expr(
+                        P(util::create_var_expr(str_to_ident("pos"))),
+                        P(util::create_self_field_expr(util::field_ident("objects")))
+                    ))
+                )))
+            )
+        ]
+    }
+
+    fn key_of(&self, key_fn: Ident, object: P<Expr>) -> P<Expr> {
+        // object.key()
+        P(util::create_method_call(
+            key_fn,
+            P(self.object_access(object, Mutability::Immutable)),
+            Vec::new()
+        ))
+    }
+
+    fn generate_renumber_stmt(&self, from: P<Expr>) -> Stmt {
+        // Everything after an insertion or removal has shifted along by one
+        // for pos in from..self.handles.len() { self.idxs[self.handles[pos].0] = Some(pos); }
+        util::create_stmt(P(util::create_for_expr(
+            str_to_ident("pos"),
+            P(util::create_range_expr(
+                from,
+                P(util::create_method_call(
+                    str_to_ident("len"),
+                    P(util::create_self_field_expr(util::field_ident("handles"))),
+                    Vec::new()
+                ))
+            )),
+            P(util::create_block(
+                vec![util::create_stmt(P(util::create_assign_expr(
+                    P(util::create_idx_expr(
+                        P(util::create_tuple_field_expr(
+                            P(util::create_idx_expr(
+                                P(util::create_var_expr(str_to_ident("pos"))),
+                                P(util::create_self_field_expr(util::field_ident("handles")))
+                            )),
+                            0
+                        )),
+                        P(util::create_self_field_expr(util::field_ident("idxs")))
+                    )),
+                    P(util::create_call(
+                        P(util::create_var_expr(str_to_ident("Some"))),
+                        vec![P(util::create_var_expr(str_to_ident("pos")))]
+                    ))
+                )))],
+                None
+            ))
+        )))
+    }
+
+    fn generate_fn_find_impl(&self, key_fn: Ident, key_ty: P<Ty>) -> ImplItem {
+        util::impl_method(
+            str_to_ident("find"),
+            vec![util::create_arg(str_to_ident("key"), key_ty)],
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::ref_ty(P(self.object_ty()))
+            ))),
+            P(util::create_block(
+                Vec::new(),
+
+                // self.objects.binary_search_by(|other| other.key().cmp(&key)).ok().map(|pos| &self.objects[pos])
+                Some(P(util::create_method_call(
+                    str_to_ident("map"),
+                    P(util::create_method_call(
+                        str_to_ident("ok"),
+                        P(util::create_method_call(
+                            str_to_ident("binary_search_by"),
+                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                            vec![P(util::create_closure_expr(
+                                vec![util::create_closure_arg(str_to_ident("other"))],
+                                P(util::create_block(
+                                    Vec::new(),
+                                    Some(P(util::create_method_call(
+                                        str_to_ident("cmp"),
+                                        self.key_of(key_fn, P(util::create_var_expr(str_to_ident("other")))),
+                                        vec![P(util::create_addr_of_expr(
+                                            Mutability::Immutable,
+                                            P(util::create_var_expr(str_to_ident("key")))
+                                        ))]
+                                    )))
+                                ))
+                            ))]
+                        )),
+                        Vec::new()
+                    )),
+                    vec![P(util::create_closure_expr(
+                        vec![util::create_closure_arg(str_to_ident("pos"))],
+                        P(util::create_block(
+                            Vec::new(),
+                            Some(P(util::create_addr_of_expr(
+                                Mutability::Immutable,
+                                P(util::create_idx_expr(
+                                    P(util::create_var_expr(str_to_ident("pos"))),
+                                    P(util::create_self_field_expr(util::field_ident("objects")))
+                                ))
+                            )))
+                        ))
+                    ))]
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_add_default_priority_impl(&self) -> ImplItem {
+        // self.add_with_priority(object, 0)
+        util::impl_mut_method(
+            str_to_ident("add"),
+            vec![util::create_arg(str_to_ident("object"), P(self.object_ty()))],
+            Some(P(self.add_ret_ty())),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_method_call(
+                    str_to_ident("add_with_priority"),
+                    P(util::create_var_expr(str_to_ident("self"))),
+                    vec![
+                        P(util::create_var_expr(str_to_ident("object"))),
+                        P(util::create_num_expr(0))
+                    ]
+                )))
+            ))
+        )
+    }
+
+    fn uses_handler_mask(&self) -> bool {
+        // Systems with more handlers than fit in the mask fall back to calling each accessor
+        self.handlers.len() <= 64
+    }
+
+    fn generate_handler_mask_stmt(&self) -> Option<Stmt> {
+        if !self.uses_handler_mask() {
+            return None
+        }
+
+        // let mask = object.handler_mask();
+        Some(util::create_let_stmt(
+            str_to_ident("mask"),
+            Some(P(util::create_method_call(
+                str_to_ident("handler_mask"),
+                P(self.object_access(P(util::create_var_expr(str_to_ident("object"))), Mutability::Immutable)),
+                Vec::new()
+            )))
+        ))
+    }
+
+    fn generate_mask_store(&self, mask: P<Expr>) -> Stmt {
+        // Masks are stored by handle rather than storage position, so they never move
+        util::create_stmt(P(util::create_assign_expr(
+            P(util::create_idx_expr(
+                P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0)),
+                P(util::create_self_field_expr(util::field_ident("masks")))
+            )),
+            mask
+        )))
+    }
+
+    fn generate_mask_consts(&self) -> Vec<Item> {
+        // pub const SYSTEM_HANDLER_MASK: u64 = 1 << i;
+        self.handlers.iter().enumerate().map(|(i, handler)| util::create_const(
+            util::const_ident(util::ident_append(self.name, handler.name), "_MASK"),
+            P(util::ty_from_ident(str_to_ident("u64"))),
+            P(util::create_num_expr(1 << i))
+        )).collect()
+    }
+
+    fn generate_fn_handler_mask_of_impl(&self) -> ImplItem {
+        // if self.idxs.get(idx.0).cloned().unwrap_or(None).is_some() { self.masks.get(idx.0).cloned() } else { None }
+        util::impl_method(
+            str_to_ident("handler_mask_of"),
+            vec![util::create_arg(str_to_ident("idx"), P(util::ty_from_ident(self.idx_name())))],
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::ty_from_ident(str_to_ident("u64"))
+            ))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_if_expr(
+                    P(util::create_method_call(
+                        str_to_ident("is_some"),
+                        P(util::create_method_call(
+                            str_to_ident("unwrap_or"),
+                            P(util::create_method_call(
+                                str_to_ident("cloned"),
+                                P(util::create_method_call(
+                                    str_to_ident("get"),
+                                    P(util::create_self_field_expr(util::field_ident("idxs"))),
+                                    vec![P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0))]
+                                )),
+                                Vec::new()
+                            )),
+                            vec![P(util::create_var_expr(str_to_ident("None")))]
+                        )),
+                        Vec::new()
+                    )),
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_method_call(
+                            str_to_ident("cloned"),
+                            P(util::create_method_call(
+                                str_to_ident("get"),
+                                P(util::create_self_field_expr(util::field_ident("masks"))),
+                                vec![P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0))]
+                            )),
+                            Vec::new()
+                        )))
+                    )),
+                    Some(P(util::create_block_expr(P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_var_expr(str_to_ident("None"))))
+                    )))))
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_handles_matching_impl(&self) -> ImplItem {
+        // *entry.1 & mask == mask
+        let matches = util::create_binop_expr(
+            P(util::create_binop_expr(
+                P(util::create_deref_expr(P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("entry"))), 1)))),
+                BinOpKind::BitAnd,
+                P(util::create_var_expr(str_to_ident("mask")))
+            )),
+            BinOpKind::Eq,
+            P(util::create_var_expr(str_to_ident("mask")))
+        );
+
+        // Removed handles have an empty mask, so only a zero mask can match them
+        let live = util::create_binop_expr(
+            P(util::create_method_call(
+                str_to_ident("is_some"),
+                P(util::create_idx_expr(
+                    P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("entry"))), 0)),
+                    P(util::create_var_expr(str_to_ident("idxs")))
+                )),
+                Vec::new()
+            )),
+            BinOpKind::And,
+            P(matches)
+        );
+
+        // self.masks.iter().enumerate().filter(|entry| ..).map(|entry| SystemIndex(entry.0)).collect()
+        util::impl_method(
+            str_to_ident("handles_matching"),
+            vec![util::create_arg(str_to_ident("mask"), P(util::ty_from_ident(str_to_ident("u64"))))],
+            Some(P(self.vec_ty(util::ty_from_ident(self.idx_name())))),
+            P(util::create_block(
+                vec![util::create_let_stmt(
+                    str_to_ident("idxs"),
+                    Some(P(util::create_addr_of_expr(
+                        Mutability::Immutable,
+                        P(util::create_self_field_expr(util::field_ident("idxs")))
+                    )))
+                )],
+                Some(P(util::create_method_call(
+                    str_to_ident("collect"),
+                    P(util::create_method_call(
+                        str_to_ident("map"),
+                        P(util::create_method_call(
+                            str_to_ident("filter"),
+                            P(util::create_method_call(
+                                str_to_ident("enumerate"),
+                                P(util::create_method_call(
+                                    str_to_ident("iter"),
+                                    P(util::create_self_field_expr(util::field_ident("masks"))),
+                                    Vec::new()
+                                )),
+                                Vec::new()
+                            )),
+                            vec![P(util::create_closure_expr(
+                                vec![util::create_closure_arg(str_to_ident("entry"))],
+                                P(util::create_block(Vec::new(), Some(P(live))))
+                            ))]
+                        )),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("entry"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_call(
+                                    P(util::create_var_expr(self.idx_name())),
+                                    vec![P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("entry"))), 0))]
+                                )))
+                            ))
+                        ))]
+                    )),
+                    Vec::new()
+                )))
+            ))
+        )
+    }
+
+    pub fn fallible_add(&self) -> bool {
+        self.capacity.is_some() || self.can_add_fn.is_some() || !self.disjoint.is_empty()
+    }
+
+    // Queued adds have nobody to hand a refused object back to, so it is kept until asked for
+    fn keeps_rejected(&self) -> bool {
+        self.commands && self.fallible_add()
+    }
+
+    fn add_ret_ty(&self) -> Ty {
+        if self.fallible_add() {
+            // Result<SystemIndex, Box<SystemObject>>
+            util::params_ty_from_ident(
+                str_to_ident("Result"),
+                vec![util::ty_from_ident(self.idx_name()), self.object_ty()]
+            )
+        } else {
+            util::ty_from_ident(self.idx_name())
+        }
+    }
+
+    fn wrap_object(&self, object: P<Expr>) -> Expr {
+        let new = |path: &[&str], inner: P<Expr>| util::create_call(
+            P(util::create_path_expr(self.std_path(path))),
+            vec![inner]
+        );
+
+        match self.storage {
+            // ::std::boxed::Box::new(object)
+            Storage::Box => new(&["boxed", "Box", "new"], object),
+
+            // ::std::rc::Rc::new(::std::cell::RefCell::new(object))
+            Storage::Rc => new(&["rc", "Rc", "new"], P(new(&["cell", "RefCell", "new"], object))),
+
+            // ::std::sync::Arc::new(::std::sync::Mutex::new(object))
+            Storage::Arc => new(&["sync", "Arc", "new"], P(new(&["sync", "Mutex", "new"], object)))
+        }
+    }
+
+    fn generate_fn_add_all_impl(&self) -> ImplItem {
+        // I: IntoIterator<Item = O>, O: SystemObject + 'static
+        let generics = util::create_generics(vec![
+            util::create_ty_param(
+                str_to_ident("I"),
+                vec![util::binding_bound(
+                    str_to_ident("IntoIterator"),
+                    str_to_ident("Item"),
+                    P(util::ty_from_ident(str_to_ident("O")))
+                )]
+            ),
+
+            util::create_ty_param(
+                str_to_ident("O"),
+                vec![
+                    util::trait_bound(self.object_name(), self.span),
+                    util::lifetime_bound("'static")
+                ]
+            )
+        ]);
+
+        util::impl_mut_generic_method(
+            str_to_ident("add_all"),
+            generics,
+            vec![util::create_arg(str_to_ident("objects"), P(util::ty_from_ident(str_to_ident("I"))))],
+            Some(P(self.vec_ty(self.add_ret_ty()))),
+            P(util::create_block(
+                vec![
+                    // let mut handles = Vec::new();
+                    util::create_let_mut_stmt(
+                        str_to_ident("handles"),
+                        Some(P(self.vec_new()))
+                    ),
+
+                    // for object in objects { handles.push(self.add(Box::new(object))); }
+                    util::create_stmt(P(util::create_for_expr(
+                        str_to_ident("object"),
+                        P(util::create_var_expr(str_to_ident("objects"))),
+                        P(util::create_block(
+                            vec![util::create_stmt(P(util::create_method_call(
+                                str_to_ident("push"),
+                                P(util::create_var_expr(str_to_ident("handles"))),
+                                vec![P(util::create_method_call(
+                                    str_to_ident("add"),
+                                    P(util::create_var_expr(str_to_ident("self"))),
+                                    vec![P(self.wrap_object(P(util::create_var_expr(str_to_ident("object")))))]
+                                ))]
+                            )))],
+                            None
+                        ))
+                    )))
+                ],
+                Some(P(util::create_var_expr(str_to_ident("handles"))))
+            ))
+        )
+    }
+
+    fn generate_fn_merge_impl(&self) -> ImplItem {
+        let into_iter = |field: &str| P(util::create_method_call(
+            str_to_ident("into_iter"),
+            P(util::create_field_expr(util::field_ident(field), str_to_ident("other"))),
+            Vec::new()
+        ));
+
+        let object = P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("entry"))), 1));
+        let add = if self.priority {
+            // self.add_with_priority(entry.1, other.priorities[(entry.0).0])
+            util::create_method_call(
+                str_to_ident("add_with_priority"),
+                P(util::create_var_expr(str_to_ident("self"))),
+                vec![object, P(util::create_idx_expr(
+                    P(util::create_tuple_field_expr(
+                        P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("entry"))), 0)),
+                        0
+                    )),
+                    P(util::create_field_expr(util::field_ident("priorities"), str_to_ident("other")))
+                ))]
+            )
+        } else {
+            // self.add(entry.1)
+            util::create_method_call(
+                str_to_ident("add"),
+                P(util::create_var_expr(str_to_ident("self"))),
+                vec![object]
+            )
+        };
+
+        util::impl_mut_method(
+            str_to_ident("merge"),
+            vec![util::create_arg(str_to_ident("other"), P(util::ty_from_ident(self.name)))],
+            Some(P(self.vec_ty(self.add_ret_ty()))),
+            P(util::create_block(
+                vec![
+                    // let mut entries = Vec::new();
+                    util::create_let_mut_stmt(
+                        str_to_ident("entries"),
+                        Some(P(self.vec_new()))
+                    ),
+
+                    // entries.extend(other.handles.into_iter().zip(other.objects.into_iter()));
+                    util::create_stmt(P(util::create_method_call(
+                        str_to_ident("extend"),
+                        P(util::create_var_expr(str_to_ident("entries"))),
+                        vec![P(util::create_method_call(
+                            str_to_ident("zip"),
+                            into_iter("handles"),
+                            vec![into_iter("objects")]
+                        ))]
+                    ))),
+
+                    // Adding in handle order makes exclusive handlers end up as they would have in the other system
+                    // entries.sort_by_key(|entry| (entry.0).0);
+                    util::create_stmt(P(util::create_method_call(
+                        str_to_ident("sort_by_key"),
+                        P(util::create_var_expr(str_to_ident("entries"))),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("entry"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_tuple_field_expr(
+                                    P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("entry"))), 0)),
+                                    0
+                                )))
+                            ))
+                        ))]
+                    ))),
+
+                    // let mut handles = Vec::new();
+                    util::create_let_mut_stmt(
+                        str_to_ident("handles"),
+                        Some(P(self.vec_new()))
+                    ),
+
+                    // for entry in entries { handles.push(self.add(entry.1)); }
+                    util::create_stmt(P(util::create_for_expr(
+                        str_to_ident("entry"),
+                        P(util::create_var_expr(str_to_ident("entries"))),
+                        P(util::create_block(
+                            vec![util::create_stmt(P(util::create_method_call(
+                                str_to_ident("push"),
+                                P(util::create_var_expr(str_to_ident("handles"))),
+                                vec![P(add)]
+                            )))],
+                            None
+                        ))
+                    )))
+                ],
+                Some(P(util::create_var_expr(str_to_ident("handles"))))
+            ))
+        )
+    }
+
+    fn generate_fn_iter_impl(&self) -> ImplItem {
+        util::impl_method(
+            str_to_ident("iter"),
+            Vec::new(),
+            Some(P(util::global_path_param_ty(
+                self.std_path(&["slice", "Iter"]),
+                self.object_ty()
+            ))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_method_call(
+                    str_to_ident("iter"),
+                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                    Vec::new()
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_as_slice_impl(&self) -> ImplItem {
+        // There is deliberately no mutable version, as reordering the objects would break every handler's indices
+        util::impl_method(
+            str_to_ident("as_slice"),
+            Vec::new(),
+            Some(P(util::ref_ty(P(util::slice_ty(P(self.object_ty())))))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("objects")))
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_iter_mut_impl(&self) -> ImplItem {
+        util::impl_mut_method(
+            str_to_ident("iter_mut"),
+            Vec::new(),
+            Some(P(util::global_path_param_ty(
+                self.std_path(&["slice", "IterMut"]),
+                self.object_ty()
+            ))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_method_call(
+                    str_to_ident("iter_mut"),
+                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                    Vec::new()
+                )))
+            ))
+        )
+    }
+
+    fn generate_end_impl(&self, name: &str, mutability: Mutability) -> ImplItem {
+        // self.objects.first()
+        let block = P(util::create_block(
+            Vec::new(),
+            Some(P(util::create_method_call(
+                str_to_ident(name),
+                P(util::create_self_field_expr(util::field_ident("objects"))),
+                Vec::new()
+            )))
+        ));
+
+        match mutability {
+            Mutability::Immutable => util::impl_method(
+                str_to_ident(name),
+                Vec::new(),
+                Some(P(util::param_ty_from_ident(str_to_ident("Option"), util::ref_ty(P(self.object_ty()))))),
+                block
+            ),
+
+            Mutability::Mutable => util::impl_mut_method(
+                str_to_ident(name),
+                Vec::new(),
+                Some(P(util::param_ty_from_ident(str_to_ident("Option"), util::mut_ref_ty(P(self.object_ty()))))),
+                block
+            )
+        }
+    }
+
+    fn generate_iter_enumerated_impl(&self, name: &str, iter: &str, iter_ty: &str) -> ImplItem {
+        // ::std::iter::Zip<::std::iter::Cloned<::std::slice::Iter<SystemIndex>>, ::std::slice::Iter<Box<SystemObject>>>
+        let ret = util::global_path_params_ty(
+            self.std_path(&["iter", "Zip"]),
+            vec![
+                util::global_path_param_ty(
+                    self.std_path(&["iter", "Cloned"]),
+                    util::global_path_param_ty(
+                        self.std_path(&["slice", "Iter"]),
+                        util::ty_from_ident(self.idx_name())
+                    )
+                ),
+                util::global_path_param_ty(
+                    self.std_path(&["slice", iter_ty]),
+                    self.object_ty()
+                )
+            ]
+        );
+
+        // self.handles.iter().cloned().zip(self.objects.iter())
+        let body = P(util::create_block(
+            Vec::new(),
+            Some(P(util::create_method_call(
+                str_to_ident("zip"),
+                P(util::create_method_call(
+                    str_to_ident("cloned"),
+                    P(util::create_method_call(
+                        str_to_ident("iter"),
+                        P(util::create_self_field_expr(util::field_ident("handles"))),
+                        Vec::new()
+                    )),
+                    Vec::new()
+                )),
+                vec![P(util::create_method_call(
+                    str_to_ident(iter),
+                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                    Vec::new()
+                ))]
+            )))
+        ));
+
+        if iter == "iter_mut" {
+            util::impl_mut_method(str_to_ident(name), Vec::new(), Some(P(ret)), body)
+        } else {
+            util::impl_method(str_to_ident(name), Vec::new(), Some(P(ret)), body)
+        }
+    }
+
+    fn generate_fn_iter_enumerated_impl(&self) -> ImplItem {
+        self.generate_iter_enumerated_impl("iter_enumerated", "iter", "Iter")
+    }
+
+    fn generate_fn_iter_enumerated_mut_impl(&self) -> ImplItem {
+        self.generate_iter_enumerated_impl("iter_enumerated_mut", "iter_mut", "IterMut")
+    }
+
+    fn generate_iter_ordered_impl(&self, name: &str, iter: &str, mutability: Mutability) -> ImplItem {
+        let object_ty = P(self.object_ty());
+
+        // ::std::vec::IntoIter<&Box<SystemObject>>
+        let ret = util::global_path_param_ty(
+            self.std_path(&["vec", "IntoIter"]),
+            match mutability {
+                Mutability::Mutable => util::mut_ref_ty(object_ty),
+                Mutability::Immutable => util::ref_ty(object_ty)
+            }
+        );
+
+        let stmts = vec![
+            // let mut entries = Vec::new();
+            util::create_let_mut_stmt(
+                str_to_ident("entries"),
+                Some(P(self.vec_new()))
+            ),
+
+            // for entry in self.handles.iter().zip(self.objects.iter()) { entries.push(entry); }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("entry"),
+                P(util::create_method_call(
+                    str_to_ident("zip"),
+                    P(util::create_method_call(
+                        str_to_ident("iter"),
+                        P(util::create_self_field_expr(util::field_ident("handles"))),
+                        Vec::new()
+                    )),
+                    vec![P(util::create_method_call(
+                        str_to_ident(iter),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
+                    ))]
+                )),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_method_call(
+                        str_to_ident("push"),
+                        P(util::create_var_expr(str_to_ident("entries"))),
+                        vec![P(util::create_var_expr(str_to_ident("entry")))]
+                    )))],
+                    None
+                ))
+            ))),
+
+            // entries.sort_by_key(|entry| (entry.0).0);
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("sort_by_key"),
+                P(util::create_var_expr(str_to_ident("entries"))),
+                vec![P(util::create_closure_expr(
+                    vec![util::create_closure_arg(str_to_ident("entry"))],
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_tuple_field_expr(
+                            P(util::create_tuple_field_expr(
+                                P(util::create_var_expr(str_to_ident("entry"))),
+                                0
+                            )),
+                            0
+                        )))
+                    ))
+                ))]
+            ))),
+
+            // let mut ordered = Vec::with_capacity(entries.len());
+            util::create_let_mut_stmt(
+                str_to_ident("ordered"),
+                Some(P(util::create_call(
+                    P(util::create_path_expr(self.std_path(&["vec", "Vec", "with_capacity"]))),
+                    vec![P(util::create_method_call(
+                        str_to_ident("len"),
+                        P(util::create_var_expr(str_to_ident("entries"))),
+                        Vec::new()
+                    ))]
+                )))
+            ),
+
+            // for entry in entries { ordered.push(entry.1); }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("entry"),
+                P(util::create_var_expr(str_to_ident("entries"))),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_method_call(
+                        str_to_ident("push"),
+                        P(util::create_var_expr(str_to_ident("ordered"))),
+                        vec![P(util::create_tuple_field_expr(
+                            P(util::create_var_expr(str_to_ident("entry"))),
+                            1
+                        ))]
+                    )))],
+                    None
+                ))
+            )))
+        ];
+
+        // ordered.into_iter()
+        let body = P(util::create_block(
+            stmts,
+            Some(P(util::create_method_call(
+                str_to_ident("into_iter"),
+                P(util::create_var_expr(str_to_ident("ordered"))),
+                Vec::new()
+            )))
+        ));
+
+        match mutability {
+            Mutability::Mutable => util::impl_mut_method(str_to_ident(name), Vec::new(), Some(P(ret)), body),
+            Mutability::Immutable => util::impl_method(str_to_ident(name), Vec::new(), Some(P(ret)), body)
+        }
+    }
+
+    fn generate_fn_iter_ordered_impl(&self) -> ImplItem {
+        self.generate_iter_ordered_impl("iter_ordered", "iter", Mutability::Immutable)
+    }
+
+    fn generate_fn_iter_ordered_mut_impl(&self) -> ImplItem {
+        self.generate_iter_ordered_impl("iter_ordered_mut", "iter_mut", Mutability::Mutable)
+    }
+
+    fn generate_fn_sort_by_impl(&self) -> ImplItem {
+        let drain = |field: &str| P(util::create_method_call(
+            str_to_ident("drain"),
+            P(util::create_self_field_expr(util::field_ident(field))),
+            vec![P(util::create_full_range_expr())]
+        ));
+
+        let entry = |field: usize| P(util::create_tuple_field_expr(
+            P(util::create_var_expr(str_to_ident("entry"))),
+            field
+        ));
+
+        let mut stmts = vec![
+            // let mut entries = Vec::new();
+            util::create_let_mut_stmt(
+                str_to_ident("entries"),
+                Some(P(self.vec_new()))
+            ),
+
+            // entries.extend(self.objects.drain(..).zip(self.handles.drain(..)));
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("extend"),
+                P(util::create_var_expr(str_to_ident("entries"))),
+                vec![P(util::create_method_call(
+                    str_to_ident("zip"),
+                    drain("objects"),
+                    vec![drain("handles")]
+                ))]
+            ))),
+
+            // entries.sort_by(|a, b| f(&a.0, &b.0));
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("sort_by"),
+                P(util::create_var_expr(str_to_ident("entries"))),
+                vec![P(util::create_closure_expr(
+                    vec![
+                        util::create_closure_arg(str_to_ident("a")),
+                        util::create_closure_arg(str_to_ident("b"))
+                    ],
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_call(
+                            P(util::create_var_expr(str_to_ident("f"))),
+                            ["a", "b"].iter().map(|name| P(util::create_addr_of_expr(
+                                Mutability::Immutable,
+                                P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident(name))), 0))
+                            ))).collect()
+                        )))
+                    ))
+                ))]
+            ))),
+
+            // for entry in entries { .. }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("entry"),
+                P(util::create_var_expr(str_to_ident("entries"))),
+                P(util::create_block(
+                    vec![
+                        // self.idxs[(entry.1).0] = Some(self.objects.len());
+                        util::create_stmt(P(util::create_assign_expr(
+                            P(util::create_idx_expr(
+                                P(util::create_tuple_field_expr(entry(1), 0)),
+                                P(util::create_self_field_expr(util::field_ident("idxs")))
+                            )),
+                            P(util::create_call(
+                                P(util::create_var_expr(str_to_ident("Some"))),
+                                vec![P(util::create_method_call(
+                                    str_to_ident("len"),
+                                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                                    Vec::new()
+                                ))]
+                            ))
+                        ))),
+
+                        // self.handles.push(entry.1);
+                        util::create_stmt(P(util::create_method_call(
+                            str_to_ident("push"),
+                            P(util::create_self_field_expr(util::field_ident("handles"))),
+                            vec![entry(1)]
+                        ))),
+
+                        // self.objects.push(entry.0);
+                        util::create_stmt(P(util::create_method_call(
+                            str_to_ident("push"),
+                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                            vec![entry(0)]
+                        )))
+                    ],
+                    None
+                ))
+            ))),
+
+            // let idxs = &self.idxs;
+            util::create_let_stmt(
+                str_to_ident("idxs"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("idxs")))
+                )))
+            )
+        ];
+
+        if self.priority {
+            // let priorities = &self.priorities;
+            stmts.push(util::create_let_stmt(
+                str_to_ident("priorities"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("priorities")))
+                )))
+            ));
+        }
+
+        // idxs[*idx]
+        let position = P(util::create_idx_expr(
+            P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("idx"))))),
+            P(util::create_var_expr(str_to_ident("idxs")))
+        ));
+
+        // Objects with a priority only move among those sharing it
+        // (priorities[*idx], idxs[*idx])
+        let key = if self.priority {
+            P(util::create_tuple_expr(vec![
+                P(util::create_idx_expr(
+                    P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("idx"))))),
+                    P(util::create_var_expr(str_to_ident("priorities")))
+                )),
+                position
+            ]))
+        } else {
+            position
+        };
+
+        // Handlers store handles, so they only need resorting to dispatch in the new order
+        for handler in self.handlers.iter().filter(|handler| handler.order_by.is_none()) {
+            // self.handler_idxs.sort_by_key(|idx| idxs[*idx]);
+            stmts.push(util::create_stmt(P(util::create_method_call(
+                str_to_ident("sort_by_key"),
+                P(util::create_self_field_expr(util::idxs_ident(handler.name))),
+                vec![P(util::create_closure_expr(
+                    vec![util::create_closure_arg(str_to_ident("idx"))],
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(key.clone())
+                    ))
+                ))]
+            ))));
+        }
+
+        let object_ty = P(util::ref_ty(P(self.object_ty())));
+
+        // F: FnMut(&Object, &Object) -> Ordering
+        let generics = util::create_generics(vec![util::create_ty_param(
+            str_to_ident("F"),
+            vec![util::fn_bound(
+                str_to_ident("FnMut"),
+                vec![object_ty.clone(), object_ty],
+                Some(P(util::global_path_ty(self.std_path(&["cmp", "Ordering"]))))
+            )]
+        )]);
+
+        util::impl_mut_generic_method(
+            str_to_ident("sort_by"),
+            generics,
+            vec![util::create_mut_arg(
+                str_to_ident("f"),
+                P(util::ty_from_ident(str_to_ident("F")))
+            )],
+            None,
+            P(util::create_block(stmts, None))
+        )
+    }
+
+    fn generate_fn_rebuild_indices_impl(&self) -> ImplItem {
+        // self.handler_idxs.clear();
+        let mut stmts: Vec<Stmt> = self.handlers.iter().map(|handler| util::create_stmt(P(util::create_method_call(
+            str_to_ident("clear"),
+            P(util::create_self_field_expr(util::idxs_ident(handler.name))),
+            Vec::new()
+        )))).collect();
+
+        // self.objects[pos]
+        let at = |field: &str| P(util::create_idx_expr(
+            P(util::create_var_expr(str_to_ident("pos"))),
+            P(util::create_self_field_expr(util::field_ident(field)))
+        ));
+
+        let mut rescan = vec![
+            // let idx = self.handles[pos];
+            util::create_let_stmt(str_to_ident("idx"), Some(at("handles"))),
+
+            // let object = &self.objects[pos];
+            util::create_let_stmt(
+                str_to_ident("object"),
+                Some(P(util::create_addr_of_expr(Mutability::Immutable, at("objects"))))
+            )
+        ];
+
+        rescan.extend(self.generate_handler_mask_stmt());
+
+        if self.uses_handler_mask() {
+            // self.masks[idx.0] = mask;
+            rescan.push(self.generate_mask_store(P(util::create_var_expr(str_to_ident("mask")))));
+        }
+
+        if self.priority {
+            // let priority = self.priorities[idx.0];
+            rescan.push(util::create_let_stmt(
+                str_to_ident("priority"),
+                Some(P(util::create_idx_expr(
+                    P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0)),
+                    P(util::create_self_field_expr(util::field_ident("priorities")))
+                )))
+            ));
+        }
+
+        for (i, handler) in self.handlers.iter().enumerate() {
+            rescan.push(util::create_stmt(P(handler.generate_add_check(
+                self,
+                i,
+                P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0))
+            ))));
+        }
+
+        // Objects are visited in storage order, so handlers without an order_by or priority follow it as after sort_by
+        // for pos in 0..self.objects.len() { .. }
+        stmts.push(util::create_stmt(P(util::create_for_expr(
+            str_to_ident("pos"),
+            P(util::create_range_expr(
+                P(util::create_num_expr(0)),
+                P(util::create_method_call(
+                    str_to_ident("len"),
+                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                    Vec::new()
+                ))
+            )),
+            P(util::create_block(rescan, None))
+        ))));
+
+        stmts.extend(self.check_bookkeeping_stmt());
+
+        util::impl_mut_method(
+            str_to_ident("rebuild_indices"),
+            Vec::new(),
+            None,
+            P(util::create_block(stmts, None))
+        )
+    }
+
+    fn generate_fn_remove_impl(&self) -> ImplItem {
+        // Sorted storage has to keep its order, so everything after the object moves down instead
+        let remove = str_to_ident(if self.sort_key.is_some() { "remove" } else { "swap_remove" });
+
+        let mut stmts = vec![
+            util::create_let_stmt(
+                str_to_ident("obj"),
+                Some(P(util::create_method_call(
+                    remove,
+                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                    vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
+                )))
+            ),
+
+            // self.handles.swap_remove(obj_idx);
+            util::create_stmt(P(util::create_method_call(
+                remove,
+                P(util::create_self_field_expr(util::field_ident("handles"))),
+                vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
+            )))
+        ];
+
+        if self.sort_key.is_some() {
+            stmts.push(self.generate_renumber_stmt(P(util::create_var_expr(str_to_ident("obj_idx")))));
+        } else {
+            // if let Some(moved) = self.handles.get(obj_idx).cloned() { *self.idxs.get_unchecked_mut(moved.0) = Some(obj_idx); }
+            stmts.push(util::create_stmt(P(util::create_if_let_expr(
+                P(util::create_tuple_struct_pat(
+                    str_to_ident("Some"),
+                    vec![str_to_ident("moved")]
+                )),
+                P(util::create_method_call(
+                    str_to_ident("cloned"),
+                    P(util::create_method_call(
+                        str_to_ident("get"),
+                        P(util::create_self_field_expr(util::field_ident("handles"))),
+                        vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
+                    )),
+                    Vec::new()
+                )),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_assign_expr(
+                        P(util::create_deref_expr(P(util::create_method_call(
+                            str_to_ident("get_unchecked_mut"),
+                            P(util::create_self_field_expr(util::field_ident("idxs"))),
+                            vec![P(util::create_tuple_field_expr(
+                                P(util::create_var_expr(str_to_ident("moved"))),
+                                0
+                            ))]
+                        )))),
+                        P(util::create_call(
+                            P(util::create_var_expr(str_to_ident("Some"))),
+                            vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
+                        ))
+                    )))],
+                    None
+                )),
+                None
+            ))));
+        }
+
+        stmts.push(util::create_stmt(P(util::create_assign_expr(
+            P(util::create_deref_expr(P(util::create_method_call(
+                str_to_ident("get_unchecked_mut"),
+                P(util::create_self_field_expr(util::field_ident("idxs"))),
+                vec![P(util::create_tuple_field_expr(
+                    P(util::create_var_expr(str_to_ident("idx"))),
+                    0
+                ))]
+            )))),
+            P(util::create_var_expr(str_to_ident("None")))
+        ))));
+
+        if self.uses_handler_mask() {
+            // self.masks[idx.0] = 0;
+            stmts.push(self.generate_mask_store(P(util::create_num_expr(0))));
+        }
+
+        stmts.extend(self.check_bookkeeping_stmt());
+
+        util::impl_mut_method(
+            str_to_ident("remove"),
+            vec![util::create_arg(
+                str_to_ident("idx"),
+                P(util::ty_from_ident(self.idx_name()))
+            )],
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                self.object_ty()
+            ))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_method_call(
+                    str_to_ident("and_then"),
+                    P(util::create_method_call(
+                        str_to_ident("cloned"),
+                        P(util::create_method_call(
+                            str_to_ident("get"),
+                            P(util::create_self_field_expr(util::field_ident("idxs"))),
+                            vec![P(util::create_tuple_field_expr(
+                                P(util::create_var_expr(str_to_ident("idx"))),
+                                0
+                            ))],
+                        )),
+                        Vec::new()
+                    )),
+                    vec![P(util::create_closure_expr(
+                        vec![util::create_arg(
+                            str_to_ident("obj_idx"),
+                            P(util::param_ty_from_ident(
+                                str_to_ident("Option"),
+                                util::ty_from_ident(str_to_ident("usize"))
+                            ))
+                        )],
+                        P(util::create_block(
+                            Vec::new(),
+                            Some(P(util::create_method_call(
+                                str_to_ident("map"),
+                                P(util::create_var_expr(str_to_ident("obj_idx"))),
+                                vec![P(util::create_closure_expr(
+                                    vec![util::create_arg(
+                                        str_to_ident("obj_idx"),
+                                        P(util::ty_from_ident(str_to_ident("usize")))
+                                    )],
+                                    P(util::create_unsafe_block(
+                                        stmts,
+                                        Some(P(util::create_var_expr(str_to_ident("obj"))))
+                                    ))
+                                ))]
+                            )))
+                        ))
+                    ))]
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_cleanup_impl(&self, cleanup_fn: Ident) -> ImplItem {
+        // Flagged objects are only removed once they have all been found, as removing one moves another into its place
+        let stmts = vec![
+            // let mut flagged = Vec::new();
+            util::create_let_mut_stmt(
+                str_to_ident("flagged"),
+                Some(P(self.vec_new()))
+            ),
+
+            // for i in 0..self.objects.len() { if self.objects[i].cleanup() { flagged.push(self.handles[i]); } }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("i"),
+                P(util::create_range_expr(
+                    P(util::create_num_expr(0)),
+                    P(util::create_method_call(
+                        str_to_ident("len"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
+                    ))
+                )),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_if_expr(
+                        P(util::create_method_call(
+                            cleanup_fn,
+                            P(self.object_access(
+                                P(util::create_idx_expr(
+                                    P(util::create_var_expr(str_to_ident("i"))),
+                                    P(util::create_self_field_expr(util::field_ident("objects")))
+                                )),
+                                Mutability::Immutable
+                            )),
+                            Vec::new()
+                        )),
+                        P(util::create_block(
+                            vec![util::create_stmt(P(util::create_method_call(
+                                str_to_ident("push"),
+                                P(util::create_var_expr(str_to_ident("flagged"))),
+                                vec![P(util::create_idx_expr(
+                                    P(util::create_var_expr(str_to_ident("i"))),
+                                    P(util::create_self_field_expr(util::field_ident("handles")))
+                                ))]
+                            )))],
+                            None
+                        )),
+                        None
+                    )))],
+                    None
+                ))
+            ))),
+
+            // let mut removed = Vec::new();
+            util::create_let_mut_stmt(
+                str_to_ident("removed"),
+                Some(P(self.vec_new()))
+            ),
+
+            // for idx in flagged { removed.extend(self.remove(idx)); }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("idx"),
+                P(util::create_var_expr(str_to_ident("flagged"))),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_method_call(
+                        str_to_ident("extend"),
+                        P(util::create_var_expr(str_to_ident("removed"))),
+                        vec![P(util::create_method_call(
+                            str_to_ident("remove"),
+                            P(util::create_var_expr(str_to_ident("self"))),
+                            vec![P(util::create_var_expr(str_to_ident("idx")))]
+                        ))]
+                    )))],
+                    None
+                ))
+            )))
+        ];
+
+        util::impl_mut_method(
+            str_to_ident("cleanup"),
+            Vec::new(),
+            Some(P(self.vec_ty(self.object_ty()))),
+            P(util::create_block(stmts, Some(P(util::create_var_expr(str_to_ident("removed"))))))
+        )
+    }
+
+    fn generate_fn_replace_impl(&self) -> ImplItem {
+        let mut stmts = Vec::new();
+
+        if let Some((key_fn, _)) = self.sort_key {
+            // The replacement takes over the old object's place, so it has to sort the same way
+            // if object.key() != self.objects[obj_idx].key() { return Err(object) }
+            stmts.push(util::create_stmt(P(util::create_if_expr(
+                P(util::create_binop_expr(
+                    self.key_of(key_fn, P(util::create_var_expr(str_to_ident("object")))),
+                    BinOpKind::Ne,
+                    self.key_of(key_fn, P(util::create_idx_expr(
+                        P(util::create_var_expr(str_to_ident("obj_idx"))),
+                        P(util::create_self_field_expr(util::field_ident("objects")))
+                    )))
+                )),
+                P(util::create_return_block(Some(P(util::create_call(
+                    P(util::create_var_expr(str_to_ident("Err"))),
+                    vec![P(util::create_var_expr(str_to_ident("object")))]
+                ))))),
+                None
+            ))));
+        }
+
+        stmts.extend(vec![
+            // let old = ::std::mem::replace(&mut self.objects[obj_idx], object);
+            util::create_let_stmt(
+                str_to_ident("old"),
+                Some(P(util::create_call(
+                    P(util::create_path_expr(self.std_path(&["mem", "replace"]))),
+                    vec![
+                        P(util::create_addr_of_expr(
+                            Mutability::Mutable,
+                            P(util::create_idx_expr(
+                                P(util::create_var_expr(str_to_ident("obj_idx"))),
+                                P(util::create_self_field_expr(util::field_ident("objects")))
+                            ))
+                        )),
+                        P(util::create_var_expr(str_to_ident("object")))
+                    ]
+                )))
+            )
+        ]);
+
+        for handler in self.handlers.iter() {
+            stmts.push(handler.generate_remove_check());
+        }
+
+        // let object = &self.objects[obj_idx];
+        stmts.push(util::create_let_stmt(
+            str_to_ident("object"),
+            Some(P(util::create_addr_of_expr(
+                Mutability::Immutable,
+                P(util::create_idx_expr(
+                    P(util::create_var_expr(str_to_ident("obj_idx"))),
+                    P(util::create_self_field_expr(util::field_ident("objects")))
+                ))
+            )))
+        ));
+
+        stmts.extend(self.generate_handler_mask_stmt());
+
+        if self.uses_handler_mask() {
+            // self.masks[idx.0] = mask;
+            stmts.push(self.generate_mask_store(P(util::create_var_expr(str_to_ident("mask")))));
+        }
+
+        if self.priority {
+            // The replacement takes over the old object's priority
+            // let priority = self.priorities[idx.0];
+            stmts.push(util::create_let_stmt(
+                str_to_ident("priority"),
+                Some(P(util::create_idx_expr(
+                    P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0)),
+                    P(util::create_self_field_expr(util::field_ident("priorities")))
+                )))
+            ));
+        }
+
+        for (i, handler) in self.handlers.iter().enumerate() {
+            stmts.push(util::create_stmt(P(handler.generate_add_check(
+                self,
+                i,
+                P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0))
+            ))));
+        }
+
+        stmts.extend(self.check_bookkeeping_stmt());
+
+        util::impl_mut_method(
+            str_to_ident("replace"),
+            vec![
+                util::create_arg(
+                    str_to_ident("idx"),
+                    P(util::ty_from_ident(self.idx_name()))
+                ),
+                util::create_arg(
+                    str_to_ident("object"),
+                    P(self.object_ty())
+                )
+            ],
+            Some(P(util::params_ty_from_ident(
+                str_to_ident("Result"),
+                vec![self.object_ty(), self.object_ty()]
+            ))),
+            P(util::create_block(
+                Vec::new(),
+
+                // if let Some(obj_idx) = self.idxs.get(idx.0).cloned().unwrap_or(None) { .. } else { Err(object) }
+                Some(P(util::create_if_let_expr(
+                    P(util::create_tuple_struct_pat(
+                        str_to_ident("Some"),
+                        vec![str_to_ident("obj_idx")]
+                    )),
+                    P(util::create_method_call(
+                        str_to_ident("unwrap_or"),
+                        P(util::create_method_call(
+                            str_to_ident("cloned"),
+                            P(util::create_method_call(
+                                str_to_ident("get"),
+                                P(util::create_self_field_expr(util::field_ident("idxs"))),
+                                vec![P(util::create_tuple_field_expr(
+                                    P(util::create_var_expr(str_to_ident("idx"))),
+                                    0
+                                ))]
+                            )),
+                            Vec::new()
+                        )),
+                        vec![P(util::create_var_expr(str_to_ident("None")))]
+                    )),
+                    P(util::create_block(
+                        stmts,
+                        Some(P(util::create_call(
+                            P(util::create_var_expr(str_to_ident("Ok"))),
+                            vec![P(util::create_var_expr(str_to_ident("old")))]
+                        )))
+                    )),
+                    Some(P(util::create_block_expr(P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_call(
+                            P(util::create_var_expr(str_to_ident("Err"))),
+                            vec![P(util::create_var_expr(str_to_ident("object")))]
+                        )))
+                    )))))
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_get_impl(&self) -> ImplItem {
+        util::impl_method(
+            str_to_ident("get"),
+            vec![util::create_arg(
+                str_to_ident("idx"),
+                P(util::ty_from_ident(self.idx_name()))
+            )],
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::ref_ty(P(self.object_ty()))
+            ))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_method_call(
+                    str_to_ident("and_then"),
+                    P(util::create_method_call(
+                        str_to_ident("cloned"),
+                        P(util::create_method_call(
+                            str_to_ident("get"),
+                            P(util::create_self_field_expr(util::field_ident("idxs"))),
+                            vec![P(util::create_tuple_field_expr(
+                                P(util::create_var_expr(str_to_ident("idx"))),
+                                0
+                            ))],
+                        )),
+                        Vec::new()
+                    )),
+                    vec![P(util::create_closure_expr(
+                        vec![util::create_arg(
+                            str_to_ident("obj_idx"),
+                            P(util::param_ty_from_ident(
+                                str_to_ident("Option"),
+                                util::ty_from_ident(str_to_ident("usize"))
+                            ))
+                        )],
+                        P(util::create_block(
+                            Vec::new(),
+                            Some(P(util::create_method_call(
+                                str_to_ident("map"),
+                                P(util::create_var_expr(str_to_ident("obj_idx"))),
+                                vec![P(util::create_closure_expr(
+                                    vec![util::create_arg(
+                                        str_to_ident("obj_idx"),
+                                        P(util::ty_from_ident(str_to_ident("usize")))
+                                    )],
+                                    P(util::create_unsafe_block(
+                                        Vec::new(),
+                                        Some(P(util::create_method_call(
+                                            str_to_ident("get_unchecked"),
+                                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                                            vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
+                                        )))
+                                    ))
+                                ))]
+                            )))
+                        ))
+                    ))]
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_get_mut_impl(&self) -> ImplItem {
+        util::impl_mut_method(
+            str_to_ident("get_mut"),
+            vec![util::create_arg(
+                str_to_ident("idx"),
+                P(util::ty_from_ident(self.idx_name()))
+            )],
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::mut_ref_ty(P(self.object_ty()))
+            ))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_method_call(
+                    str_to_ident("and_then"),
+                    P(util::create_method_call(
+                        str_to_ident("cloned"),
+                        P(util::create_method_call(
+                            str_to_ident("get"),
+                            P(util::create_self_field_expr(util::field_ident("idxs"))),
+                            vec![P(util::create_tuple_field_expr(
+                                P(util::create_var_expr(str_to_ident("idx"))),
+                                0
+                            ))],
+                        )),
+                        Vec::new()
+                    )),
+                    vec![P(util::create_closure_expr(
+                        vec![util::create_arg(
+                            str_to_ident("obj_idx"),
+                            P(util::param_ty_from_ident(
+                                str_to_ident("Option"),
+                                util::ty_from_ident(str_to_ident("usize"))
+                            ))
+                        )],
+                        P(util::create_block(
+                            Vec::new(),
+                            Some(P(util::create_method_call(
+                                str_to_ident("map"),
+                                P(util::create_var_expr(str_to_ident("obj_idx"))),
+                                vec![P(util::create_closure_expr(
+                                    vec![util::create_arg(
+                                        str_to_ident("obj_idx"),
+                                        P(util::ty_from_ident(str_to_ident("usize")))
+                                    )],
+                                    P(util::create_unsafe_block(
+                                        Vec::new(),
+                                        Some(P(util::create_method_call(
+                                            str_to_ident("get_unchecked_mut"),
+                                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                                            vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
+                                        )))
+                                    ))
+                                ))]
+                            )))
+                        ))
+                    ))]
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_reserve_impl(&self) -> ImplItem {
+        let mut fields = vec![
+            util::field_ident("objects"),
+            util::field_ident("handles"),
+            util::field_ident("idxs")
+        ];
+
+        // Every new object could implement every handler, so each handler's list gets the same room
+        fields.extend(self.handlers.iter().map(|handler| util::idxs_ident(handler.name)));
+
+        if self.uses_handler_mask() {
+            fields.push(util::field_ident("masks"));
+        }
+
+        if self.priority {
+            fields.push(util::field_ident("priorities"));
+        }
+
+        util::impl_mut_method(
+            str_to_ident("reserve"),
+            vec![util::create_arg(
+                str_to_ident("additional"),
+                P(util::ty_from_ident(str_to_ident("usize")))
+            )],
+            None,
+            P(util::create_block(
+                // self.field.reserve(additional);
+                fields.into_iter().map(|field| util::create_stmt(P(util::create_method_call(
+                    str_to_ident("reserve"),
+                    P(util::create_self_field_expr(field)),
+                    vec![P(util::create_var_expr(str_to_ident("additional")))]
+                )))).collect(),
+                None
+            ))
+        )
+    }
+
+    fn generate_fn_shrink_to_fit_impl(&self) -> ImplItem {
+        let mut fields = vec![
+            util::field_ident("objects"),
+            util::field_ident("handles"),
+            util::field_ident("idxs")
+        ];
+
+        fields.extend(self.handlers.iter().map(|handler| util::idxs_ident(handler.name)));
+
+        if self.uses_handler_mask() {
+            fields.push(util::field_ident("masks"));
+        }
+
+        if self.priority {
+            fields.push(util::field_ident("priorities"));
+        }
+
+        util::impl_mut_method(
+            str_to_ident("shrink_to_fit"),
+            Vec::new(),
+            None,
+            P(util::create_block(
+                // self.field.shrink_to_fit();
+                fields.into_iter().map(|field| util::create_stmt(P(util::create_method_call(
+                    str_to_ident("shrink_to_fit"),
+                    P(util::create_self_field_expr(field)),
+                    Vec::new()
+                )))).collect(),
+                None
+            ))
+        )
+    }
+
+    fn generate_fn_capacity_impl(&self) -> ImplItem {
+        util::impl_method(
+            str_to_ident("capacity"),
+            Vec::new(),
+            Some(P(util::ty_from_ident(str_to_ident("usize")))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_method_call(
+                    str_to_ident("capacity"),
+                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                    Vec::new()
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_membership_impl(&self) -> ImplItem {
+        util::impl_method(
+            str_to_ident("membership"),
+            Vec::new(),
+            Some(P(util::array_ty(P(util::ty_from_ident(str_to_ident("usize"))), self.handlers.len()))),
+            P(util::create_block(
+                Vec::new(),
+
+                // [self.handler_idxs.iter().filter(..).count(), ..]
+                Some(P(util::create_array_expr(self.handlers.iter().map(|handler| P(handler.live_count(
+                    P(util::create_self_field_expr(util::idxs_ident(handler.name)))
+                ))).collect())))
+            ))
+        )
+    }
+
+    fn generate_fn_handler_counts_impl(&self) -> ImplItem {
+        // [("Handler", self.handler_idxs.iter().filter(..).count()), ..].to_vec()
+        let counts = util::create_method_call(
+            str_to_ident("to_vec"),
+            P(util::create_array_expr(self.handlers.iter().map(|handler| P(util::create_tuple_expr(vec![
+                P(util::create_str_expr(handler.name.name.as_str())),
+                P(handler.live_count(P(util::create_self_field_expr(util::idxs_ident(handler.name)))))
+            ]))).collect())),
+            Vec::new()
+        );
+
+        util::impl_method(
+            str_to_ident("handler_counts"),
+            Vec::new(),
+            Some(P(self.vec_ty(util::tuple_ty(vec![
+                P(util::static_ref_ty(P(util::ty_from_ident(str_to_ident("str"))))),
+                P(util::ty_from_ident(str_to_ident("usize")))
+            ])))),
+            P(util::create_block(Vec::new(), Some(P(counts))))
+        )
+    }
+
+    fn generate_fn_same_membership_impl(&self) -> ImplItem {
+        // self.objects.len() == other.objects.len()
+        let objects_match = P(util::create_binop_expr(
+            P(util::create_method_call(
+                str_to_ident("len"),
+                P(util::create_self_field_expr(util::field_ident("objects"))),
+                Vec::new()
+            )),
+            BinOpKind::Eq,
+            P(util::create_method_call(
+                str_to_ident("len"),
+                P(util::create_field_expr(util::field_ident("objects"), str_to_ident("other"))),
+                Vec::new()
+            ))
+        ));
+
+        // .. && self.handler_idxs.iter().filter(..).count() == other.handler_idxs.iter().filter(..).count() && ..
+        let check = self.handlers.iter().fold(objects_match, |check, handler| P(util::create_binop_expr(
+            check,
+            BinOpKind::And,
+            P(util::create_binop_expr(
+                P(handler.live_count_in("self", P(util::create_self_field_expr(util::idxs_ident(handler.name))))),
+                BinOpKind::Eq,
+                P(handler.live_count_in("other", P(util::create_field_expr(util::idxs_ident(handler.name), str_to_ident("other")))))
+            ))
+        )));
+
+        util::impl_method(
+            str_to_ident("same_membership"),
+            vec![util::create_arg(str_to_ident("other"), P(util::ref_ty_from_ident(self.name)))],
+            Some(P(util::ty_from_ident(str_to_ident("bool")))),
+            P(util::create_block(Vec::new(), Some(check)))
+        )
+    }
+
+    fn generate_fn_count_where_impl(&self) -> ImplItem {
+        // F: Fn(&Box<SystemObject>) -> bool
+        let generics = util::create_generics(vec![util::create_ty_param(
+            str_to_ident("F"),
+            vec![util::fn_bound(
+                str_to_ident("Fn"),
+                vec![P(util::ref_ty(P(self.object_ty())))],
+                Some(P(util::ty_from_ident(str_to_ident("bool"))))
+            )]
+        )]);
+
+        util::impl_generic_method(
+            str_to_ident("count_where"),
+            generics,
+            vec![util::create_arg(
+                str_to_ident("f"),
+                P(util::ty_from_ident(str_to_ident("F")))
+            )],
+            Some(P(util::ty_from_ident(str_to_ident("usize")))),
+            P(util::create_block(
+                Vec::new(),
+
+                // self.objects.iter().filter(|object| f(object)).count()
+                Some(P(util::create_method_call(
+                    str_to_ident("count"),
+                    P(util::create_method_call(
+                        str_to_ident("filter"),
+                        P(util::create_method_call(
+                            str_to_ident("iter"),
+                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                            Vec::new()
+                        )),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("object"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_call(
+                                    P(util::create_var_expr(str_to_ident("f"))),
+                                    vec![P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("object")))))]
+                                )))
+                            ))
+                        ))]
+                    )),
+                    Vec::new()
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_handlers_of_impl(&self) -> ImplItem {
+        let mut checks = Vec::new();
+
+        for handler in self.handlers.iter() {
+            // if object.as_handler().is_some() { handlers.push("Handler"); }
+            checks.push(util::create_stmt(P(util::create_if_expr(
+                P(util::create_method_call(
+                    str_to_ident("is_some"),
+                    P(util::create_method_call(
+                        util::as_ident(handler.name),
+                        P(self.object_access(
+                            P(util::create_var_expr(str_to_ident("object"))),
+                            Mutability::Immutable
+                        )),
+                        Vec::new()
+                    )),
+                    Vec::new()
+                )),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_method_call(
+                        str_to_ident("push"),
+                        P(util::create_var_expr(str_to_ident("handlers"))),
+                        vec![P(util::create_str_expr(handler.name.name.as_str()))]
+                    )))],
+                    None
+                )),
+                None
+            ))));
+        }
+
+        util::impl_method(
+            str_to_ident("handlers_of"),
+            vec![util::create_arg(
+                str_to_ident("idx"),
+                P(util::ty_from_ident(self.idx_name()))
+            )],
+            Some(P(self.vec_ty(util::static_ref_ty(P(util::ty_from_ident(str_to_ident("str"))))))),
+            P(util::create_block(
+                vec![
+                    // let mut handlers = Vec::new();
+                    util::create_let_mut_stmt(
+                        str_to_ident("handlers"),
+                        Some(P(self.vec_new()))
+                    ),
+
+                    // if let Some(object) = self.get(idx) { .. }
+                    util::create_stmt(P(util::create_if_let_expr(
+                        P(util::create_tuple_struct_pat(
+                            str_to_ident("Some"),
+                            vec![str_to_ident("object")]
+                        )),
+                        P(util::create_method_call(
+                            str_to_ident("get"),
+                            P(util::create_var_expr(str_to_ident("self"))),
+                            vec![P(util::create_var_expr(str_to_ident("idx")))]
+                        )),
+                        P(util::create_block(checks, None)),
+                        None
+                    )))
+                ],
+                Some(P(util::create_var_expr(str_to_ident("handlers"))))
+            ))
+        )
+    }
+
+    fn generate_fn_dump_membership_impl(&self) -> ImplItem {
+        let idx = || P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("idx")))));
+
+        util::impl_method(
+            str_to_ident("dump_membership"),
+            Vec::new(),
+            Some(P(self.vec_ty(util::tuple_ty(vec![
+                P(util::ty_from_ident(self.idx_name())),
+                P(self.vec_ty(util::static_ref_ty(P(util::ty_from_ident(str_to_ident("str"))))))
+            ])))),
+            P(util::create_block(
+                Vec::new(),
+
+                // self.handles.iter().map(|idx| (*idx, self.handlers_of(*idx))).collect()
+                Some(P(util::create_method_call(
+                    str_to_ident("collect"),
+                    P(util::create_method_call(
+                        str_to_ident("map"),
+                        P(util::create_method_call(
+                            str_to_ident("iter"),
+                            P(util::create_self_field_expr(util::field_ident("handles"))),
+                            Vec::new()
+                        )),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("idx"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_tuple_expr(vec![
+                                    idx(),
+                                    P(util::create_method_call(
+                                        str_to_ident("handlers_of"),
+                                        P(util::create_var_expr(str_to_ident("self"))),
+                                        vec![idx()]
+                                    ))
+                                ])))
+                            ))
+                        ))]
+                    )),
+                    Vec::new()
+                )))
+            ))
+        )
+    }
+
+    fn check_bookkeeping_stmt(&self) -> Option<Stmt> {
+        if !self.debug_checks {
+            return None
+        }
+
+        // self.check_bookkeeping();
+        Some(util::create_stmt(P(util::create_method_call(
+            str_to_ident("check_bookkeeping"),
+            P(util::create_var_expr(str_to_ident("self"))),
+            Vec::new()
+        ))))
+    }
+
+    fn generate_fn_check_bookkeeping_impls(&self) -> Vec<ImplItem> {
+        let stmts = self.handlers.iter().map(|handler| {
+            // self.objects.get(idx).expect(..).as_handler().expect(..);
+            let check = util::create_method_call(
+                str_to_ident("expect"),
+                P(util::create_method_call(
+                    util::as_ident(handler.name),
+                    P(self.object_access(
+                        P(util::create_method_call(
+                            str_to_ident("expect"),
+                            P(util::create_method_call(
+                                str_to_ident("get"),
+                                P(util::create_self_field_expr(util::field_ident("objects"))),
+                                vec![P(util::create_var_expr(str_to_ident("idx")))]
+                            )),
+                            vec![P(util::create_str_expr(InternedString::new("A handler refers to an object past the end of the system")))]
+                        )),
+                        Mutability::Immutable
+                    )),
+                    Vec::new()
+                )),
+                vec![P(util::create_str_expr(InternedString::new("A handler refers to an object that does not implement it")))]
+            );
+
+            // for handle in self.handler_idxs.iter() { if let Some(idx) = self.idxs[*handle] { .. } }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("handle"),
+                P(util::create_method_call(
+                    str_to_ident("iter"),
+                    P(util::create_self_field_expr(util::idxs_ident(handler.name))),
+                    Vec::new()
+                )),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_if_let_expr(
+                        P(util::create_tuple_struct_pat(
+                            str_to_ident("Some"),
+                            vec![str_to_ident("idx")]
+                        )),
+                        P(util::create_idx_expr(
+                            P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("handle"))))),
+                            P(util::create_self_field_expr(util::field_ident("idxs")))
+                        )),
+                        P(util::create_block(vec![util::create_stmt(P(check))], None)),
+                        None
+                    )))],
+                    None
+                ))
+            )))
+        }).collect();
+
+        self.generate_debug_check_impls("check_bookkeeping", stmts)
+    }
+
+    fn generate_fn_check_not_dispatching_impls(&self) -> Vec<ImplItem> {
+        // (if self.dispatching { None } else { Some(()) }).expect(..);
+        let check = util::create_method_call(
+            str_to_ident("expect"),
+            P(util::create_if_expr(
+                P(util::create_self_field_expr(util::field_ident("dispatching"))),
+                P(util::create_block(Vec::new(), Some(P(util::create_var_expr(str_to_ident("None")))))),
+                Some(P(util::create_block_expr(P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_call(
+                        P(util::create_var_expr(str_to_ident("Some"))),
+                        vec![P(util::create_tuple_expr(Vec::new()))]
+                    )))
+                )))))
+            )),
+            vec![P(util::create_str_expr(InternedString::new("A signal was sent while another was being dispatched")))]
+        );
+
+        self.generate_debug_check_impls("check_not_dispatching", vec![util::create_stmt(P(check))])
+    }
+
+    // Release builds get an empty twin, so that the calls cost nothing there
+    fn generate_debug_check_impls(&self, name: &str, stmts: Vec<Stmt>) -> Vec<ImplItem> {
+        let mut checked = util::impl_method_priv(
+            str_to_ident(name),
+            Vec::new(),
+            None,
+            P(util::create_block(stmts, None))
+        );
+        checked.attrs.push(util::create_cfg_attr("debug_assertions", true));
+
+        let mut unchecked = util::impl_method_priv(
+            str_to_ident(name),
+            Vec::new(),
+            None,
+            P(util::create_block(Vec::new(), None))
+        );
+        unchecked.attrs.push(util::create_cfg_attr("debug_assertions", false));
+
+        vec![checked, unchecked]
+    }
+
+    fn generate_fn_is_dispatching_impl(&self) -> ImplItem {
+        // self.dispatching
+        util::impl_method(
+            str_to_ident("is_dispatching"),
+            Vec::new(),
+            Some(P(util::ty_from_ident(str_to_ident("bool")))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_self_field_expr(util::field_ident("dispatching"))))
+            ))
+        )
+    }
+
+    fn generate_fn_apply_commands_impl(&self) -> ImplItem {
+        let command_arm = |variant: &str, binding: &str| util::create_arm(
+            P(util::create_path_tuple_struct_pat(
+                vec![self.command_name(), str_to_ident(variant)],
+                vec![str_to_ident(binding)]
+            )),
+            // let _result = self.add(object);
+            P(util::create_block_expr(P(util::create_block(
+                vec![util::create_let_stmt(
+                    str_to_ident("_result"),
+                    Some(P(util::create_method_call(
+                        str_to_ident(&variant.to_lowercase()),
+                        P(util::create_var_expr(str_to_ident("self"))),
+                        vec![P(util::create_var_expr(str_to_ident(binding)))]
+                    )))
+                )],
+                None
+            ))))
+        );
+
+        // if let Err(object) = self.add(object) { self.rejected.push(object); }
+        let add_arm = util::create_arm(
+            P(util::create_path_tuple_struct_pat(
+                vec![self.command_name(), str_to_ident("Add")],
+                vec![str_to_ident("object")]
+            )),
+            P(util::create_block_expr(P(util::create_block(
+                vec![util::create_stmt(P(util::create_if_let_expr(
+                    P(util::create_tuple_struct_pat(
+                        str_to_ident("Err"),
+                        vec![str_to_ident("object")]
+                    )),
+                    P(util::create_method_call(
+                        str_to_ident("add"),
+                        P(util::create_var_expr(str_to_ident("self"))),
+                        vec![P(util::create_var_expr(str_to_ident("object")))]
+                    )),
+                    P(util::create_block(
+                        vec![util::create_stmt(P(util::create_method_call(
+                            str_to_ident("push"),
+                            P(util::create_self_field_expr(util::field_ident("rejected"))),
+                            vec![P(util::create_var_expr(str_to_ident("object")))]
+                        )))],
+                        None
+                    )),
+                    None
+                )))],
+                None
+            ))))
+        );
+
+        util::impl_mut_method_priv(
+            str_to_ident("apply_commands"),
+            Vec::new(),
+            None,
+            P(util::create_block(
+                vec![
+                    // let commands = ::std::mem::replace(&mut self.commands, Vec::new());
+                    util::create_let_stmt(
+                        str_to_ident("commands"),
+                        Some(P(util::create_call(
+                            P(util::create_path_expr(self.std_path(&["mem", "replace"]))),
+                            vec![
+                                P(util::create_addr_of_expr(
+                                    Mutability::Mutable,
+                                    P(util::create_self_field_expr(util::field_ident("commands")))
+                                )),
+                                P(self.vec_new())
+                            ]
+                        )))
+                    ),
+
+                    // for command in commands { match command { .. } }
+                    util::create_stmt(P(util::create_for_expr(
+                        str_to_ident("command"),
+                        P(util::create_var_expr(str_to_ident("commands"))),
+                        P(util::create_block(
+                            vec![util::create_stmt(P(util::create_match_expr(
+                                P(util::create_var_expr(str_to_ident("command"))),
+                                vec![
+                                    if self.keeps_rejected() { add_arm } else { command_arm("Add", "object") },
+                                    command_arm("Remove", "idx")
+                                ]
+                            )))],
+                            None
+                        ))
+                    )))
+                ],
+                None
+            ))
+        )
+    }
+
+    fn generate_fn_take_rejected_impl(&self) -> ImplItem {
+        // ::std::mem::replace(&mut self.rejected, Vec::new())
+        util::impl_mut_method(
+            str_to_ident("take_rejected"),
+            Vec::new(),
+            Some(P(self.vec_ty(self.object_ty()))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_call(
+                    P(util::create_path_expr(self.std_path(&["mem", "replace"]))),
+                    vec![
+                        P(util::create_addr_of_expr(
+                            Mutability::Mutable,
+                            P(util::create_self_field_expr(util::field_ident("rejected")))
+                        )),
+                        P(self.vec_new())
+                    ]
+                )))
+            ))
+        )
+    }
+
+    fn generate_fn_checkpoint_impl(&self) -> ImplItem {
+        let mut fields = vec![
+            // handles: self.idxs.len()
+            util::create_field(
+                str_to_ident("handles"),
+                P(util::create_method_call(
+                    str_to_ident("len"),
+                    P(util::create_self_field_expr(util::field_ident("idxs"))),
+                    Vec::new()
+                ))
+            )
+        ];
+
+        for handler in self.handlers.iter() {
+            // handler_idxs: self.handler_idxs.clone()
+            fields.push(util::create_field(
+                util::idxs_ident(handler.name),
+                P(util::create_method_call(
+                    str_to_ident("clone"),
+                    P(util::create_self_field_expr(util::idxs_ident(handler.name))),
+                    Vec::new()
+                ))
+            ));
+        }
+
+        util::impl_method(
+            str_to_ident("checkpoint"),
+            Vec::new(),
+            Some(P(util::ty_from_ident(self.snapshot_name()))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_struct_expr(self.snapshot_name(), fields)))
+            ))
+        )
+    }
+
+    fn generate_fn_restore_impl(&self) -> ImplItem {
+        let mut stmts = vec![
+            // let mut removed = Vec::new();
+            util::create_let_mut_stmt(
+                str_to_ident("removed"),
+                Some(P(self.vec_new()))
+            ),
+
+            // for idx in snapshot.handles..self.idxs.len() { .. }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("idx"),
+                P(util::create_range_expr(
+                    P(util::create_field_expr(str_to_ident("handles"), str_to_ident("snapshot"))),
+                    P(util::create_method_call(
+                        str_to_ident("len"),
+                        P(util::create_self_field_expr(util::field_ident("idxs"))),
+                        Vec::new()
+                    ))
+                )),
+                P(util::create_block(
+                    vec![
+                        // if let Some(object) = self.remove(SystemIndex(idx)) { removed.push(object); }
+                        util::create_stmt(P(util::create_if_let_expr(
+                            P(util::create_tuple_struct_pat(
+                                str_to_ident("Some"),
+                                vec![str_to_ident("object")]
+                            )),
+                            P(util::create_method_call(
+                                str_to_ident("remove"),
+                                P(util::create_var_expr(str_to_ident("self"))),
+                                vec![P(util::create_call(
+                                    P(util::create_var_expr(self.idx_name())),
+                                    vec![P(util::create_var_expr(str_to_ident("idx")))]
+                                ))]
+                            )),
+                            P(util::create_block(
+                                vec![util::create_stmt(P(util::create_method_call(
+                                    str_to_ident("push"),
+                                    P(util::create_var_expr(str_to_ident("removed"))),
+                                    vec![P(util::create_var_expr(str_to_ident("object")))]
+                                )))],
+                                None
+                            )),
+                            None
+                        )))
+                    ],
+                    None
+                ))
+            ))),
+
+            // let idxs = &self.idxs;
+            util::create_let_stmt(
+                str_to_ident("idxs"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("idxs")))
+                )))
+            ),
+
+            // let objects = &self.objects;
+            util::create_let_stmt(
+                str_to_ident("objects"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("objects")))
+                )))
+            )
+        ];
+
+        for handler in self.handlers.iter() {
+            // An object replaced since the checkpoint may no longer implement the handler
+            // |obj_idx| objects[obj_idx].as_handler().is_some()
+            let implements = util::create_closure_expr(
+                vec![util::create_closure_arg(str_to_ident("obj_idx"))],
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_method_call(
+                        str_to_ident("is_some"),
+                        P(util::create_method_call(
+                            util::as_ident(handler.name),
+                            P(self.object_access(
+                                P(util::create_idx_expr(
+                                    P(util::create_var_expr(str_to_ident("obj_idx"))),
+                                    P(util::create_var_expr(str_to_ident("objects")))
+                                )),
+                                Mutability::Immutable
+                            )),
+                            Vec::new()
+                        )),
+                        Vec::new()
+                    )))
+                ))
+            );
+
+            // self.handler_idxs = snapshot.handler_idxs.into_iter().filter(|idx| idxs[*idx].map_or(false, ..)).collect();
+            stmts.push(util::create_stmt(P(util::create_assign_expr(
+                P(util::create_self_field_expr(util::idxs_ident(handler.name))),
+                P(util::create_method_call(
+                    str_to_ident("collect"),
+                    P(util::create_method_call(
+                        str_to_ident("filter"),
+                        P(util::create_method_call(
+                            str_to_ident("into_iter"),
+                            P(util::create_field_expr(util::idxs_ident(handler.name), str_to_ident("snapshot"))),
+                            Vec::new()
+                        )),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("idx"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_method_call(
+                                    str_to_ident("map_or"),
+                                    P(util::create_idx_expr(
+                                        P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("idx"))))),
+                                        P(util::create_var_expr(str_to_ident("idxs")))
+                                    )),
+                                    vec![P(util::create_var_expr(str_to_ident("false"))), P(implements)]
+                                )))
+                            ))
+                        ))]
+                    )),
+                    Vec::new()
+                ))
+            ))));
+        }
+
+        util::impl_mut_method(
+            str_to_ident("restore"),
+            vec![util::create_arg(
+                str_to_ident("snapshot"),
+                P(util::ty_from_ident(self.snapshot_name()))
+            )],
+            Some(P(self.vec_ty(self.object_ty()))),
+            P(util::create_block(
+                stmts,
+                Some(P(util::create_var_expr(str_to_ident("removed"))))
+            ))
+        )
+    }
+
+    fn id_matches(&self, id_fn: Ident) -> P<Expr> {
+        // |object| object.id() == id
+        P(util::create_closure_expr(
+            vec![util::create_closure_arg(str_to_ident("object"))],
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_binop_expr(
+                    P(util::create_method_call(
+                        id_fn,
+                        P(self.object_access(P(util::create_var_expr(str_to_ident("object"))), Mutability::Immutable)),
+                        Vec::new()
+                    )),
+                    BinOpKind::Eq,
+                    P(util::create_var_expr(str_to_ident("id")))
+                )))
+            ))
+        ))
+    }
+
+    fn generate_find_by_id_impl(&self, id_fn: Ident, name: &str, iter: &str, mutability: Mutability) -> ImplItem {
+        let object_ty = P(self.object_ty());
+
+        let ret = util::param_ty_from_ident(
+            str_to_ident("Option"),
+            match mutability {
+                Mutability::Mutable => util::mut_ref_ty(object_ty),
+                Mutability::Immutable => util::ref_ty(object_ty)
+            }
+        );
+
+        // self.objects.iter().find(|object| object.id() == id)
+        let body = P(util::create_block(
+            Vec::new(),
+            Some(P(util::create_method_call(
+                str_to_ident("find"),
+                P(util::create_method_call(
+                    str_to_ident(iter),
+                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                    Vec::new()
+                )),
+                vec![self.id_matches(id_fn)]
+            )))
+        ));
+
+        let args = vec![util::create_arg(
+            str_to_ident("id"),
+            P(util::ty_from_ident(str_to_ident("u64")))
+        )];
+
+        match mutability {
+            Mutability::Mutable => util::impl_mut_method(str_to_ident(name), args, Some(P(ret)), body),
+            Mutability::Immutable => util::impl_method(str_to_ident(name), args, Some(P(ret)), body)
+        }
+    }
+
+    fn generate_position_of_impl(&self, id_fn: Ident) -> ImplItem {
+        util::impl_method(
+            str_to_ident("position_of"),
+            vec![util::create_arg(
+                str_to_ident("id"),
+                P(util::ty_from_ident(str_to_ident("u64")))
+            )],
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::ty_from_ident(str_to_ident("usize"))
+            ))),
+            P(util::create_block(
+                Vec::new(),
+
+                // self.objects.iter().position(|object| object.id() == id)
+                Some(P(util::create_method_call(
+                    str_to_ident("position"),
+                    P(util::create_method_call(
+                        str_to_ident("iter"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
+                    )),
+                    vec![self.id_matches(id_fn)]
+                )))
+            ))
+        )
+    }
+
+    fn generate_get_as_impl(&self, name: &str, get: &str, as_any: &str, downcast: &str, mutability: Mutability) -> ImplItem {
+        // T: 'static
+        let generics = util::create_generics(vec![util::create_ty_param(
+            str_to_ident("T"),
+            vec![util::lifetime_bound("'static")]
+        )]);
+
+        let ret = util::param_ty_from_ident(
+            str_to_ident("Option"),
+            match mutability {
+                Mutability::Mutable => util::mut_ref_ty_from_ident(str_to_ident("T")),
+                Mutability::Immutable => util::ref_ty_from_ident(str_to_ident("T"))
+            }
+        );
+
+        // self.get(idx).and_then(|object| object.as_any().downcast_ref())
+        let body = P(util::create_block(
+            Vec::new(),
+            Some(P(util::create_method_call(
+                str_to_ident("and_then"),
+                P(util::create_method_call(
+                    str_to_ident(get),
+                    P(util::create_var_expr(str_to_ident("self"))),
+                    vec![P(util::create_var_expr(str_to_ident("idx")))]
+                )),
+                vec![P(util::create_closure_expr(
+                    vec![util::create_closure_arg(str_to_ident("object"))],
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_method_call(
+                            str_to_ident(downcast),
+                            P(util::create_method_call(
+                                str_to_ident(as_any),
+                                P(util::create_var_expr(str_to_ident("object"))),
+                                Vec::new()
+                            )),
+                            Vec::new()
+                        )))
+                    ))
+                ))]
+            )))
+        ));
+
+        let args = vec![util::create_arg(
+            str_to_ident("idx"),
+            P(util::ty_from_ident(self.idx_name()))
+        )];
+
+        match mutability {
+            Mutability::Mutable => util::impl_mut_generic_method(str_to_ident(name), generics, args, Some(P(ret)), body),
+            Mutability::Immutable => util::impl_generic_method(str_to_ident(name), generics, args, Some(P(ret)), body)
+        }
+    }
+
+    // Each signal in a batch gets its own copy of the arguments it shares with a later one, so none is moved twice
+    fn batch_arg(&self, arg: &HandlerFnArg, used_later: bool) -> Expr {
+        let var = util::create_var_expr(arg.name);
+
+        match arg.ty.node {
+            // &mut *arg
+            TyKind::Rptr(_, MutTy { mutbl: Mutability::Mutable, .. }) => util::create_addr_of_expr(
+                Mutability::Mutable,
+                P(util::create_deref_expr(P(var)))
+            ),
+
+            TyKind::Rptr(..) => var,
+            _ if !used_later => var,
+
+            // arg.clone()
+            _ => util::create_method_call(str_to_ident("clone"), P(var), Vec::new())
+        }
+    }
+
+    fn generate_batch_impl(&self, batch: &BatchInfo) -> ImplItem {
+        let args = self.batch_args(batch).unwrap_or(Vec::new());
+
+        let functions: Vec<&HandlerFnInfo> = batch.signals.iter().filter_map(|signal| self.find_signal(*signal)).collect();
+
+        // let _result = self.signal(..);
+        let stmts = functions.iter().enumerate().map(|(i, function)| {
+            util::create_let_stmt(
+                str_to_ident("_result"),
+                Some(P(util::create_method_call(
+                    function.source_name,
+                    P(util::create_var_expr(str_to_ident("self"))),
+                    function.args.iter().map(|arg| {
+                        let used_later = functions[i + 1..].iter()
+                            .any(|later| later.args.iter().any(|other| other.name.name == arg.name.name));
+
+                        P(self.batch_arg(arg, used_later))
+                    }).collect()
+                )))
+            )
+        }).collect();
+
+        // Signals sharing a lifetime name share the batch's lifetime parameter, just as they share arguments
+        let mut lifetimes: Vec<Lifetime> = Vec::new();
+        let mut predicates: Vec<WherePredicate> = Vec::new();
+        for function in batch.signals.iter().filter_map(|signal| self.find_signal(*signal)) {
+            for lifetime in function.lifetimes.iter() {
+                if !lifetimes.iter().any(|existing| existing.name == lifetime.name) {
+                    lifetimes.push(*lifetime);
+                }
+            }
+
+            predicates.extend(function.where_predicates.iter().cloned());
+        }
+
+        util::with_where_predicates(
+            util::with_lifetimes(
+                util::impl_mut_method(
+                    batch.name,
+                    args.iter().map(|arg| arg.generate()).collect(),
+                    None,
+                    P(util::create_block(stmts, None))
+                ),
+                &lifetimes
+            ),
+            &predicates
+        )
+    }
+
+    fn generate_impl(&self) -> Item {
+        let mut fns = vec![
+            self.generate_fn_new_impl(),
+            self.generate_fn_add_impl(),
+            self.generate_fn_add_all_impl(),
+            self.generate_fn_merge_impl(),
+            self.generate_fn_iter_impl(),
+            self.generate_fn_iter_mut_impl(),
+            self.generate_fn_as_slice_impl(),
+            self.generate_end_impl("first", Mutability::Immutable),
+            self.generate_end_impl("first_mut", Mutability::Mutable),
+            self.generate_end_impl("last", Mutability::Immutable),
+            self.generate_end_impl("last_mut", Mutability::Mutable),
+            self.generate_fn_iter_enumerated_impl(),
+            self.generate_fn_iter_enumerated_mut_impl(),
+            self.generate_fn_iter_ordered_impl(),
+            self.generate_fn_iter_ordered_mut_impl(),
+            self.generate_fn_remove_impl(),
+            self.generate_fn_replace_impl(),
+            self.generate_fn_rebuild_indices_impl(),
+            self.generate_fn_get_impl(),
+            self.generate_fn_get_mut_impl(),
+            self.generate_fn_reserve_impl(),
+            self.generate_fn_capacity_impl(),
+            self.generate_fn_shrink_to_fit_impl(),
+            self.generate_fn_count_where_impl(),
+            self.generate_fn_handlers_of_impl(),
+            self.generate_fn_dump_membership_impl(),
+            self.generate_fn_membership_impl(),
+            self.generate_fn_handler_counts_impl(),
+            self.generate_fn_same_membership_impl(),
+            self.generate_fn_checkpoint_impl(),
+            self.generate_fn_restore_impl(),
+        ];
+
+        if self.downcast && self.storage == Storage::Box {
+            fns.push(self.generate_get_as_impl("get_as", "get", "as_any", "downcast_ref", Mutability::Immutable));
+            fns.push(self.generate_get_as_impl("get_as_mut", "get_mut", "as_any_mut", "downcast_mut", Mutability::Mutable));
+        }
+
+        if self.priority {
+            fns.push(self.generate_fn_add_default_priority_impl());
+        }
+
+        // Sorted storage keeps its own order
+        if let Some((key_fn, ref key_ty)) = self.sort_key {
+            fns.push(self.generate_fn_find_impl(key_fn, key_ty.clone()));
+        } else {
+            fns.push(self.generate_fn_sort_by_impl());
+        }
+
+        // A system without handlers has nothing to preallocate
+        if !self.handlers.is_empty() {
+            fns.push(self.generate_fn_with_handler_capacities_impl());
+        }
+
+        if self.commands {
+            fns.push(self.generate_fn_apply_commands_impl());
+        }
+
+        if self.keeps_rejected() {
+            fns.push(self.generate_fn_take_rejected_impl());
+        }
+
+        if self.debug_checks {
+            fns.extend(self.generate_fn_check_bookkeeping_impls());
+            fns.extend(self.generate_fn_check_not_dispatching_impls());
+            fns.push(self.generate_fn_is_dispatching_impl());
+        }
+
+        if let Some(cleanup_fn) = self.cleanup_fn {
+            fns.push(self.generate_fn_cleanup_impl(cleanup_fn));
+        }
+
+        if self.uses_handler_mask() {
+            fns.push(self.generate_fn_handler_mask_of_impl());
+            fns.push(self.generate_fn_handles_matching_impl());
+        }
+
+        for (field, ty) in self.accumulators() {
+            fns.extend(self.generate_accumulator_impls(field, ty));
+        }
+
+        if let Some(id_fn) = self.id_fn {
+            fns.push(self.generate_find_by_id_impl(id_fn, "find_by_id", "iter", Mutability::Immutable));
+            fns.push(self.generate_find_by_id_impl(id_fn, "find_by_id_mut", "iter_mut", Mutability::Mutable));
+            fns.push(self.generate_position_of_impl(id_fn));
+        }
+
+        for handler in self.handlers.iter() {
+            if handler.exclusive {
+                fns.push(handler.generate_the_self(self));
+                fns.push(handler.generate_the_self_mut(self));
+            }
+
+            // Shared storage can only hand out guards, not plain references
+            if self.storage == Storage::Box {
+                fns.push(handler.generate_as_self_at(self));
+                fns.push(handler.generate_as_self_mut_at(self));
+                fns.push(handler.generate_refs(self));
+                fns.push(handler.generate_iter(self));
+            }
+
+            fns.push(handler.generate_set_enabled());
+            handler.generate_signal_impl(self, &mut fns);
+        }
+
+        for handlers in self.generated_combinations() {
+            fns.push(self.generate_fn_combined_iter_impl(&handlers));
+        }
+
+        for batch in self.batches.iter().chain(self.phases.iter()) {
+            fns.push(self.generate_batch_impl(batch));
+        }
+
+        if !self.phases.is_empty() {
+            fns.push(self.generate_batch_impl(&self.phases_batch()));
+        }
+
+        util::create_impl(
+            self.name,
+            None,
+            fns
+        )
+    }
+
+    pub fn find_handler(&self, name: &str) -> Option<&HandlerInfo> {
+        self.handlers.iter().find(|handler| format!("{}", handler.name) == name)
+    }
+
+    pub fn generate_object_impl(&self, thing: Ident, impls: &Vec<String>, forwards: &Vec<(String, Ident)>) -> Box<MacResult> {
+        MacEager::items(SmallVector::many(self.generate_object_items(thing, impls, forwards)))
+    }
+
+    fn generate_forward_impl(&self, thing: Ident, handler: &HandlerInfo, field: Ident) -> Item {
+        util::create_path_impl(
+            thing,
+            Some(self.qualified_path(handler.trait_name(self))),
+            handler.fns.iter().map(|function| function.generate_forward(self, field)).collect()
+        )
+    }
+
+    pub fn generate_object_items(&self, thing: Ident, impls: &Vec<String>, forwards: &Vec<(String, Ident)>) -> Vec<P<Item>> {
+        let mut items = Vec::new();
+
+        for handler in self.handlers.iter() {
+            items.extend_from_slice(&[
+                util::impl_method_priv(
+                    util::as_ident(handler.name),
+                    Vec::new(),
+                    Some(P(util::param_ty_from_ident(
+                        str_to_ident("Option"),
+                        util::ref_ty(P(self.qualified_ty(handler.trait_name(self))))
+                    ))),
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(if impls.contains(&format!("{}", handler.name)) {
+                            util::create_call(
+                                P(util::create_var_expr(str_to_ident("Some"))),
+                                vec![P(util::create_cast_expr(
+                                        P(util::create_var_expr(str_to_ident("self"))),
+                                        P(util::ref_ty(P(self.qualified_ty(handler.trait_name(self)))))
+                                ))]
+                            )
+                        } else {
+                            util::create_var_expr(str_to_ident("None"))
+                        })),
+                    ))
+                ),
+
+                util::impl_mut_method_priv(
+                    util::as_mut_ident(handler.name),
+                    Vec::new(),
+                    Some(P(util::param_ty_from_ident(
+                        str_to_ident("Option"),
+                        util::mut_ref_ty(P(self.qualified_ty(handler.trait_name(self))))
+                    ))),
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(if impls.contains(&format!("{}", handler.name)) {
+                            util::create_call(
+                                P(util::create_var_expr(str_to_ident("Some"))),
+                                vec![P(util::create_cast_expr(
+                                        P(util::create_var_expr(str_to_ident("self"))),
+                                        P(util::mut_ref_ty(P(self.qualified_ty(handler.trait_name(self)))))
+                                ))]
+                            )
+                        } else {
+                            util::create_var_expr(str_to_ident("None"))
+                        })),
+                    ))
+                )
+            ]);
+
+            if handler.is_consumable() {
+                items.push(util::impl_box_method_priv(
+                    util::into_ident(handler.name),
+                    P(self.box_self_ty()),
+                    Vec::new(),
+                    Some(P(handler.into_self_ty(self, true))),
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(if impls.contains(&format!("{}", handler.name)) {
+                            util::create_call(
+                                P(util::create_var_expr(str_to_ident("Ok"))),
+                                vec![P(util::create_cast_expr(
+                                        P(util::create_var_expr(str_to_ident("self"))),
+                                        P(self.box_ty(self.qualified_ty(handler.trait_name(self))))
+                                ))]
+                            )
+                        } else {
+                            util::create_call(
+                                P(util::create_var_expr(str_to_ident("Err"))),
+                                vec![P(util::create_cast_expr(
+                                        P(util::create_var_expr(str_to_ident("self"))),
+                                        P(self.box_ty(self.qualified_ty(self.object_name())))
+                                ))]
+                            )
+                        }))
+                    ))
+                ));
+            }
+        }
+
+        if self.uses_handler_mask() {
+            let mask = self.handlers.iter().enumerate()
+                .filter(|&(_, handler)| impls.contains(&format!("{}", handler.name)))
+                .fold(0, |mask, (i, _)| mask | (1 << i));
+
+            items.push(util::impl_method_priv(
+                str_to_ident("handler_mask"),
+                Vec::new(),
+                Some(P(util::ty_from_ident(str_to_ident("u64")))),
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_num_expr(mask)))
+                ))
+            ));
+        }
+
+        // "Thing"
+        items.push(util::impl_method_priv(
+            str_to_ident("type_name"),
+            Vec::new(),
+            Some(P(util::static_ref_ty(P(util::ty_from_ident(str_to_ident("str")))))),
+            P(util::create_block(
+                Vec::new(),
+                Some(P(util::create_str_expr(thing.name.as_str())))
+            ))
+        ));
+
+        if self.downcast {
+            // self as &::std::any::Any
+            items.push(util::impl_method_priv(
+                str_to_ident("as_any"),
+                Vec::new(),
+                Some(P(self.any_ty(Mutability::Immutable))),
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_cast_expr(
+                        P(util::create_var_expr(str_to_ident("self"))),
+                        P(self.any_ty(Mutability::Immutable))
+                    )))
+                ))
+            ));
+
+            // self as &mut ::std::any::Any
+            items.push(util::impl_mut_method_priv(
+                str_to_ident("as_any_mut"),
+                Vec::new(),
+                Some(P(self.any_ty(Mutability::Mutable))),
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_cast_expr(
+                        P(util::create_var_expr(str_to_ident("self"))),
+                        P(self.any_ty(Mutability::Mutable))
+                    )))
+                ))
+            ));
+        }
+
+        let mut result = vec![P(util::create_path_impl(
+            thing,
+            Some(self.qualified_path(self.object_name())),
+            items
+        ))];
+
+        for &(ref name, field) in forwards.iter() {
+            if let Some(handler) = self.find_handler(name) {
+                result.push(P(self.generate_forward_impl(thing, handler, field)));
+            }
+        }
+
+        result
+    }
+
+    pub fn generate_ast(&self) -> Box<MacResult> {
+        MacEager::items(SmallVector::many(self.generate_items()))
+    }
+
+    pub fn generate_items(&self) -> Vec<P<Item>> {
+        let mut items: Vec<P<Item>> = self.handlers.iter().map(|handler| P(handler.generate(self))).collect();
+        items.extend_from_slice(&[
+            P(self.generate_requirement_check()),
+            P(self.generate_object_trait()),
+            P(self.generate_idx_struct()),
+            P(self.generate_snapshot_struct()),
+            P(self.generate_num_handlers_const()),
+            P(self.generate_struct()),
+            P(self.generate_impl())
+        ]);
+
+        items.extend(self.generate_index_impls().into_iter().map(P));
+
+        if let Some(clone_fn) = self.clone_fn {
+            items.push(P(self.generate_clone_impl(clone_fn)));
+        }
+
+        if self.from_object {
+            items.push(P(self.generate_from_object_impl()));
+        }
+
+        if self.storage == Storage::Box {
+            for handler in self.handlers.iter() {
+                items.push(P(handler.generate_iter_struct(self)));
+                items.push(P(handler.generate_iter_impl(self)));
+            }
+        }
+
+        for handlers in self.generated_combinations() {
+            items.push(P(self.generate_combined_iter_struct(&handlers)));
+            items.push(P(self.generate_combined_iter_impl(&handlers)));
+        }
+
+        if self.dispatch_trait {
+            items.push(P(self.generate_dispatch_trait()));
+            items.push(P(self.generate_dispatch_impl()));
+        }
+
+        if self.signals_trait {
+            items.push(P(self.generate_signals_trait()));
+            items.push(P(self.generate_signals_impl()));
+        }
+
+        if self.builder {
+            items.push(P(self.generate_builder_struct()));
+            items.push(P(self.generate_builder_impl()));
+        }
+
+        if self.uses_handler_mask() {
+            items.extend(self.generate_mask_consts().into_iter().map(P));
+        }
+
+        if self.commands {
+            items.push(P(self.generate_command_enum()));
+        }
+
+        if self.module {
+            let mut mod_items = vec![P(util::create_use_glob_item(vec![str_to_ident("super")], false))];
+            mod_items.extend(items);
+
+            items = vec![P(util::create_mod(self.module_name(), mod_items))];
+
+            let mut names = vec![self.name, self.idx_name(), self.snapshot_name(), self.object_name(), self.num_handlers_name()];
+            names.extend(self.iter_names());
+            if self.commands {
+                names.push(self.command_name());
+            }
+
+            if self.dispatch_trait {
+                names.push(self.dispatch_name());
+            }
+
+            if self.signals_trait {
+                names.push(self.signals_name());
+            }
+
+            if self.builder {
+                names.push(self.builder_name());
+            }
+
+            if self.uses_handler_mask() {
+                names.extend(self.generate_mask_consts().iter().map(|item| item.ident));
+            }
+
+            for name in names {
+                items.push(P(util::create_pub_use_item(vec![str_to_ident("self"), self.module_name(), name])));
+            }
+        }
+
+        items.push(P(self.generate_prelude()));
+        items
+    }
+
+    fn generate_prelude(&self) -> Item {
+        // Everything an object needs in scope to implement its handlers
+        let mut names = vec![self.object_name()];
+        names.extend(self.handlers.iter().map(|handler| handler.trait_name(self)));
+        if self.commands {
+            names.push(self.command_name());
+        }
+
+        if self.dispatch_trait {
+            names.push(self.dispatch_name());
+        }
+
+        // pub mod system_prelude { pub use super::Name; .. }
+        util::create_mod(
+            util::ident_append(self.module_name(), str_to_ident("_prelude")),
+            names.into_iter().map(|name| {
+                let mut path = vec![str_to_ident("super")];
+                if self.module {
+                    path.push(self.module_name());
+                }
+
+                path.push(name);
+                P(util::create_pub_use_item(path))
+            }).collect()
+        )
+    }
+}
+
+impl HandlerInfo {
+    pub fn new(name: Ident) -> HandlerInfo {
+        HandlerInfo {
+            name: name,
+            reqs: Vec::new(),
+            req_spans: Vec::new(),
+            lifetime_reqs: Vec::new(),
+            fns: Vec::new(),
+            exclusive: false,
+            unsafety: Unsafety::Normal,
+            order_by: None,
+            attrs: Vec::new()
+        }
+    }
+    
+    pub fn add_requirement(&mut self, req: Ident, span: Span) {
+        self.reqs.push(req);
+        self.req_spans.push(span);
+    }
+
+    pub fn add_lifetime_requirement(&mut self, lifetime: Lifetime) {
+        self.lifetime_reqs.push(lifetime);
+    }
+
+    pub fn add_function(&mut self, function: HandlerFnInfo) {
+        self.fns.push(function);
+    }
+
+    fn all_reqs(&self, system: &SystemInfo) -> Vec<Ident> {
+        // Handlers may repeat a system-wide bound without it being applied twice
+        let mut reqs = system.handler_reqs.clone();
+        reqs.extend(self.reqs.iter().filter(|req| !system.handler_reqs.iter().any(|other| other.name == req.name)));
+        reqs
+    }
+
+    fn all_lifetime_reqs(&self, system: &SystemInfo) -> Vec<Lifetime> {
+        let mut lifetimes = system.handler_lifetime_reqs.clone();
+        lifetimes.extend(self.lifetime_reqs.iter().filter(|lifetime| !system.handler_lifetime_reqs.iter().any(|other| other.name == lifetime.name)));
+        lifetimes
+    }
+
+    pub fn is_consumable(&self) -> bool {
+        self.fns.iter().any(|function| function.consuming)
+    }
+
+    pub fn trait_name(&self, system: &SystemInfo) -> Ident {
+        if system.prefix_handlers {
+            util::ident_append(system.name, self.name)
+        } else {
+            self.name
+        }
+    }
+
+    fn into_self_ty(&self, system: &SystemInfo, qualified: bool) -> Ty {
+        let (handler_ty, object_ty) = if qualified {
+            (system.qualified_ty(self.trait_name(system)), system.qualified_ty(system.object_name()))
+        } else {
+            (util::ty_from_ident(self.trait_name(system)), util::ty_from_ident(system.object_name()))
+        };
+
+        util::params_ty_from_ident(
+            str_to_ident("Result"),
+            vec![
+                system.box_ty(handler_ty),
+                system.box_ty(object_ty)
+            ]
+        )
+    }
+
+    pub fn generate_into_self(&self, system: &SystemInfo) -> TraitItem {
+        util::create_box_trait_method(
+            util::into_ident(self.name),
+            P(system.box_self_ty()),
+            Vec::new(),
+            Some(P(self.into_self_ty(system, false)))
+        )
+    }
+
+    pub fn generate_as_self(&self, system: &SystemInfo) -> TraitItem {
+        util::create_trait_method(
+            util::as_ident(self.name),
+            Vec::new(),
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::ref_ty_from_ident(self.trait_name(system))
+            )))
+        )
+    }
+
+    pub fn generate_as_self_mut(&self, system: &SystemInfo) -> TraitItem {
+        util::create_mut_trait_method(
+            util::as_mut_ident(self.name),
+            Vec::new(),
+            Some(P(util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::mut_ref_ty_from_ident(self.trait_name(system))
+            )))
+        )
+    }
+
+    pub fn generate(&self, system: &SystemInfo) -> Item {
+        let mut item = util::create_unsafety_trait(
+            self.unsafety,
+            self.trait_name(system),
+            &self.all_reqs(system),
+            &self.all_lifetime_reqs(system),
+            &self.fns.iter().map(|function| function.generate(system)).collect()
+        );
+
+        // cfg attributes have already been evaluated when the handler was parsed
+        item.attrs = self.attrs.iter().filter(|attr| !attr.check_name("cfg")).cloned().collect();
+
+        item
+    }
+
+    fn generate_the_self_impl(&self, name: Ident, as_name: Ident, get: &str, ret: Ty) -> ImplItem {
+        let none = || Some(P(util::create_block_expr(P(util::create_block(
+            Vec::new(),
+            Some(P(util::create_var_expr(str_to_ident("None"))))
+        )))));
+
+        // The handle may belong to an object that has since been removed
+        // if let Some(obj_idx) = *self.idxs.get_unchecked(idx) { self.objects.get_unchecked(obj_idx).as_handler() } else { None }
+        let if_block = util::create_block(
+            Vec::new(),
+            Some(P(util::create_if_let_expr(
+                P(util::create_tuple_struct_pat(
+                    str_to_ident("Some"),
+                    vec![str_to_ident("obj_idx")]
+                )),
+                P(util::create_deref_expr(P(util::create_method_call(
+                    str_to_ident("get_unchecked"),
+                    P(util::create_self_field_expr(util::field_ident("idxs"))),
+                    vec![P(util::create_var_expr(str_to_ident("idx")))]
+                )))),
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_method_call(
+                        as_name,
+                        P(util::create_method_call(
+                            str_to_ident(get),
+                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                            vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
+                        )),
+                        Vec::new()
+                    )))
+                )),
+                none()
+            )))
+        );
+
+        // if let Some(idx) = self.handler_idxs.first().cloned() { .. } else { None }
+        let body = P(util::create_unsafe_block(
+            Vec::new(),
+            Some(P(util::create_if_let_expr(
+                P(util::create_tuple_struct_pat(
+                    str_to_ident("Some"),
+                    vec![str_to_ident("idx")]
+                )),
+                P(util::create_method_call(
+                    str_to_ident("cloned"),
+                    P(util::create_method_call(
+                        str_to_ident("first"),
+                        P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                        Vec::new()
+                    )),
+                    Vec::new()
+                )),
+                P(if_block),
+                none()
+            )))
+        ));
+
+        if get == "get_unchecked_mut" {
+            util::impl_mut_method(name, Vec::new(), Some(P(ret)), body)
+        } else {
+            util::impl_method(name, Vec::new(), Some(P(ret)), body)
+        }
+    }
+
+    fn generate_as_self_at_impl(&self, system: &SystemInfo, name: Ident, as_name: Ident, get: &str, ret: Ty) -> ImplItem {
+        // self.get(idx).and_then(|object| object.as_handler())
+        let body = P(util::create_block(
+            Vec::new(),
+            Some(P(util::create_method_call(
+                str_to_ident("and_then"),
+                P(util::create_method_call(
+                    str_to_ident(get),
+                    P(util::create_var_expr(str_to_ident("self"))),
+                    vec![P(util::create_var_expr(str_to_ident("idx")))]
+                )),
+                vec![P(util::create_closure_expr(
+                    vec![util::create_closure_arg(str_to_ident("object"))],
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_method_call(
+                            as_name,
+                            P(util::create_var_expr(str_to_ident("object"))),
+                            Vec::new()
+                        )))
+                    ))
+                ))]
+            )))
+        ));
+
+        let args = vec![util::create_arg(str_to_ident("idx"), P(util::ty_from_ident(system.idx_name())))];
+
+        if get == "get_mut" {
+            util::impl_mut_method(name, args, Some(P(ret)), body)
+        } else {
+            util::impl_method(name, args, Some(P(ret)), body)
+        }
+    }
+
+    pub fn generate_as_self_at(&self, system: &SystemInfo) -> ImplItem {
+        self.generate_as_self_at_impl(
+            system,
+            util::ident_append(util::as_ident(self.name), str_to_ident("_at")),
+            util::as_ident(self.name),
+            "get",
+            util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::ref_ty_from_ident(self.trait_name(system))
+            )
+        )
+    }
+
+    pub fn generate_as_self_mut_at(&self, system: &SystemInfo) -> ImplItem {
+        self.generate_as_self_at_impl(
+            system,
+            util::ident_append(util::as_mut_ident(self.name), str_to_ident("_at")),
+            util::as_mut_ident(self.name),
+            "get_mut",
+            util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::mut_ref_ty_from_ident(self.trait_name(system))
+            )
+        )
+    }
+
+    pub fn generate_refs(&self, system: &SystemInfo) -> ImplItem {
+        // Walks every object rather than the idxs, so it does not depend on the handler's bookkeeping
+        let stmts = vec![
+            // let mut refs = Vec::new();
+            util::create_let_mut_stmt(
+                str_to_ident("refs"),
+                Some(P(system.vec_new()))
+            ),
+
+            // refs.extend(self.objects.iter().filter_map(|object| object.as_handler()));
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("extend"),
+                P(util::create_var_expr(str_to_ident("refs"))),
+                vec![P(util::create_method_call(
+                    str_to_ident("filter_map"),
+                    P(util::create_method_call(
+                        str_to_ident("iter"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
+                        Vec::new()
                     )),
                     vec![P(util::create_closure_expr(
-                        vec![util::create_arg(
-                            str_to_ident("obj_idx"),
-                            P(util::param_ty_from_ident(
-                                str_to_ident("Option"),
-                                util::ty_from_ident(str_to_ident("usize"))
-                            ))
-                        )],
+                        vec![util::create_closure_arg(str_to_ident("object"))],
                         P(util::create_block(
                             Vec::new(),
                             Some(P(util::create_method_call(
-                                str_to_ident("map"),
-                                P(util::create_var_expr(str_to_ident("obj_idx"))),
-                                vec![P(util::create_closure_expr(
-                                    vec![util::create_arg(
-                                        str_to_ident("obj_idx"),
-                                        P(util::ty_from_ident(str_to_ident("usize")))
-                                    )],
-                                    P(util::create_unsafe_block(
-                                        vec![
-                                            util::create_let_stmt(
-                                                str_to_ident("obj"),
-                                                Some(P(util::create_method_call(
-                                                    str_to_ident("swap_remove"),
-                                                    P(util::create_self_field_expr(str_to_ident("objects"))),
-                                                    vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
-                                                )))
-                                            ),
-                                            util::create_stmt(P(util::create_assign_expr(
-                                                P(util::create_deref_expr(P(util::create_method_call(
-                                                    str_to_ident("unwrap"),
-                                                    P(util::create_method_call(
-                                                        str_to_ident("last_mut"),
-                                                        P(util::create_self_field_expr(str_to_ident("idxs"))),
-                                                        Vec::new()
-                                                    )),
-                                                    Vec::new()
-                                                )))),
-                                                P(util::create_call(
-                                                    P(util::create_var_expr(str_to_ident("Some"))),
-                                                    vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
-                                                ))
-                                            ))),
-                                            util::create_stmt(P(util::create_assign_expr(
-                                                P(util::create_deref_expr(P(util::create_method_call(
-                                                    str_to_ident("get_unchecked_mut"),
-                                                    P(util::create_self_field_expr(str_to_ident("idxs"))),
-                                                    vec![P(util::create_tuple_field_expr(
-                                                        P(util::create_var_expr(str_to_ident("idx"))),
-                                                        0
-                                                    ))]
-                                                )))),
-                                                P(util::create_var_expr(str_to_ident("None")))
-                                            )))
-                                        ],
-                                        Some(P(util::create_var_expr(str_to_ident("obj"))))
-                                    ))
-                                ))]
+                                util::as_ident(self.name),
+                                P(util::create_var_expr(str_to_ident("object"))),
+                                Vec::new()
                             )))
                         ))
                     ))]
+                ))]
+            )))
+        ];
+
+        // ::std::vec::IntoIter<&Handler>
+        util::impl_method(
+            util::refs_ident(self.name),
+            Vec::new(),
+            Some(P(util::global_path_param_ty(
+                system.std_path(&["vec", "IntoIter"]),
+                util::ref_ty_from_ident(self.trait_name(system))
+            ))),
+            P(util::create_block(
+                stmts,
+                Some(P(util::create_method_call(
+                    str_to_ident("into_iter"),
+                    P(util::create_var_expr(str_to_ident("refs"))),
+                    Vec::new()
                 )))
             ))
         )
     }
 
-    fn generate_fn_get_impl(&self) -> ImplItem {
+    pub fn iter_name(&self, system: &SystemInfo) -> Ident {
+        util::ident_append(self.trait_name(system), str_to_ident("Iter"))
+    }
+
+    pub fn generate_iter_struct(&self, system: &SystemInfo) -> Item {
+        let lifetime = util::create_lifetime("'a");
+
+        // pub struct HandlerIter<'a> { .. }
+        util::create_generic_struct(
+            self.iter_name(system),
+            util::create_lifetime_generics(&vec![lifetime]),
+            vec![
+                // objects: &'a [Box<SystemObject>]
+                util::create_struct_field(
+                    str_to_ident("objects"),
+                    P(util::lifetime_ref_ty(lifetime, P(util::slice_ty(P(system.object_ty())))))
+                ),
+
+                // idxs: &'a [Option<usize>]
+                util::create_struct_field(
+                    str_to_ident("idxs"),
+                    P(util::lifetime_ref_ty(lifetime, P(util::slice_ty(P(util::param_ty_from_ident(
+                        str_to_ident("Option"),
+                        util::ty_from_ident(str_to_ident("usize"))
+                    ))))))
+                ),
+
+                // handles: ::std::slice::Iter<'a, usize>
+                util::create_struct_field(
+                    str_to_ident("handles"),
+                    P(util::global_path_lifetime_params_ty(
+                        system.std_path(&["slice", "Iter"]),
+                        vec![lifetime],
+                        vec![util::ty_from_ident(str_to_ident("usize"))]
+                    ))
+                )
+            ]
+        )
+    }
+
+    pub fn generate_iter_impl(&self, system: &SystemInfo) -> Item {
+        let lifetime = util::create_lifetime("'a");
+        let item_ty = util::lifetime_ref_ty(lifetime, P(util::ty_from_ident(self.trait_name(system))));
+
+        // Handles that have gone stale are skipped, as the iterator cannot drop them from the system
+        let stmts = vec![
+            // for handle in self.handles.by_ref() { .. }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("handle"),
+                P(util::create_method_call(
+                    str_to_ident("by_ref"),
+                    P(util::create_self_field_expr(str_to_ident("handles"))),
+                    Vec::new()
+                )),
+                P(util::create_block(
+                    vec![
+                        // if let Some(idx) = self.idxs[*handle] { return self.objects[idx].as_handler(); }
+                        util::create_stmt(P(util::create_if_let_expr(
+                            P(util::create_tuple_struct_pat(
+                                str_to_ident("Some"),
+                                vec![str_to_ident("idx")]
+                            )),
+                            P(util::create_idx_expr(
+                                P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("handle"))))),
+                                P(util::create_self_field_expr(str_to_ident("idxs")))
+                            )),
+                            P(util::create_return_block(Some(P(util::create_method_call(
+                                util::as_ident(self.name),
+                                P(util::create_idx_expr(
+                                    P(util::create_var_expr(str_to_ident("idx"))),
+                                    P(util::create_self_field_expr(str_to_ident("objects")))
+                                )),
+                                Vec::new()
+                            ))))),
+                            None
+                        )))
+                    ],
+                    None
+                ))
+            )))
+        ];
+
+        // impl<'a> Iterator for HandlerIter<'a> { .. }
+        util::create_generic_param_path_impl(
+            self.iter_name(system),
+            util::create_lifetime_generics(&vec![lifetime]),
+            util::lifetime_ty_from_ident(self.iter_name(system), lifetime),
+            system.std_path(&["iter", "Iterator"]),
+            Vec::new(),
+            vec![
+                util::impl_type(str_to_ident("Item"), P(item_ty.clone())),
+                util::impl_mut_method_priv(
+                    str_to_ident("next"),
+                    Vec::new(),
+                    Some(P(util::param_ty_from_ident(str_to_ident("Option"), item_ty))),
+                    P(util::create_block(stmts, Some(P(util::create_var_expr(str_to_ident("None"))))))
+                )
+            ]
+        )
+    }
+
+    pub fn generate_iter(&self, system: &SystemInfo) -> ImplItem {
+        // HandlerIter { objects: &self.objects, idxs: &self.idxs, handles: self.handler_idxs.iter() }
         util::impl_method(
-            str_to_ident("get"),
-            vec![util::create_arg(
-                str_to_ident("idx"),
-                P(util::ty_from_ident(self.idx_name()))
-            )],
-            Some(P(util::param_ty_from_ident(
-                str_to_ident("Option"),
-                util::ref_ty(P(util::param_ty_from_ident(
-                    str_to_ident("Box"),
-                    util::ty_from_ident(self.object_name())
-                )))
-            ))),
+            util::iter_ident(self.name),
+            Vec::new(),
+            Some(P(util::ty_from_ident(self.iter_name(system)))),
             P(util::create_block(
                 Vec::new(),
-                Some(P(util::create_method_call(
-                    str_to_ident("and_then"),
+                Some(P(util::create_struct_expr(self.iter_name(system), vec![
+                    util::create_field(str_to_ident("objects"), P(util::create_addr_of_expr(
+                        Mutability::Immutable,
+                        P(util::create_self_field_expr(util::field_ident("objects")))
+                    ))),
+                    util::create_field(str_to_ident("idxs"), P(util::create_addr_of_expr(
+                        Mutability::Immutable,
+                        P(util::create_self_field_expr(util::field_ident("idxs")))
+                    ))),
+                    util::create_field(str_to_ident("handles"), P(util::create_method_call(
+                        str_to_ident("iter"),
+                        P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                        Vec::new()
+                    )))
+                ])))
+            ))
+        )
+    }
+
+    pub fn generate_the_self(&self, system: &SystemInfo) -> ImplItem {
+        self.generate_the_self_impl(
+            util::the_ident(self.name),
+            util::as_ident(self.name),
+            "get_unchecked",
+            util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::ref_ty_from_ident(self.trait_name(system))
+            )
+        )
+    }
+
+    pub fn generate_the_self_mut(&self, system: &SystemInfo) -> ImplItem {
+        self.generate_the_self_impl(
+            util::the_mut_ident(self.name),
+            util::as_mut_ident(self.name),
+            "get_unchecked_mut",
+            util::param_ty_from_ident(
+                str_to_ident("Option"),
+                util::mut_ref_ty_from_ident(self.trait_name(system))
+            )
+        )
+    }
+
+    fn generate_parallel_signal(&self, system: &SystemInfo, func: &HandlerFnInfo) -> ImplItem {
+        let enabled_check = self.generate_enabled_check(self.count_disabled(system, func));
+
+        let stmts = vec![
+            // use ::rayon::prelude::*;
+            util::create_use_glob_stmt(vec![str_to_ident("rayon"), str_to_ident("prelude")]),
+
+            // self.objects.par_iter_mut().filter_map(|object| object.as_handler_mut()).for_each(|object| ..);
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("for_each"),
+                P(util::create_method_call(
+                    str_to_ident("filter_map"),
                     P(util::create_method_call(
-                        str_to_ident("cloned"),
-                        P(util::create_method_call(
-                            str_to_ident("get"),
-                            P(util::create_self_field_expr(str_to_ident("idxs"))),
-                            vec![P(util::create_tuple_field_expr(
-                                P(util::create_var_expr(str_to_ident("idx"))),
-                                0
-                            ))],
-                        )),
+                        str_to_ident("par_iter_mut"),
+                        P(util::create_self_field_expr(util::field_ident("objects"))),
                         Vec::new()
                     )),
                     vec![P(util::create_closure_expr(
-                        vec![util::create_arg(
-                            str_to_ident("obj_idx"),
-                            P(util::param_ty_from_ident(
-                                str_to_ident("Option"),
-                                util::ty_from_ident(str_to_ident("usize"))
-                            ))
-                        )],
+                        vec![util::create_closure_arg(str_to_ident("object"))],
                         P(util::create_block(
                             Vec::new(),
                             Some(P(util::create_method_call(
-                                str_to_ident("map"),
-                                P(util::create_var_expr(str_to_ident("obj_idx"))),
-                                vec![P(util::create_closure_expr(
-                                    vec![util::create_arg(
-                                        str_to_ident("obj_idx"),
-                                        P(util::ty_from_ident(str_to_ident("usize")))
-                                    )],
-                                    P(util::create_unsafe_block(
-                                        Vec::new(),
-                                        Some(P(util::create_method_call(
-                                            str_to_ident("get_unchecked"),
-                                            P(util::create_self_field_expr(str_to_ident("objects"))),
-                                            vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
-                                        )))
-                                    ))
-                                ))]
+                                util::as_mut_ident(self.name),
+                                P(util::create_var_expr(str_to_ident("object"))),
+                                Vec::new()
                             )))
                         ))
                     ))]
+                )),
+                vec![P(util::create_closure_expr(
+                    vec![util::create_closure_arg(str_to_ident("object"))],
+                    P(util::create_block(
+                        Vec::new(),
+                        Some(P(util::create_method_call(
+                            func.dest_name,
+                            P(util::create_var_expr(str_to_ident("object"))),
+                            system.dispatch_args(func)
+                        )))
+                    ))
+                ))]
+            )))
+        ];
+
+        // The parallel walk goes over the objects, so removed handles are still in the idxs
+        let result = if self.is_counted(system, func) {
+            Some(P(self.live_count(P(util::create_self_field_expr(util::idxs_ident(self.name))))))
+        } else {
+            None
+        };
+
+        let (stmts, result) = system.wrap_dispatching(enabled_check, stmts, result, false);
+
+        util::impl_mut_method(
+            func.source_name,
+            func.args.iter().map(|arg| arg.generate()).collect(),
+            self.count_ret_ty(system, func),
+            P(util::create_block(stmts, result))
+        )
+    }
+
+    fn generate_consuming_signal(&self, system: &SystemInfo, func: &HandlerFnInfo) -> ImplItem {
+        let loop_block = util::create_block(
+            vec![
+                // if let Some(object) = self.remove(SystemIndex(idx)) { .. }
+                util::create_stmt(P(util::create_if_let_expr(
+                    P(util::create_tuple_struct_pat(
+                        str_to_ident("Some"),
+                        vec![str_to_ident("object")]
+                    )),
+                    P(util::create_method_call(
+                        str_to_ident("remove"),
+                        P(util::create_var_expr(str_to_ident("self"))),
+                        vec![P(util::create_call(
+                            P(util::create_var_expr(system.idx_name())),
+                            vec![P(util::create_var_expr(str_to_ident("idx")))]
+                        ))]
+                    )),
+                    P(util::create_block(
+                        vec![
+                            // if let Ok(object) = object.into_handler() { object.slot(..); }
+                            util::create_stmt(P(util::create_if_let_expr(
+                                P(util::create_tuple_struct_pat(
+                                    str_to_ident("Ok"),
+                                    vec![str_to_ident("object")]
+                                )),
+                                P(util::create_method_call(
+                                    util::into_ident(self.name),
+                                    P(util::create_var_expr(str_to_ident("object"))),
+                                    Vec::new()
+                                )),
+                                P(util::create_block(
+                                    vec![util::create_stmt(P(util::create_method_call(
+                                        func.dest_name,
+                                        P(util::create_var_expr(str_to_ident("object"))),
+                                        system.dispatch_args(func)
+                                    )))],
+                                    None
+                                )),
+                                None
+                            )))
+                        ],
+                        None
+                    )),
+                    None
+                )))
+            ],
+            None
+        );
+
+        let enabled_check = self.generate_enabled_check(self.count_disabled(system, func));
+
+        let stmts = vec![
+            // let idxs = ::std::mem::replace(&mut self.handler_idxs, Vec::new());
+            util::create_let_stmt(
+                str_to_ident("idxs"),
+                Some(P(util::create_call(
+                    P(util::create_path_expr(system.std_path(&["mem", "replace"]))),
+                    vec![
+                        P(util::create_addr_of_expr(
+                            Mutability::Mutable,
+                            P(util::create_self_field_expr(util::idxs_ident(self.name)))
+                        )),
+                        P(system.vec_new())
+                    ]
+                )))
+            ),
+
+            // let count = idxs.iter().filter(..).count();
+            util::create_let_stmt(
+                str_to_ident("count"),
+                Some(P(self.live_count(P(util::create_var_expr(str_to_ident("idxs"))))))
+            ),
+
+            // for idx in idxs { .. }
+            util::create_stmt(P(util::create_for_expr(
+                str_to_ident("idx"),
+                P(util::create_var_expr(str_to_ident("idxs"))),
+                P(loop_block)
+            )))
+        ];
+
+        let result = if self.is_counted(system, func) {
+            Some(P(util::create_var_expr(str_to_ident("count"))))
+        } else {
+            None
+        };
+
+        let (stmts, result) = system.wrap_commands(stmts, result);
+        let (stmts, result) = system.wrap_dispatching(enabled_check, stmts, result, false);
+
+        util::impl_mut_method(
+            func.source_name,
+            func.args.iter().map(|arg| arg.generate()).collect(),
+            self.count_ret_ty(system, func),
+            P(util::create_block(stmts, result))
+        )
+    }
+
+    fn is_counted(&self, system: &SystemInfo, func: &HandlerFnInfo) -> bool {
+        system.counted && (func.ret.is_none() || func.accumulate.is_some())
+    }
+
+    fn count_ret_ty(&self, system: &SystemInfo, func: &HandlerFnInfo) -> Option<P<Ty>> {
+        if self.is_counted(system, func) {
+            Some(P(util::ty_from_ident(str_to_ident("usize"))))
+        } else {
+            None
+        }
+    }
+
+    fn count_result(&self, system: &SystemInfo, func: &HandlerFnInfo, idxs: P<Expr>) -> Option<P<Expr>> {
+        if self.is_counted(system, func) {
+            // idxs.len()
+            Some(P(util::create_method_call(str_to_ident("len"), idxs, Vec::new())))
+        } else {
+            None
+        }
+    }
+
+    fn live_idxs(&self, idxs: P<Expr>) -> Expr {
+        self.live_idxs_in("self", idxs)
+    }
+
+    fn live_idxs_in(&self, system: &str, idxs: P<Expr>) -> Expr {
+        // idxs.iter().filter(|i| system.idxs[**i].is_some())
+        util::create_method_call(
+            str_to_ident("filter"),
+            P(util::create_method_call(str_to_ident("iter"), idxs, Vec::new())),
+            vec![P(util::create_closure_expr(
+                vec![util::create_closure_arg(str_to_ident("i"))],
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_method_call(
+                        str_to_ident("is_some"),
+                        P(util::create_idx_expr(
+                            P(util::create_deref_expr(P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("i"))))))),
+                            P(util::create_field_expr(util::field_ident("idxs"), str_to_ident(system)))
+                        )),
+                        Vec::new()
+                    )))
+                ))
+            ))]
+        )
+    }
+
+    pub fn live_count(&self, idxs: P<Expr>) -> Expr {
+        self.live_count_in("self", idxs)
+    }
+
+    // Removed objects are only dropped from the idxs by the next signal, so they are left out here
+    pub fn live_count_in(&self, system: &str, idxs: P<Expr>) -> Expr {
+        util::create_method_call(str_to_ident("count"), P(self.live_idxs_in(system, idxs)), Vec::new())
+    }
+
+    fn count_disabled(&self, system: &SystemInfo, func: &HandlerFnInfo) -> Option<P<Expr>> {
+        if self.is_counted(system, func) {
+            Some(P(util::create_num_expr(0)))
+        } else {
+            None
+        }
+    }
+
+    fn generate_enabled_check(&self, result: Option<P<Expr>>) -> Stmt {
+        // if !self.handler_enabled { return ..; }
+        util::create_stmt(P(util::create_if_expr(
+            P(util::create_unary_expr(
+                UnOp::Not,
+                P(util::create_self_field_expr(util::enabled_ident(self.name)))
+            )),
+            P(util::create_return_block(result)),
+            None
+        )))
+    }
+
+    pub fn generate_set_enabled(&self) -> ImplItem {
+        // self.handler_enabled = enabled;
+        util::impl_mut_method(
+            util::set_enabled_ident(self.name),
+            vec![util::create_arg(str_to_ident("enabled"), P(util::ty_from_ident(str_to_ident("bool"))))],
+            None,
+            P(util::create_block(
+                vec![util::create_stmt(P(util::create_assign_expr(
+                    P(util::create_self_field_expr(util::enabled_ident(self.name))),
+                    P(util::create_var_expr(str_to_ident("enabled")))
+                )))],
+                None
+            ))
+        )
+    }
+
+    fn generate_targets(&self, system: &SystemInfo, func: &HandlerFnInfo) -> ImplItem {
+        util::impl_method(
+            util::ident_append(func.source_name, str_to_ident("_targets")),
+            Vec::new(),
+            Some(P(system.vec_ty(util::ty_from_ident(system.idx_name())))),
+            P(util::create_block(
+                vec![self.generate_enabled_check(Some(P(system.vec_new())))],
+
+                // self.handler_idxs.iter().filter(..).map(|i| SystemIndex(*i)).collect()
+                Some(P(util::create_method_call(
+                    str_to_ident("collect"),
+                    P(util::create_method_call(
+                        str_to_ident("map"),
+                        P(self.live_idxs(P(util::create_self_field_expr(util::idxs_ident(self.name))))),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("i"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_call(
+                                    P(util::create_var_expr(system.idx_name())),
+                                    vec![P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("i")))))]
+                                )))
+                            ))
+                        ))]
+                    )),
+                    Vec::new()
                 )))
             ))
         )
     }
 
-    fn generate_fn_get_mut_impl(&self) -> ImplItem {
-        util::impl_mut_method(
-            str_to_ident("get_mut"),
-            vec![util::create_arg(
-                str_to_ident("idx"),
-                P(util::ty_from_ident(self.idx_name()))
-            )],
-            Some(P(util::param_ty_from_ident(
-                str_to_ident("Option"),
-                util::mut_ref_ty(P(util::param_ty_from_ident(
-                    str_to_ident("Box"),
-                    util::ty_from_ident(self.object_name())
-                )))
-            ))),
+    fn generate_will_dispatch(&self, func: &HandlerFnInfo) -> ImplItem {
+        util::impl_method(
+            util::ident_append(str_to_ident("will_dispatch_"), func.source_name),
+            Vec::new(),
+            Some(P(util::ty_from_ident(str_to_ident("bool")))),
             P(util::create_block(
                 Vec::new(),
-                Some(P(util::create_method_call(
-                    str_to_ident("and_then"),
+
+                // self.handler_enabled && self.handler_idxs.iter().filter(..).next().is_some()
+                Some(P(util::create_binop_expr(
+                    P(util::create_self_field_expr(util::enabled_ident(self.name))),
+                    BinOpKind::And,
                     P(util::create_method_call(
-                        str_to_ident("cloned"),
+                        str_to_ident("is_some"),
                         P(util::create_method_call(
-                            str_to_ident("get"),
-                            P(util::create_self_field_expr(str_to_ident("idxs"))),
-                            vec![P(util::create_tuple_field_expr(
-                                P(util::create_var_expr(str_to_ident("idx"))),
-                                0
-                            ))],
+                            str_to_ident("next"),
+                            P(self.live_idxs(P(util::create_self_field_expr(util::idxs_ident(self.name))))),
+                            Vec::new()
                         )),
                         Vec::new()
-                    )),
-                    vec![P(util::create_closure_expr(
-                        vec![util::create_arg(
-                            str_to_ident("obj_idx"),
-                            P(util::param_ty_from_ident(
-                                str_to_ident("Option"),
-                                util::ty_from_ident(str_to_ident("usize"))
-                            ))
-                        )],
-                        P(util::create_block(
-                            Vec::new(),
-                            Some(P(util::create_method_call(
-                                str_to_ident("map"),
-                                P(util::create_var_expr(str_to_ident("obj_idx"))),
-                                vec![P(util::create_closure_expr(
-                                    vec![util::create_arg(
-                                        str_to_ident("obj_idx"),
-                                        P(util::ty_from_ident(str_to_ident("usize")))
-                                    )],
-                                    P(util::create_unsafe_block(
-                                        Vec::new(),
-                                        Some(P(util::create_method_call(
-                                            str_to_ident("get_unchecked_mut"),
-                                            P(util::create_self_field_expr(str_to_ident("objects"))),
-                                            vec![P(util::create_var_expr(str_to_ident("obj_idx")))]
-                                        )))
-                                    ))
-                                ))]
-                            )))
-                        ))
-                    ))]
+                    ))
                 )))
             ))
         )
     }
 
-    fn generate_impl(&self) -> Item {
-        let mut fns = vec![
-            self.generate_fn_new_impl(),
-            self.generate_fn_add_impl(),
-            self.generate_fn_iter_impl(),
-            self.generate_fn_iter_mut_impl(),
-            self.generate_fn_remove_impl(),
-            self.generate_fn_get_impl(),
-            self.generate_fn_get_mut_impl(),
+    fn generate_send_to(&self, system: &SystemInfo, func: &HandlerFnInfo) -> ImplItem {
+        // object.slot(..)
+        let call = util::create_method_call(
+            func.dest_name,
+            P(util::create_var_expr(str_to_ident("object"))),
+            system.dispatch_args(func)
+        );
+
+        // if let Some(obj_idx) = self.idxs.get(idx.0).cloned().unwrap_or(None) { .. }
+        let mut lookup = util::create_stmt(P(util::create_if_let_expr(
+            P(util::create_tuple_struct_pat(
+                str_to_ident("Some"),
+                vec![str_to_ident("obj_idx")]
+            )),
+            P(util::create_method_call(
+                str_to_ident("unwrap_or"),
+                P(util::create_method_call(
+                    str_to_ident("cloned"),
+                    P(util::create_method_call(
+                        str_to_ident("get"),
+                        P(util::create_self_field_expr(util::field_ident("idxs"))),
+                        vec![P(util::create_tuple_field_expr(
+                            P(util::create_var_expr(str_to_ident("idx"))),
+                            0
+                        ))]
+                    )),
+                    Vec::new()
+                )),
+                vec![P(util::create_var_expr(str_to_ident("None")))]
+            )),
+            P(util::create_block(
+                // if let Some(object) = self.objects[obj_idx].as_handler_mut() { result = Some(..); }
+                vec![util::create_stmt(P(util::create_if_let_expr(
+                    P(util::create_tuple_struct_pat(
+                        str_to_ident("Some"),
+                        vec![str_to_ident("object")]
+                    )),
+                    P(util::create_method_call(
+                        func.accessor(self.name),
+                        P(system.object_access(
+                            P(util::create_idx_expr(
+                                P(util::create_var_expr(str_to_ident("obj_idx"))),
+                                P(util::create_self_field_expr(util::field_ident("objects")))
+                            )),
+                            func.object_mutability()
+                        )),
+                        Vec::new()
+                    )),
+                    P(util::create_block(
+                        vec![util::create_stmt(P(util::create_assign_expr(
+                            P(util::create_var_expr(str_to_ident("result"))),
+                            P(util::create_call(
+                                P(util::create_var_expr(str_to_ident("Some"))),
+                                vec![P(call)]
+                            ))
+                        )))],
+                        None
+                    )),
+                    None
+                )))],
+                None
+            )),
+            None
+        )));
+
+        // Only the latest object implementing an exclusive handler receives its signals, and there is at most one
+        if self.exclusive {
+            // if self.handler_idxs.contains(&idx.0) { .. }
+            lookup = util::create_stmt(P(util::create_if_expr(
+                P(util::create_method_call(
+                    str_to_ident("contains"),
+                    P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                    vec![P(util::create_addr_of_expr(
+                        Mutability::Immutable,
+                        P(util::create_tuple_field_expr(P(util::create_var_expr(str_to_ident("idx"))), 0))
+                    ))]
+                )),
+                P(util::create_block(vec![lookup], None)),
+                None
+            )));
+        }
+
+        let enabled_check = self.generate_enabled_check(Some(P(match func.ret {
+            Some(_) => util::create_var_expr(str_to_ident("None")),
+            None => util::create_var_expr(str_to_ident("false"))
+        })));
+
+        let stmts = vec![
+            // let mut result = None;
+            util::create_let_mut_stmt(
+                str_to_ident("result"),
+                Some(P(util::create_var_expr(str_to_ident("None"))))
+            ),
+
+            lookup
         ];
 
-        for handler in self.handlers.iter() {
-            handler.generate_signal_impl(&mut fns);
+        let (ret, result) = match func.ret {
+            Some(ref ty) => (
+                util::param_ty_from_ident(str_to_ident("Option"), (**ty).clone()),
+                util::create_var_expr(str_to_ident("result"))
+            ),
+
+            // result.is_some()
+            None => (
+                util::ty_from_ident(str_to_ident("bool")),
+                util::create_method_call(
+                    str_to_ident("is_some"),
+                    P(util::create_var_expr(str_to_ident("result"))),
+                    Vec::new()
+                )
+            )
+        };
+
+        let (stmts, result) = system.wrap_commands(stmts, Some(P(result)));
+        let (stmts, result) = system.wrap_dispatching(enabled_check, stmts, result, func.takes_shared_self(system));
+
+        let mut args = vec![util::create_arg(str_to_ident("idx"), P(util::ty_from_ident(system.idx_name())))];
+        args.extend(func.args.iter().map(|arg| arg.generate()));
+
+        let name = util::ident_append(
+            util::ident_append(str_to_ident("send_"), func.source_name),
+            str_to_ident("_to")
+        );
+
+        if func.takes_shared_self(system) {
+            util::impl_method(name, args, Some(P(ret)), P(util::create_block(stmts, result)))
+        } else {
+            util::impl_mut_method(name, args, Some(P(ret)), P(util::create_block(stmts, result)))
         }
+    }
 
-        util::create_impl(
-            self.name,
-            None,
-            fns
-        )
+    fn generate_default_signal(&self, system: &SystemInfo, func: &HandlerFnInfo) -> ImplItem {
+        // self.signal(.., default)
+        let call = util::create_method_call(
+            func.source_name,
+            P(util::create_var_expr(str_to_ident("self"))),
+            func.args.iter().map(|arg| match arg.default {
+                Some(ref expr) => expr.clone(),
+                None => P(util::create_var_expr(arg.name))
+            }).collect()
+        );
+
+        let args = func.args.iter().filter(|arg| arg.default.is_none()).map(|arg| arg.generate()).collect();
+        let ret = match func.ret {
+            Some(ref ty) if func.accumulate.is_none() => Some(P(system.vec_ty((**ty).clone()))),
+            _ => self.count_ret_ty(system, func)
+        };
+        let body = P(util::create_block(Vec::new(), Some(P(call))));
+
+        if func.takes_shared_self(system) {
+            util::impl_method(util::ident_append(func.source_name, str_to_ident("_default")), args, ret, body)
+        } else {
+            util::impl_mut_method(util::ident_append(func.source_name, str_to_ident("_default")), args, ret, body)
+        }
     }
 
-    pub fn generate_object_impl(&self, thing: Ident, impls: &Vec<String>) -> Box<MacResult> {
-        let mut items = Vec::new();
+    pub fn generate_signal_impl(&self, system: &SystemInfo, items: &mut Vec<ImplItem>) {
+        for func in self.fns.iter() {
+            items.push(util::must_use(self.generate_will_dispatch(func)));
+            items.push(util::must_use(self.generate_targets(system, func)));
 
-        for handler in self.handlers.iter() {
-            items.extend_from_slice(&[
-                util::impl_method_priv(
-                    util::as_ident(handler.name),
-                    Vec::new(),
-                    Some(P(util::param_ty_from_ident(
-                        str_to_ident("Option"),
-                        util::ref_ty_from_ident(handler.name)
-                    ))),
-                    P(util::create_block(
-                        Vec::new(),
-                        Some(P(if impls.contains(&format!("{}", handler.name)) {
-                            util::create_call(
-                                P(util::create_var_expr(str_to_ident("Some"))),
-                                vec![P(util::create_cast_expr(
-                                        P(util::create_var_expr(str_to_ident("self"))),
-                                        P(util::ref_ty_from_ident(handler.name))
-                                ))]
-                            )
-                        } else {
-                            util::create_var_expr(str_to_ident("None"))
-                        })),
-                    ))
-                ),
+            // Single signals may move their arguments, so they can only be sent to one object
+            if func.single {
+                let mut send_to = self.generate_send_to(system, func);
+                send_to.attrs.extend(func.docs.iter().cloned());
+                items.push(func.with_generics(send_to));
+                continue
+            }
 
-                util::impl_mut_method_priv(
-                    util::as_mut_ident(handler.name),
-                    Vec::new(),
-                    Some(P(util::param_ty_from_ident(
-                        str_to_ident("Option"),
-                        util::mut_ref_ty_from_ident(handler.name)
-                    ))),
-                    P(util::create_block(
-                        Vec::new(),
-                        Some(P(if impls.contains(&format!("{}", handler.name)) {
-                            util::create_call(
-                                P(util::create_var_expr(str_to_ident("Some"))),
-                                vec![P(util::create_cast_expr(
-                                        P(util::create_var_expr(str_to_ident("self"))),
-                                        P(util::mut_ref_ty_from_ident(handler.name))
-                                ))]
-                            )
-                        } else {
-                            util::create_var_expr(str_to_ident("None"))
-                        })),
+            if !func.consuming {
+                items.push(func.with_generics(self.generate_send_to(system, func)));
+            }
+
+            if func.args.iter().any(|arg| arg.default.is_some()) {
+                items.push(func.with_generics(self.generate_default_signal(system, func)));
+            }
+
+            items.push(self.generate_signal(system, func));
+        }
+    }
+
+    pub fn generate_signal(&self, system: &SystemInfo, func: &HandlerFnInfo) -> ImplItem {
+        let mut signal = if func.parallel {
+            self.generate_parallel_signal(system, func)
+        } else if func.consuming {
+            self.generate_consuming_signal(system, func)
+        } else {
+            self.generate_sequential_signal(system, func)
+        };
+
+        signal.attrs.extend(func.docs.iter().cloned());
+        func.with_generics(signal)
+    }
+
+    fn generate_dispatch_signal(&self, system: &SystemInfo, func: &HandlerFnInfo) -> TraitItem {
+        // handler.slot(..)
+        let call = util::create_method_call(
+            func.dest_name,
+            P(util::create_var_expr(str_to_ident("handler"))),
+            func.args.iter().map(|arg| P(util::create_var_expr(arg.name))).collect()
+        );
+
+        let (init, call, result) = match func.ret {
+            Some(_) => (
+                // let mut results = Vec::new();
+                Some(util::create_let_mut_stmt(
+                    str_to_ident("results"),
+                    Some(P(system.vec_new()))
+                )),
+
+                // results.push(..);
+                util::create_stmt(P(util::create_method_call(
+                    str_to_ident("push"),
+                    P(util::create_var_expr(str_to_ident("results"))),
+                    vec![P(call)]
+                ))),
+
+                Some(P(util::create_var_expr(str_to_ident("results"))))
+            ),
+
+            None => (None, util::create_stmt(P(call)), None)
+        };
+
+        let call = match func.gate {
+            // if handler.gate() { .. }
+            Some(gate) => util::create_stmt(P(util::create_if_expr(
+                P(util::create_method_call(gate, P(util::create_var_expr(str_to_ident("handler"))), Vec::new())),
+                P(util::create_block(vec![call], None)),
+                None
+            ))),
+
+            None => call
+        };
+
+        // if let Some(handler) = object.as_handler_mut() { .. }
+        let dispatch = util::create_stmt(P(util::create_if_let_expr(
+            P(util::create_tuple_struct_pat(str_to_ident("Some"), vec![str_to_ident("handler")])),
+            P(util::create_method_call(
+                func.accessor(self.name),
+                P(util::create_var_expr(str_to_ident("object"))),
+                Vec::new()
+            )),
+            P(util::create_block(vec![call], None)),
+            None
+        )));
+
+        let mut stmts: Vec<Stmt> = init.into_iter().collect();
+
+        // The closure borrows the arguments, so it can be called once per object
+        // self.for_each_object(|object| { .. });
+        stmts.push(util::create_stmt(P(util::create_method_call(
+            str_to_ident("for_each_object"),
+            P(util::create_var_expr(str_to_ident("self"))),
+            vec![P(util::create_ref_closure_expr(
+                vec![util::create_closure_arg(str_to_ident("object"))],
+                P(util::create_block(vec![dispatch], None))
+            ))]
+        ))));
+
+        let mut item = util::create_mut_trait_method(
+            func.source_name,
+            func.args.iter().map(|arg| arg.generate()).collect(),
+            func.ret.as_ref().map(|ty| P(system.vec_ty((**ty).clone())))
+        );
+
+        if let TraitItemKind::Method(_, ref mut body) = item.node {
+            *body = Some(P(util::create_block(stmts, result)));
+        }
+
+        item.attrs.extend(func.docs.iter().cloned());
+        func.trait_with_generics(item)
+    }
+
+    fn generate_sequential_signal(&self, system: &SystemInfo, func: &HandlerFnInfo) -> ImplItem {
+        // object.as_handler_mut().unwrap().slot(..)
+        let call = util::create_method_call(
+            func.dest_name,
+            P(util::create_method_call(
+                str_to_ident("unwrap"),
+                P(util::create_method_call(
+                    func.accessor(self.name),
+                    P(system.object_access(
+                        P(util::create_method_call(
+                            str_to_ident(if func.reads { "get_unchecked" } else { "get_unchecked_mut" }),
+                            P(util::create_self_field_expr(util::field_ident("objects"))),
+                            vec![P(util::create_var_expr(str_to_ident("idx")))]
+                        )),
+                        func.object_mutability()
+                    )),
+                    Vec::new()
+                )),
+                Vec::new(),
+            )),
+            system.dispatch_args(func)
+        );
+
+        let (init, call, result) = match func.ret {
+            Some(_) if func.accumulate.is_some() => (
+                None,
+
+                // self.field += ..;
+                util::create_stmt(P(util::create_assignop_expr(
+                    P(util::create_self_field_expr(func.accumulate.unwrap())),
+                    BinOpKind::Add,
+                    P(call)
+                ))),
+
+                self.count_result(system, func, P(util::create_self_field_expr(util::idxs_ident(self.name))))
+            ),
+
+            Some(_) if func.fold => (
+                // let mut acc = init;
+                Some(util::create_let_mut_stmt(
+                    str_to_ident("acc"),
+                    Some(P(util::create_var_expr(str_to_ident("init"))))
+                )),
+
+                // acc = f(acc, ..);
+                util::create_stmt(P(util::create_assign_expr(
+                    P(util::create_var_expr(str_to_ident("acc"))),
+                    P(util::create_call(
+                        P(util::create_var_expr(str_to_ident("f"))),
+                        vec![
+                            P(util::create_var_expr(str_to_ident("acc"))),
+                            P(call)
+                        ]
                     ))
-                )
-            ]);
+                ))),
+
+                Some(P(util::create_var_expr(str_to_ident("acc"))))
+            ),
+
+            Some(_) => (
+                // let mut results = Vec::new();
+                Some(util::create_let_mut_stmt(
+                    str_to_ident("results"),
+                    Some(P(system.vec_new()))
+                )),
+
+                // results.push(..);
+                util::create_stmt(P(util::create_method_call(
+                    str_to_ident("push"),
+                    P(util::create_var_expr(str_to_ident("results"))),
+                    vec![P(call)]
+                ))),
+
+                Some(P(util::create_var_expr(str_to_ident("results"))))
+            ),
+
+            None => (
+                None,
+                util::create_stmt(P(call)),
+                self.count_result(system, func, P(util::create_self_field_expr(util::idxs_ident(self.name))))
+            )
+        };
+
+        let mut init: Vec<Stmt> = init.into_iter().collect();
+
+        // Signals on &self cannot drop stale idxs as they go, so they skip them instead
+        let shared = func.takes_shared_self(system);
+
+        let mut calls = vec![call];
+
+        // Skipped objects are not dispatched to, so the count has to be kept as the signal goes
+        let result = if self.is_counted(system, func) && (func.gate.is_some() || shared) {
+            // let mut count = 0;
+            init.push(util::create_let_mut_stmt(
+                str_to_ident("count"),
+                Some(P(util::create_num_expr(0)))
+            ));
+
+            // count += 1;
+            calls.push(util::create_stmt(P(util::create_assignop_expr(
+                P(util::create_var_expr(str_to_ident("count"))),
+                BinOpKind::Add,
+                P(util::create_num_expr(1))
+            ))));
+
+            Some(P(util::create_var_expr(str_to_ident("count"))))
+        } else {
+            result
+        };
+
+        let calls = match func.gate {
+            Some(gate) => {
+                // object.as_handler().unwrap().gate()
+                let check = util::create_method_call(
+                    gate,
+                    P(util::create_method_call(
+                        str_to_ident("unwrap"),
+                        P(util::create_method_call(
+                            util::as_ident(self.name),
+                            P(system.object_access(
+                                P(util::create_method_call(
+                                    str_to_ident("get_unchecked"),
+                                    P(util::create_self_field_expr(util::field_ident("objects"))),
+                                    vec![P(util::create_var_expr(str_to_ident("idx")))]
+                                )),
+                                Mutability::Immutable
+                            )),
+                            Vec::new()
+                        )),
+                        Vec::new()
+                    )),
+                    Vec::new()
+                );
+
+                // if object.gate() { .. }
+                vec![util::create_stmt(P(util::create_if_expr(
+                    P(check),
+                    P(util::create_block(calls, None)),
+                    None
+                )))]
+            },
+
+            None => calls
+        };
+
+        let disabled = match func.ret {
+            // init
+            Some(_) if func.fold => Some(P(util::create_var_expr(str_to_ident("init")))),
+
+            // Vec::new()
+            Some(_) if func.accumulate.is_none() => Some(P(system.vec_new())),
+
+            _ => self.count_disabled(system, func)
+        };
+
+        let enabled_check = self.generate_enabled_check(disabled);
+
+        let mut stmts: Vec<Stmt> = system.check_bookkeeping_stmt().into_iter().collect();
+        stmts.extend(init);
+
+        if shared {
+            // for idx in self.handler_idxs.iter() { if let Some(idx) = *self.idxs.get_unchecked(*idx) { .. } }
+            stmts.push(util::create_stmt(P(util::create_for_expr(
+                str_to_ident("idx"),
+                P(util::create_method_call(
+                    str_to_ident("iter"),
+                    P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                    Vec::new()
+                )),
+                P(util::create_block(
+                    vec![util::create_stmt(P(util::create_if_let_expr(
+                        P(util::create_tuple_struct_pat(
+                            str_to_ident("Some"),
+                            vec![str_to_ident("idx")]
+                        )),
+                        P(util::create_deref_expr(P(util::create_method_call(
+                            str_to_ident("get_unchecked"),
+                            P(util::create_self_field_expr(util::field_ident("idxs"))),
+                            vec![P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("idx")))))]
+                        )))),
+                        P(util::create_block(calls, None)),
+                        None
+                    )))],
+                    None
+                ))
+            ))));
+        } else {
+            stmts.extend(self.generate_sequential_loop(system, calls));
         }
 
-        MacEager::items(SmallVector::one(P(util::create_impl(
-            thing,
-            Some(self.object_name()),
-            items
-        ))))
-    }
+        let mut args: Vec<Arg> = func.args.iter().map(|arg| arg.generate()).collect();
+        let (stmts, result) = system.wrap_commands(stmts, result);
+        let (stmts, result) = system.wrap_dispatching(enabled_check, stmts, result, shared);
+        let body = P(util::create_unsafe_block(stmts, result));
 
-    pub fn generate_ast(&self) -> Box<MacResult> {
-        let mut items: Vec<P<Item>> = self.handlers.iter().map(|handler| P(handler.generate())).collect();
-        items.extend_from_slice(&[
-            P(self.generate_object_trait()),
-            P(self.generate_idx_struct()),
-            P(self.generate_struct()),
-            P(self.generate_impl())
-        ]);
+        match func.ret {
+            Some(ref ty) if func.fold => {
+                args.push(util::create_arg(str_to_ident("init"), ty.clone()));
+                args.push(util::create_arg(str_to_ident("f"), P(util::ty_from_ident(str_to_ident("F")))));
 
-        MacEager::items(SmallVector::many(items))
-    }
-}
+                // F: Fn(T, T) -> T
+                let generics = util::create_generics(vec![util::create_ty_param(
+                    str_to_ident("F"),
+                    vec![util::fn_bound(
+                        str_to_ident("Fn"),
+                        vec![ty.clone(), ty.clone()],
+                        Some(ty.clone())
+                    )]
+                )]);
 
-impl HandlerInfo {
-    pub fn new(name: Ident) -> HandlerInfo {
-        HandlerInfo {
-            name: name,
-            reqs: Vec::new(),
-            fns: Vec::new()
+                if shared {
+                    util::impl_generic_method(func.source_name, generics, args, Some(ty.clone()), body)
+                } else {
+                    util::impl_mut_generic_method(func.source_name, generics, args, Some(ty.clone()), body)
+                }
+            },
+
+            Some(ref ty) if func.accumulate.is_none() => {
+                let ret = Some(P(system.vec_ty((**ty).clone())));
+
+                if shared {
+                    util::impl_method(func.source_name, args, ret, body)
+                } else {
+                    util::impl_mut_method(func.source_name, args, ret, body)
+                }
+            },
+
+            _ if shared => util::impl_method(func.source_name, args, self.count_ret_ty(system, func), body),
+
+            _ => util::impl_mut_method(func.source_name, args, self.count_ret_ty(system, func), body)
         }
     }
-    
-    pub fn add_requirement(&mut self, req: Ident) {
-        self.reqs.push(req);
-    }
 
-    pub fn add_function(&mut self, function: HandlerFnInfo) {
-        self.fns.push(function);
-    }
+    // Walks the handler's idxs, dropping any that have gone stale since the last signal
+    fn generate_sequential_loop(&self, system: &SystemInfo, calls: Vec<Stmt>) -> Vec<Stmt> {
+        let mut calls = calls;
 
-    pub fn generate_as_self(&self) -> TraitItem {
-        util::create_trait_method(
-            util::as_ident(self.name),
-            Vec::new(),
-            Some(P(util::param_ty_from_ident(
-                str_to_ident("Option"),
-                util::ref_ty_from_ident(self.name)
-            )))
-        )
-    }
+        // i += 1;
+        calls.push(util::create_stmt(P(util::create_assignop_expr(
+            P(util::create_var_expr(str_to_ident("i"))),
+            BinOpKind::Add,
+            P(util::create_num_expr(1))
+        ))));
 
-    pub fn generate_as_self_mut(&self) -> TraitItem {
-        util::create_mut_trait_method(
-            util::as_mut_ident(self.name),
-            Vec::new(),
-            Some(P(util::param_ty_from_ident(
-                str_to_ident("Option"),
-                util::mut_ref_ty_from_ident(self.name)
-            )))
-        )
-    }
+        let loop_block = util::create_block(
+            vec![
+                // if i >= len() { break }
+                util::create_stmt(P(util::create_if_expr(
+                    P(util::create_binop_expr(
+                        P(util::create_var_expr(str_to_ident("i"))),
+                        BinOpKind::Ge,
+                        P(util::create_method_call(
+                            str_to_ident("len"),
+                            P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                            Vec::new()
+                        ))
+                    )),
+                    P(util::create_break_block()),
+                    None
+                ))),
 
-    pub fn generate(&self) -> Item {
-        util::create_trait(
-            self.name,
-            &self.reqs,
-            &self.fns.iter().map(|function| function.generate()).collect()
-        )
-    }
+                // let idx = *handler_idxs.get_unchecked(i);
+                util::create_let_stmt(
+                    str_to_ident("idx"),
+                    Some(P(util::create_deref_expr(P(util::create_method_call(
+                        str_to_ident("get_unchecked"),
+                        P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                        vec![P(util::create_var_expr(str_to_ident("i")))]
+                    )))))
+                ),
 
-    pub fn generate_signal_impl(&self, items: &mut Vec<ImplItem>) {
-        for func in self.fns.iter() {
-            let loop_block = util::create_block(
-                vec![
-                    // if i > len() { return }
-                    util::create_stmt(P(util::create_if_expr(
-                        P(util::create_binop_expr(
-                            P(util::create_var_expr(str_to_ident("i"))),
-                            BinOpKind::Ge,
-                            P(util::create_method_call(
-                                str_to_ident("len"),
-                                P(util::create_self_field_expr(util::idxs_ident(self.name))),
-                                Vec::new()
-                            ))
-                        )),
-                        P(util::create_return_block(None)),
-                        None
-                    ))),
+                util::create_let_stmt(
+                    str_to_ident("idx"),
+                    Some(P(util::create_deref_expr(P(util::create_method_call(
+                        str_to_ident("get_unchecked"),
+                        P(util::create_self_field_expr(util::field_ident("idxs"))),
+                        vec![P(util::create_var_expr(str_to_ident("idx")))]
+                    )))))
+                ),
 
-                    // let idx = *handler_idxs.get_unchecked(i);
-                    util::create_let_stmt(
-                        str_to_ident("idx"),
-                        Some(P(util::create_deref_expr(P(util::create_method_call(
-                            str_to_ident("get_unchecked"),
+                util::create_stmt(P(util::create_if_let_expr(
+                    P(util::create_tuple_struct_pat(
+                        str_to_ident("Some"),
+                        vec![str_to_ident("idx")]
+                    )),
+                    P(util::create_var_expr(str_to_ident("idx"))),
+                    P(util::create_block(calls, None)),
+                    Some(P(util::create_block_expr(P(util::create_block(
+                        vec![util::create_stmt(P(util::create_method_call(
+                            // Ordered handlers must keep their idxs sorted
+                            str_to_ident(if self.order_by.is_some() || system.priority { "remove" } else { "swap_remove" }),
                             P(util::create_self_field_expr(util::idxs_ident(self.name))),
                             vec![P(util::create_var_expr(str_to_ident("i")))]
-                        )))))
-                    ),
+                        )))],
+                        None
+                    ))))),
+                )))
+            ],
+            None
+        );
 
-                    util::create_let_stmt(
-                        str_to_ident("idx"),
-                        Some(P(util::create_deref_expr(P(util::create_method_call(
-                            str_to_ident("get_unchecked"),
-                            P(util::create_self_field_expr(str_to_ident("idxs"))),
-                            vec![P(util::create_var_expr(str_to_ident("idx")))]
-                        )))))
-                    ),
+        vec![
+            // let mut i = 0;
+            util::create_let_mut_stmt(
+                str_to_ident("i"),
+                Some(P(util::create_num_expr(0)))
+            ),
 
-                    util::create_stmt(P(util::create_if_let_expr(
-                        P(util::create_tuple_struct_pat(
-                            str_to_ident("Some"),
-                            vec![str_to_ident("idx")]
+            // loop { .. }
+            util::create_stmt(P(util::create_loop_expr(P(loop_block))))
+        ]
+    }
+
+    pub fn generate_remove_check(&self) -> Stmt {
+        // self.handler_idxs.retain(|i| *i != idx.0);
+        util::create_stmt(P(util::create_method_call(
+            str_to_ident("retain"),
+            P(util::create_self_field_expr(util::idxs_ident(self.name))),
+            vec![P(util::create_closure_expr(
+                vec![util::create_closure_arg(str_to_ident("i"))],
+                P(util::create_block(
+                    Vec::new(),
+                    Some(P(util::create_binop_expr(
+                        P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("i"))))),
+                        BinOpKind::Ne,
+                        P(util::create_tuple_field_expr(
+                            P(util::create_var_expr(str_to_ident("idx"))),
+                            0
+                        ))
+                    )))
+                ))
+            ))]
+        )))
+    }
+
+    fn generate_ordered_insert(&self, system: &SystemInfo, key: Ident, idx: P<Expr>) -> Vec<Stmt> {
+        // object.as_handler().unwrap().key()
+        let key_of = |object: P<Expr>| P(util::create_method_call(
+            key,
+            P(util::create_method_call(
+                str_to_ident("unwrap"),
+                P(util::create_method_call(
+                    util::as_ident(self.name),
+                    P(system.object_access(object, Mutability::Immutable)),
+                    Vec::new()
+                )),
+                Vec::new()
+            )),
+            Vec::new()
+        ));
+
+        // objects[idx]
+        let existing = P(util::create_idx_expr(
+            P(util::create_var_expr(str_to_ident("idx"))),
+            P(util::create_var_expr(str_to_ident("objects")))
+        ));
+
+        vec![
+            // let key = object.as_handler().unwrap().key();
+            util::create_let_stmt(str_to_ident("key"), Some(key_of(P(util::create_var_expr(str_to_ident("object")))))),
+
+            // let objects = &self.objects;
+            util::create_let_stmt(
+                str_to_ident("objects"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("objects")))
+                )))
+            ),
+
+            // let idxs = &self.idxs;
+            util::create_let_stmt(
+                str_to_ident("idxs"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("idxs")))
+                )))
+            ),
+
+            // Removed objects stay in the idxs until the next signal, so they never end the search
+            // let pos = self.handler_idxs.iter().position(|i| idxs[*i].map_or(false, |idx| key_of(objects[idx]) > key))
+            //     .unwrap_or(self.handler_idxs.len());
+            util::create_let_stmt(
+                str_to_ident("pos"),
+                Some(P(util::create_method_call(
+                    str_to_ident("unwrap_or"),
+                    P(util::create_method_call(
+                        str_to_ident("position"),
+                        P(util::create_method_call(
+                            str_to_ident("iter"),
+                            P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                            Vec::new()
                         )),
-                        P(util::create_var_expr(str_to_ident("idx"))),
-                        P(util::create_block(
-                            vec![
-                                util::create_stmt(P(util::create_method_call(
-                                    func.dest_name,
-                                    P(util::create_method_call(
-                                        str_to_ident("unwrap"),
-                                        P(util::create_method_call(
-                                            util::as_mut_ident(self.name),
-                                            P(util::create_method_call(
-                                                str_to_ident("get_unchecked_mut"),
-                                                P(util::create_self_field_expr(str_to_ident("objects"))),
-                                                vec![P(util::create_var_expr(str_to_ident("idx")))]
-                                            )),
-                                            Vec::new()
-                                        )),
-                                        Vec::new(),
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("i"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_method_call(
+                                    str_to_ident("map_or"),
+                                    P(util::create_idx_expr(
+                                        P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("i"))))),
+                                        P(util::create_var_expr(str_to_ident("idxs")))
                                     )),
-                                    func.args.iter().map(|arg| P(util::create_var_expr(arg.name))).collect()
-                                ))),
-
-                                util::create_stmt(P(util::create_assignop_expr(
-                                    P(util::create_var_expr(str_to_ident("i"))),
-                                    BinOpKind::Add,
-                                    P(util::create_num_expr(1))
+                                    vec![
+                                        P(util::create_var_expr(str_to_ident("false"))),
+                                        P(util::create_closure_expr(
+                                            vec![util::create_closure_arg(str_to_ident("idx"))],
+                                            P(util::create_block(
+                                                Vec::new(),
+                                                Some(P(util::create_binop_expr(
+                                                    key_of(existing),
+                                                    BinOpKind::Gt,
+                                                    P(util::create_var_expr(str_to_ident("key")))
+                                                )))
+                                            ))
+                                        ))
+                                    ]
                                 )))
-                            ],
-                            None
+                            ))
+                        ))]
+                    )),
+                    vec![P(util::create_method_call(
+                        str_to_ident("len"),
+                        P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                        Vec::new()
+                    ))]
+                )))
+            ),
+
+            // self.handler_idxs.insert(pos, idx);
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("insert"),
+                P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                vec![P(util::create_var_expr(str_to_ident("pos"))), idx]
+            )))
+        ]
+    }
+
+    fn generate_priority_insert(&self, idx: P<Expr>) -> Vec<Stmt> {
+        vec![
+            // let priorities = &self.priorities;
+            util::create_let_stmt(
+                str_to_ident("priorities"),
+                Some(P(util::create_addr_of_expr(
+                    Mutability::Immutable,
+                    P(util::create_self_field_expr(util::field_ident("priorities")))
+                )))
+            ),
+
+            // let pos = self.handler_idxs.iter().position(|i| priorities[*i] > priority)
+            //     .unwrap_or(self.handler_idxs.len());
+            util::create_let_stmt(
+                str_to_ident("pos"),
+                Some(P(util::create_method_call(
+                    str_to_ident("unwrap_or"),
+                    P(util::create_method_call(
+                        str_to_ident("position"),
+                        P(util::create_method_call(
+                            str_to_ident("iter"),
+                            P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                            Vec::new()
                         )),
-                        Some(P(util::create_block_expr(P(util::create_block(
-                            vec![util::create_stmt(P(util::create_method_call(
-                                str_to_ident("swap_remove"),
-                                P(util::create_self_field_expr(util::idxs_ident(self.name))),
-                                vec![P(util::create_var_expr(str_to_ident("i")))]
-                            )))],
-                            None
-                        ))))),
-                    )))
-                ],
-                None
-            );
+                        vec![P(util::create_closure_expr(
+                            vec![util::create_closure_arg(str_to_ident("i"))],
+                            P(util::create_block(
+                                Vec::new(),
+                                Some(P(util::create_binop_expr(
+                                    P(util::create_idx_expr(
+                                        P(util::create_deref_expr(P(util::create_var_expr(str_to_ident("i"))))),
+                                        P(util::create_var_expr(str_to_ident("priorities")))
+                                    )),
+                                    BinOpKind::Gt,
+                                    P(util::create_var_expr(str_to_ident("priority")))
+                                )))
+                            ))
+                        ))]
+                    )),
+                    vec![P(util::create_method_call(
+                        str_to_ident("len"),
+                        P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                        Vec::new()
+                    ))]
+                )))
+            ),
 
-            items.push(util::impl_mut_method(
-                func.source_name,
-                func.args.iter().map(|arg| arg.generate()).collect(),
-                None,
-                P(util::create_unsafe_block(
-                    vec![
-                        // let mut i = 0;
-                        util::create_let_mut_stmt(
-                            str_to_ident("i"),
-                            Some(P(util::create_num_expr(0)))
-                        ),
+            // self.handler_idxs.insert(pos, idx);
+            util::create_stmt(P(util::create_method_call(
+                str_to_ident("insert"),
+                P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                vec![P(util::create_var_expr(str_to_ident("pos"))), idx]
+            )))
+        ]
+    }
 
-                        // loop { .. }
-                        util::create_stmt(P(util::create_loop_expr(P(loop_block)))),
-                    ],
-                    None
-                ))
-            ));
+    pub fn generate_add_check(&self, system: &SystemInfo, index: usize, idx: P<Expr>) -> Expr {
+        let mut stmts = Vec::new();
+
+        if self.exclusive {
+            // self.handler_idxs.clear();
+            stmts.push(util::create_stmt(P(util::create_method_call(
+                str_to_ident("clear"),
+                P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                Vec::new()
+            ))));
         }
-    }
 
-    pub fn generate_add_check(&self) -> Expr {
-        util::create_if_expr(
-            P(util::create_method_call(
+        match self.order_by {
+            Some(key) => stmts.extend(self.generate_ordered_insert(system, key, idx)),
+
+            None if system.priority => stmts.extend(self.generate_priority_insert(idx)),
+
+            // self.handler_idxs.push(idx);
+            None => stmts.push(util::create_stmt(P(util::create_method_call(
+                str_to_ident("push"),
+                P(util::create_self_field_expr(util::idxs_ident(self.name))),
+                vec![idx]
+            ))))
+        }
+
+        let check = if system.uses_handler_mask() {
+            // mask & (1 << index) != 0
+            util::create_binop_expr(
+                P(util::create_binop_expr(
+                    P(util::create_var_expr(str_to_ident("mask"))),
+                    BinOpKind::BitAnd,
+                    P(util::create_num_expr(1 << index))
+                )),
+                BinOpKind::Ne,
+                P(util::create_num_expr(0))
+            )
+        } else {
+            // object.as_handler().is_some()
+            util::create_method_call(
                 str_to_ident("is_some"),
                 P(util::create_method_call(
                     util::as_ident(self.name),
-                    P(util::create_var_expr(str_to_ident("object"))),
+                    P(system.object_access(P(util::create_var_expr(str_to_ident("object"))), Mutability::Immutable)),
                     Vec::new()
                 )),
                 Vec::new()
-            )),
+            )
+        };
 
-            P(util::create_block(
-                vec![
-                    util::create_stmt(P(util::create_method_call(
-                        str_to_ident("push"),
-                        P(util::create_self_field_expr(util::idxs_ident(self.name))),
-                        vec![
-                            P(util::create_var_expr(str_to_ident("idx")))
-                        ]
-                    ))),
-                ],
-                None
-            )),
+        util::create_if_expr(
+            P(check),
+
+            P(util::create_block(stmts, None)),
 
             None
         )
@@ -800,37 +6155,140 @@ impl HandlerInfo {
 }
 
 impl HandlerFnInfo {
-    pub fn new(source: Ident, dest: Ident, args: Vec<HandlerFnArg>) -> HandlerFnInfo {
+    pub fn new(source: Ident, dest: Ident, args: Vec<HandlerFnArg>, ret: Option<P<Ty>>) -> HandlerFnInfo {
         HandlerFnInfo {
             source_name: source,
             dest_name: dest,
-            args: args
+            args: args,
+            ret: ret,
+            parallel: false,
+            consuming: false,
+            fold: false,
+            single: false,
+            reads: false,
+            gate: None,
+            accumulate: None,
+            body: None,
+            docs: Vec::new(),
+            lifetimes: Vec::new(),
+            where_predicates: Vec::new()
         }
     }
 
-    pub fn generate(&self) -> TraitItem {
-        util::create_mut_trait_method(
+    // Read-only slots only need a shared borrow of each object
+    fn object_mutability(&self) -> Mutability {
+        if self.reads {
+            Mutability::Immutable
+        } else {
+            Mutability::Mutable
+        }
+    }
+
+    // Read-only signals with nothing to queue or accumulate can be sent through a shared borrow of the system
+    fn takes_shared_self(&self, system: &SystemInfo) -> bool {
+        self.reads && !(self.parallel || self.consuming || system.commands || self.accumulate.is_some())
+    }
+
+    fn is_dispatchable(&self) -> bool {
+        !(self.parallel || self.consuming || self.single || self.fold || self.accumulate.is_some())
+    }
+
+    fn accessor(&self, handler: Ident) -> Ident {
+        if self.reads {
+            util::as_ident(handler)
+        } else {
+            util::as_mut_ident(handler)
+        }
+    }
+
+    fn slot_args(&self, system: &SystemInfo, name: &str, qualified: bool) -> Vec<Arg> {
+        let mut args = system.leading_args(name, qualified);
+        args.extend(self.args.iter().map(|arg| arg.generate()));
+        args
+    }
+
+    fn default_slot_args(&self, system: &SystemInfo) -> Vec<Arg> {
+        // Patterns are only allowed in the slot when it has a body to bind them in
+        let mut args = system.leading_args("commands", false);
+        args.extend(self.args.iter().map(|arg| arg.generate_pat()));
+        args
+    }
+
+    pub fn generate(&self, system: &SystemInfo) -> TraitItem {
+        let mut item = if self.consuming {
+            util::create_box_trait_method(
+                self.dest_name,
+                P(system.box_self_ty()),
+                if self.body.is_some() { self.default_slot_args(system) } else { self.slot_args(system, "commands", false) },
+                None
+            )
+        } else if self.reads {
+            util::create_trait_method(
+                self.dest_name,
+                if self.body.is_some() { self.default_slot_args(system) } else { self.slot_args(system, "commands", false) },
+                self.ret.clone()
+            )
+        } else {
+            util::create_mut_trait_method(
+                self.dest_name,
+                if self.body.is_some() { self.default_slot_args(system) } else { self.slot_args(system, "commands", false) },
+                self.ret.clone()
+            )
+        };
+
+        if let TraitItemKind::Method(_, ref mut body) = item.node {
+            *body = self.body.clone();
+        }
+
+        self.trait_with_generics(item)
+    }
+
+    pub fn generate_forward(&self, system: &SystemInfo, field: Ident) -> ImplItem {
+        // self.field.signal(..);
+        let forward = util::impl_mut_method_priv(
             self.dest_name,
-            self.args.iter().map(|arg| arg.generate()).collect(),
-            None
-        )
+            self.slot_args(system, "_commands", true),
+            None,
+            P(util::create_block(
+                vec![util::create_stmt(P(util::create_method_call(
+                    self.source_name,
+                    P(util::create_self_field_expr(field)),
+                    self.args.iter().map(|arg| P(util::create_var_expr(arg.name))).collect()
+                )))],
+                None
+            ))
+        );
+
+        self.with_generics(forward)
+    }
+
+    pub fn with_generics(&self, item: ImplItem) -> ImplItem {
+        util::with_where_predicates(util::with_lifetimes(item, &self.lifetimes), &self.where_predicates)
+    }
+
+    pub fn trait_with_generics(&self, item: TraitItem) -> TraitItem {
+        util::trait_with_where_predicates(util::trait_with_lifetimes(item, &self.lifetimes), &self.where_predicates)
     }
 }
 
 impl HandlerFnArg {
-    pub fn new(name: Ident, ty: Ident, ptr: Option<Mutability>) -> HandlerFnArg {
+    pub fn new(name: Ident, ty: P<Ty>) -> HandlerFnArg {
         HandlerFnArg {
             name: name,
+            pat: None,
             ty: ty,
-            ptr: ptr
+            default: None
         }
     }
 
     pub fn generate(&self) -> Arg {
-        util::create_arg(self.name, match self.ptr {
-            Some(Mutability::Immutable) => P(util::ref_ty_from_ident(self.ty)),
-            Some(Mutability::Mutable) => P(util::mut_ref_ty_from_ident(self.ty)),
-            None => P(util::ty_from_ident(self.ty))
-        })
+        util::create_arg(self.name, self.ty.clone())
+    }
+
+    pub fn generate_pat(&self) -> Arg {
+        match self.pat {
+            Some(ref pat) => util::create_pat_arg(pat.clone(), self.ty.clone()),
+            None => self.generate()
+        }
     }
 }
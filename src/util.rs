@@ -20,8 +20,8 @@
 
 use syntax::ast::*;
 use syntax::ptr::P;
-use syntax::parse::token::{str_to_ident, InternedString};
-use syntax::codemap::{respan, Spanned, DUMMY_SP};
+use syntax::parse::token::{intern, str_to_ident, InternedString};
+use syntax::codemap::{respan, Span, Spanned, DUMMY_SP};
 use syntax::abi::Abi;
 
 fn self_arg(mutability: Mutability) -> Arg {
@@ -59,6 +59,35 @@ fn self_arg(mutability: Mutability) -> Arg {
     }
 }
 
+fn value_self_arg(mutability: Mutability) -> Arg {
+    Arg {
+        ty: P(Ty {
+            id: DUMMY_NODE_ID,
+            node: TyKind::ImplicitSelf,
+            span: DUMMY_SP
+        }),
+
+        pat: P(Pat {
+            id: DUMMY_NODE_ID,
+            node: PatKind::Ident(
+                BindingMode::ByValue(mutability),
+                Spanned {
+                    span: DUMMY_SP,
+                    node: str_to_ident("self")
+                },
+                None
+            ),
+            span: DUMMY_SP
+        }),
+
+        id: DUMMY_NODE_ID
+    }
+}
+
+fn box_self_arg(self_ty: P<Ty>) -> Arg {
+    create_arg(str_to_ident("self"), self_ty)
+}
+
 // https://github.com/rust-lang/rust/blob/213d57983d1640d22bd69e7351731fd1adcbf9b2/src/librustc_lint/bad_style.rs#L148
 fn to_snake_case(mut str: &str) -> String {
     let mut words = vec![];
@@ -97,9 +126,34 @@ pub fn ident_append(a: Ident, b: Ident) -> Ident {
     str_to_ident(&(str1 + &str2))
 }
 
+pub fn mod_ident(name: Ident) -> Ident {
+    let mut name = format!("{}", name);
+    str_to_ident(&to_snake_case(&mut name))
+}
+
+pub fn const_ident(name: Ident, suffix: &str) -> Ident {
+    let mut name = format!("{}", name);
+    str_to_ident(&(to_snake_case(&mut name).to_uppercase() + suffix))
+}
+
+// Generated fields are prefixed so that they cannot collide with anything the user defines
+pub fn field_ident(name: &str) -> Ident {
+    str_to_ident(&format!("__handlers_{}", name))
+}
+
 pub fn idxs_ident(name: Ident) -> Ident {
     let mut name = format!("{}", name);
-    ident_append(str_to_ident(&to_snake_case(&mut name)), str_to_ident("_idxs"))
+    field_ident(&(to_snake_case(&mut name) + "_idxs"))
+}
+
+pub fn enabled_ident(name: Ident) -> Ident {
+    let mut name = format!("{}", name);
+    field_ident(&(to_snake_case(&mut name) + "_enabled"))
+}
+
+pub fn set_enabled_ident(name: Ident) -> Ident {
+    let mut name = format!("{}", name);
+    str_to_ident(&format!("set_{}_enabled", to_snake_case(&mut name)))
 }
 
 pub fn as_ident(name: Ident) -> Ident {
@@ -111,6 +165,35 @@ pub fn as_mut_ident(name: Ident) -> Ident {
     ident_append(as_ident(name), str_to_ident("_mut"))
 }
 
+pub fn refs_ident(name: Ident) -> Ident {
+    let mut name = format!("{}", name);
+    str_to_ident(&format!("{}_refs", to_snake_case(&mut name)))
+}
+
+pub fn combined_iter_ident(names: &[Ident]) -> Ident {
+    let names: Vec<String> = names.iter().map(|name| to_snake_case(&format!("{}", name))).collect();
+    str_to_ident(&format!("iter_{}", names.join("_and_")))
+}
+
+pub fn iter_ident(name: Ident) -> Ident {
+    let mut name = format!("{}", name);
+    str_to_ident(&format!("{}_iter", to_snake_case(&mut name)))
+}
+
+pub fn the_ident(name: Ident) -> Ident {
+    let mut name = format!("{}", name);
+    ident_append(str_to_ident("the_"), str_to_ident(&to_snake_case(&mut name)))
+}
+
+pub fn the_mut_ident(name: Ident) -> Ident {
+    ident_append(the_ident(name), str_to_ident("_mut"))
+}
+
+pub fn into_ident(name: Ident) -> Ident {
+    let mut name = format!("{}", name);
+    ident_append(str_to_ident("into_"), str_to_ident(&to_snake_case(&mut name)))
+}
+
 pub fn ty_from_ident(name: Ident) -> Ty {
     Ty {
         id: DUMMY_NODE_ID,
@@ -154,6 +237,48 @@ pub fn ref_ty(ty: P<Ty>) -> Ty {
     }
 }
 
+pub fn static_ref_ty(ty: P<Ty>) -> Ty {
+    lifetime_ref_ty(create_lifetime("'static"), ty)
+}
+
+pub fn lifetime_ref_ty(lifetime: Lifetime, ty: P<Ty>) -> Ty {
+    Ty {
+        id: DUMMY_NODE_ID,
+        node: TyKind::Rptr(
+            Some(lifetime),
+            MutTy {
+                ty: ty,
+                mutbl: Mutability::Immutable
+            }
+        ),
+        span: DUMMY_SP
+    }
+}
+
+pub fn slice_ty(ty: P<Ty>) -> Ty {
+    Ty {
+        id: DUMMY_NODE_ID,
+        node: TyKind::Vec(ty),
+        span: DUMMY_SP
+    }
+}
+
+pub fn array_ty(ty: P<Ty>, len: usize) -> Ty {
+    Ty {
+        id: DUMMY_NODE_ID,
+        node: TyKind::FixedLengthVec(ty, P(create_num_expr(len as u64))),
+        span: DUMMY_SP
+    }
+}
+
+pub fn tuple_ty(tys: Vec<P<Ty>>) -> Ty {
+    Ty {
+        id: DUMMY_NODE_ID,
+        node: TyKind::Tup(tys),
+        span: DUMMY_SP
+    }
+}
+
 pub fn mut_ref_ty_from_ident(name: Ident) -> Ty {
     Ty {
         id: DUMMY_NODE_ID,
@@ -201,7 +326,67 @@ pub fn param_ty_from_ident(name: Ident, ty: Ty) -> Ty {
     }
 }
 
+pub fn params_ty_from_ident(name: Ident, tys: Vec<Ty>) -> Ty {
+    Ty {
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        node: TyKind::Path(None, Path {
+            span: DUMMY_SP,
+            global: false,
+            segments: vec![PathSegment {
+                identifier: name,
+                parameters: PathParameters::AngleBracketed(AngleBracketedParameterData {
+                    lifetimes: Vec::new(),
+                    types: P::from_vec(tys.into_iter().map(|ty| P(ty)).collect()),
+                    bindings: P::from_vec(Vec::new())
+                })
+            }]
+        })
+    }
+}
+
 pub fn path_param_ty(names: Vec<Ident>, ty: Ty) -> Ty {
+    path_params_ty(names, vec![ty])
+}
+
+pub fn path_params_ty(names: Vec<Ident>, tys: Vec<Ty>) -> Ty {
+    path_lifetime_params_ty(names, Vec::new(), tys)
+}
+
+pub fn path_lifetime_params_ty(names: Vec<Ident>, lifetimes: Vec<Lifetime>, tys: Vec<Ty>) -> Ty {
+    create_path_params_ty(names, lifetimes, tys, false)
+}
+
+pub fn global_path_param_ty(names: Vec<Ident>, ty: Ty) -> Ty {
+    global_path_params_ty(names, vec![ty])
+}
+
+pub fn global_path_params_ty(names: Vec<Ident>, tys: Vec<Ty>) -> Ty {
+    global_path_lifetime_params_ty(names, Vec::new(), tys)
+}
+
+pub fn global_path_lifetime_params_ty(names: Vec<Ident>, lifetimes: Vec<Lifetime>, tys: Vec<Ty>) -> Ty {
+    create_path_params_ty(names, lifetimes, tys, true)
+}
+
+fn create_path_params_ty(names: Vec<Ident>, lifetimes: Vec<Lifetime>, tys: Vec<Ty>, global: bool) -> Ty {
+    Ty {
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        node: TyKind::Path(None, lifetime_params_path(names, lifetimes, tys, global))
+    }
+}
+
+pub fn lifetime_ty_from_ident(name: Ident, lifetime: Lifetime) -> Ty {
+    create_path_params_ty(vec![name], vec![lifetime], Vec::new(), false)
+}
+
+// Only std traits are implemented through a path, so it always starts from the root
+fn params_path(names: Vec<Ident>, tys: Vec<Ty>) -> Path {
+    lifetime_params_path(names, Vec::new(), tys, true)
+}
+
+fn lifetime_params_path(names: Vec<Ident>, lifetimes: Vec<Lifetime>, tys: Vec<Ty>, global: bool) -> Path {
     let mut segments: Vec<PathSegment> = names.iter().map(|name| PathSegment {
         identifier: *name,
         parameters: PathParameters::none()
@@ -210,19 +395,38 @@ pub fn path_param_ty(names: Vec<Ident>, ty: Ty) -> Ty {
     {
         let mut last = segments.last_mut().unwrap();
         last.parameters = PathParameters::AngleBracketed(AngleBracketedParameterData {
-            lifetimes: Vec::new(),
-            types: P::from_vec(vec![P(ty)]),
+            lifetimes: lifetimes,
+            types: P::from_vec(tys.into_iter().map(|ty| P(ty)).collect()),
             bindings: P::from_vec(Vec::new())
         });
     }
 
+    Path {
+        span: DUMMY_SP,
+        global: global,
+        segments: segments
+    }
+}
+
+pub fn path_ty(names: Vec<Ident>) -> Ty {
+    create_path_ty(names, false)
+}
+
+pub fn global_path_ty(names: Vec<Ident>) -> Ty {
+    create_path_ty(names, true)
+}
+
+fn create_path_ty(names: Vec<Ident>, global: bool) -> Ty {
     Ty {
         id: DUMMY_NODE_ID,
         span: DUMMY_SP,
         node: TyKind::Path(None, Path {
             span: DUMMY_SP,
-            global: false,
-            segments: segments
+            global: global,
+            segments: names.into_iter().map(|name| PathSegment {
+                identifier: name,
+                parameters: PathParameters::none()
+            }).collect()
         })
     }
 }
@@ -329,6 +533,10 @@ pub fn create_tuple_struct(name: Ident, fields: Vec<P<Ty>>) -> Item {
 }
 
 pub fn create_struct(name: Ident, fields: Vec<StructField>) -> Item {
+    create_generic_struct(name, Default::default(), fields)
+}
+
+pub fn create_generic_struct(name: Ident, generics: Generics, fields: Vec<StructField>) -> Item {
     Item {
         ident: name,
         attrs: Vec::new(),
@@ -337,6 +545,48 @@ pub fn create_struct(name: Ident, fields: Vec<StructField>) -> Item {
                 fields,
                 DUMMY_NODE_ID
             ),
+            generics
+        ),
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        vis: Visibility::Public
+    }
+}
+
+pub fn create_const(name: Ident, ty: P<Ty>, expr: P<Expr>) -> Item {
+    Item {
+        ident: name,
+        attrs: Vec::new(),
+        node: ItemKind::Const(ty, expr),
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        vis: Visibility::Public
+    }
+}
+
+pub fn create_enum(name: Ident, variants: Vec<(Ident, Vec<P<Ty>>)>) -> Item {
+    Item {
+        ident: name,
+        attrs: Vec::new(),
+        node: ItemKind::Enum(
+            EnumDef {
+                variants: variants.into_iter().map(|(name, fields)| respan(DUMMY_SP, Variant_ {
+                    name: name,
+                    attrs: Vec::new(),
+                    data: VariantData::Tuple(
+                        fields.into_iter().map(|ty| StructField {
+                            span: DUMMY_SP,
+                            ident: None,
+                            vis: Visibility::Inherited,
+                            id: DUMMY_NODE_ID,
+                            ty: ty,
+                            attrs: Vec::new()
+                        }).collect(),
+                        DUMMY_NODE_ID
+                    ),
+                    disr_expr: None
+                })).collect()
+            },
             Default::default()
         ),
         id: DUMMY_NODE_ID,
@@ -346,9 +596,8 @@ pub fn create_struct(name: Ident, fields: Vec<StructField>) -> Item {
 }
 
 pub fn create_arg(name: Ident, ty: P<Ty>) -> Arg {
-    Arg {
-        ty: ty,
-        pat: P(Pat {
+    create_pat_arg(
+        P(Pat {
             id: DUMMY_NODE_ID,
             node: PatKind::Ident(
                 BindingMode::ByValue(Mutability::Immutable),
@@ -357,10 +606,41 @@ pub fn create_arg(name: Ident, ty: P<Ty>) -> Arg {
             ),
             span: DUMMY_SP
         }),
+        ty
+    )
+}
+
+pub fn create_mut_arg(name: Ident, ty: P<Ty>) -> Arg {
+    create_pat_arg(
+        P(Pat {
+            id: DUMMY_NODE_ID,
+            node: PatKind::Ident(
+                BindingMode::ByValue(Mutability::Mutable),
+                respan(DUMMY_SP, name),
+                None
+            ),
+            span: DUMMY_SP
+        }),
+        ty
+    )
+}
+
+pub fn create_pat_arg(pat: P<Pat>, ty: P<Ty>) -> Arg {
+    Arg {
+        ty: ty,
+        pat: pat,
         id: DUMMY_NODE_ID
     }
 }
 
+pub fn create_closure_arg(name: Ident) -> Arg {
+    create_arg(name, P(Ty {
+        id: DUMMY_NODE_ID,
+        node: TyKind::Infer,
+        span: DUMMY_SP
+    }))
+}
+
 pub fn create_mut_trait_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>) -> TraitItem {
     let mut args = args;
     args.insert(0, self_arg(Mutability::Mutable));
@@ -421,6 +701,70 @@ pub fn create_trait_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>) -> T
     }
 }
 
+// The signature of an inherent method, as a required trait method
+pub fn trait_method_from_impl(item: &ImplItem) -> TraitItem {
+    let sig = match item.node {
+        ImplItemKind::Method(ref sig, _) => sig.clone(),
+        _ => panic!("Expected a method")
+    };
+
+    TraitItem {
+        id: DUMMY_NODE_ID,
+        ident: item.ident,
+        attrs: item.attrs.clone(),
+        node: TraitItemKind::Method(sig, None),
+        span: DUMMY_SP
+    }
+}
+
+// Implements a trait method by calling the inherent method it was made from
+pub fn forward_impl_method(item: &ImplItem, args: Vec<P<Expr>>) -> ImplItem {
+    let mut item = item.clone();
+    let name = item.ident;
+
+    item.vis = Visibility::Inherited;
+    item.attrs = Vec::new();
+
+    if let ImplItemKind::Method(_, ref mut block) = item.node {
+        *block = P(create_block(
+            Vec::new(),
+            Some(P(create_method_call(name, P(create_var_expr(str_to_ident("self"))), args)))
+        ));
+    }
+
+    item
+}
+
+pub fn create_box_trait_method(name: Ident, self_ty: P<Ty>, args: Vec<Arg>, ret: Option<P<Ty>>) -> TraitItem {
+    let mut args = args;
+    args.insert(0, box_self_arg(self_ty));
+
+    TraitItem {
+        id: DUMMY_NODE_ID,
+        ident: name,
+        attrs: Vec::new(),
+        node: TraitItemKind::Method(
+            MethodSig {
+                unsafety: Unsafety::Normal,
+                constness: Constness::NotConst,
+                abi: Abi::Rust,
+                decl: P(FnDecl {
+                    inputs: args,
+                    output: if let Some(ty) = ret {
+                        FunctionRetTy::Ty(ty)
+                    } else {
+                        FunctionRetTy::Default(DUMMY_SP)
+                    },
+                    variadic: false
+                }),
+                generics: Default::default(),
+            },
+            None
+        ),
+        span: DUMMY_SP
+    }
+}
+
 pub fn create_block_expr(block: P<Block>) -> Expr {
     Expr {
         id: DUMMY_NODE_ID,
@@ -460,11 +804,47 @@ pub fn create_var_expr(name: Ident) -> Expr {
     }
 }
 
+pub fn create_path_expr(names: Vec<Ident>) -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::Path(
+            None,
+            Path {
+                span: DUMMY_SP,
+                global: true,
+                segments: names.into_iter().map(|name| PathSegment {
+                    identifier: name,
+                    parameters: PathParameters::none()
+                }).collect()
+            }
+        ),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
+pub fn create_addr_of_expr(mutability: Mutability, expr: P<Expr>) -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::AddrOf(mutability, expr),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
 pub fn create_closure_expr(args: Vec<Arg>, block: P<Block>) -> Expr {
+    create_capture_closure_expr(CaptureBy::Value, args, block)
+}
+
+pub fn create_ref_closure_expr(args: Vec<Arg>, block: P<Block>) -> Expr {
+    create_capture_closure_expr(CaptureBy::Ref, args, block)
+}
+
+fn create_capture_closure_expr(capture: CaptureBy, args: Vec<Arg>, block: P<Block>) -> Expr {
     Expr {
         id: DUMMY_NODE_ID,
         node: ExprKind::Closure(
-            CaptureBy::Value,
+            capture,
             P(FnDecl {
                 inputs: args,
                 output: FunctionRetTy::Default(DUMMY_SP),
@@ -490,13 +870,43 @@ pub fn create_tuple_field_expr(expr: P<Expr>, field: usize) -> Expr {
     }
 }
 
-pub fn create_num_expr(num: u64) -> Expr {
+pub fn create_tuple_expr(exprs: Vec<P<Expr>>) -> Expr {
     Expr {
         id: DUMMY_NODE_ID,
-        node: ExprKind::Lit(P(respan(DUMMY_SP, LitKind::Int(
-            num,
-            LitIntType::Unsuffixed
-        )))),
+        node: ExprKind::Tup(exprs),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
+pub fn create_num_expr(num: u64) -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::Lit(P(respan(DUMMY_SP, LitKind::Int(
+            num,
+            LitIntType::Unsuffixed
+        )))),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
+pub fn create_str_expr(s: InternedString) -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::Lit(P(respan(DUMMY_SP, LitKind::Str(
+            s,
+            StrStyle::Cooked
+        )))),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
+pub fn create_array_expr(exprs: Vec<P<Expr>>) -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::Vec(exprs),
         span: DUMMY_SP,
         attrs: None
     }
@@ -525,10 +935,14 @@ pub fn create_struct_expr(name: Ident, fields: Vec<Field>) -> Expr {
 }
 
 pub fn create_deref_expr(expr: P<Expr>) -> Expr {
+    create_unary_expr(UnOp::Deref, expr)
+}
+
+pub fn create_unary_expr(op: UnOp, expr: P<Expr>) -> Expr {
     Expr {
         id: DUMMY_NODE_ID,
         node: ExprKind::Unary(
-            UnOp::Deref,
+            op,
             expr
         ),
         span: DUMMY_SP,
@@ -587,6 +1001,24 @@ pub fn create_if_let_expr(p: P<Pat>, i: P<Expr>, t: P<Block>, e: Option<P<Expr>>
     }
 }
 
+pub fn create_match_expr(expr: P<Expr>, arms: Vec<Arm>) -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::Match(expr, arms),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
+pub fn create_arm(pat: P<Pat>, body: P<Expr>) -> Arm {
+    Arm {
+        attrs: Vec::new(),
+        pats: vec![pat],
+        guard: None,
+        body: body
+    }
+}
+
 pub fn create_loop_expr(block: P<Block>) -> Expr {
     Expr {
         id: DUMMY_NODE_ID,
@@ -596,6 +1028,24 @@ pub fn create_loop_expr(block: P<Block>) -> Expr {
     }
 }
 
+pub fn create_range_expr(from: P<Expr>, to: P<Expr>) -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::Range(Some(from), Some(to), RangeLimits::HalfOpen),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
+pub fn create_full_range_expr() -> Expr {
+    Expr {
+        id: DUMMY_NODE_ID,
+        node: ExprKind::Range(None, None, RangeLimits::HalfOpen),
+        span: DUMMY_SP,
+        attrs: None
+    }
+}
+
 pub fn create_for_expr(name: Ident, range: P<Expr>, block: P<Block>) -> Expr {
     Expr {
         id: DUMMY_NODE_ID,
@@ -646,6 +1096,10 @@ pub fn create_assignop_expr(left: P<Expr>, op: BinOpKind, right: P<Expr>) -> Exp
 }
 
 pub fn impl_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
+    impl_generic_method(name, Default::default(), args, ret, block)
+}
+
+pub fn impl_generic_method(name: Ident, generics: Generics, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
     let mut args = args;
     args.insert(0, self_arg(Mutability::Immutable));
 
@@ -670,7 +1124,7 @@ pub fn impl_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Blo
                     },
                     variadic: false
                 }),
-                generics: Default::default(),
+                generics: generics,
             },
             block
         )
@@ -709,6 +1163,12 @@ pub fn impl_method_priv(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block:
     }
 }
 
+pub fn impl_static_method_priv(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
+    let mut item = impl_static_method(name, args, ret, block);
+    item.vis = Visibility::Inherited;
+    item
+}
+
 pub fn impl_static_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
     ImplItem {
         id: DUMMY_NODE_ID,
@@ -739,6 +1199,10 @@ pub fn impl_static_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block
 }
 
 pub fn impl_mut_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
+    impl_mut_generic_method(name, Default::default(), args, ret, block)
+}
+
+pub fn impl_mut_generic_method(name: Ident, generics: Generics, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
     let mut args = args;
     args.insert(0, self_arg(Mutability::Mutable));
 
@@ -749,6 +1213,70 @@ pub fn impl_mut_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P
         defaultness: Defaultness::Final,
         attrs: Vec::new(),
         span: DUMMY_SP,
+        node: ImplItemKind::Method(
+            MethodSig {
+                unsafety: Unsafety::Normal,
+                constness: Constness::NotConst,
+                abi: Abi::Rust,
+                decl: P(FnDecl {
+                    inputs: args,
+                    output: if let Some(ty) = ret {
+                        FunctionRetTy::Ty(ty)
+                    } else {
+                        FunctionRetTy::Default(DUMMY_SP)
+                    },
+                    variadic: false
+                }),
+                generics: generics,
+            },
+            block
+        )
+    }
+}
+
+pub fn impl_value_method(name: Ident, mutability: Mutability, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
+    let mut args = args;
+    args.insert(0, value_self_arg(mutability));
+
+    ImplItem {
+        id: DUMMY_NODE_ID,
+        ident: name,
+        vis: Visibility::Public,
+        defaultness: Defaultness::Final,
+        attrs: Vec::new(),
+        span: DUMMY_SP,
+        node: ImplItemKind::Method(
+            MethodSig {
+                unsafety: Unsafety::Normal,
+                constness: Constness::NotConst,
+                abi: Abi::Rust,
+                decl: P(FnDecl {
+                    inputs: args,
+                    output: if let Some(ty) = ret {
+                        FunctionRetTy::Ty(ty)
+                    } else {
+                        FunctionRetTy::Default(DUMMY_SP)
+                    },
+                    variadic: false
+                }),
+                generics: Default::default(),
+            },
+            block
+        )
+    }
+}
+
+pub fn impl_box_method_priv(name: Ident, self_ty: P<Ty>, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
+    let mut args = args;
+    args.insert(0, box_self_arg(self_ty));
+
+    ImplItem {
+        id: DUMMY_NODE_ID,
+        ident: name,
+        vis: Visibility::Inherited,
+        defaultness: Defaultness::Final,
+        attrs: Vec::new(),
+        span: DUMMY_SP,
         node: ImplItemKind::Method(
             MethodSig {
                 unsafety: Unsafety::Normal,
@@ -771,6 +1299,10 @@ pub fn impl_mut_method(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P
 }
 
 pub fn impl_mut_method_priv(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
+    impl_mut_generic_method_priv(name, Default::default(), args, ret, block)
+}
+
+pub fn impl_mut_generic_method_priv(name: Ident, generics: Generics, args: Vec<Arg>, ret: Option<P<Ty>>, block: P<Block>) -> ImplItem {
     let mut args = args;
     args.insert(0, self_arg(Mutability::Mutable));
 
@@ -795,7 +1327,7 @@ pub fn impl_mut_method_priv(name: Ident, args: Vec<Arg>, ret: Option<P<Ty>>, blo
                     },
                     variadic: false
                 }),
-                generics: Default::default(),
+                generics: generics,
             },
             block
         )
@@ -874,7 +1406,23 @@ pub fn create_return_block(expr: Option<P<Expr>>) -> Block {
     )
 }
 
+pub fn create_break_block() -> Block {
+    create_block(
+        vec![create_stmt(P(Expr {
+            id: DUMMY_NODE_ID,
+            node: ExprKind::Break(None),
+            span: DUMMY_SP,
+            attrs: None
+        }))],
+        None
+    )
+}
+
 pub fn create_impl(name: Ident, tr: Option<Ident>, items: Vec<ImplItem>) -> Item {
+    create_path_impl(name, tr.map(|name| vec![name]), items)
+}
+
+pub fn create_path_impl(name: Ident, tr: Option<Vec<Ident>>, items: Vec<ImplItem>) -> Item {
     Item {
         ident: name,
         attrs: Vec::new(),
@@ -882,16 +1430,14 @@ pub fn create_impl(name: Ident, tr: Option<Ident>, items: Vec<ImplItem>) -> Item
             Unsafety::Normal,
             ImplPolarity::Positive,
             Default::default(),
-            tr.map(|name| TraitRef {
+            tr.map(|names| TraitRef {
                 path: Path {
                     span: DUMMY_SP,
                     global: false,
-                    segments: vec![
-                        PathSegment {
-                            identifier: name,
-                            parameters: PathParameters::none()
-                        }
-                    ]
+                    segments: names.into_iter().map(|name| PathSegment {
+                        identifier: name,
+                        parameters: PathParameters::none()
+                    }).collect()
                 },
                 ref_id: DUMMY_NODE_ID
             }),
@@ -904,33 +1450,58 @@ pub fn create_impl(name: Ident, tr: Option<Ident>, items: Vec<ImplItem>) -> Item
     }
 }
 
-pub fn create_trait(name: Ident, reqs: &Vec<Ident>, items: &Vec<TraitItem>) -> Item {
+pub fn create_param_path_impl(name: Ident, tr: Vec<Ident>, params: Vec<Ty>, items: Vec<ImplItem>) -> Item {
+    create_generic_param_path_impl(name, Default::default(), ty_from_ident(name), tr, params, items)
+}
+
+pub fn create_generic_param_path_impl(name: Ident, generics: Generics, ty: Ty, tr: Vec<Ident>, params: Vec<Ty>, items: Vec<ImplItem>) -> Item {
     Item {
         ident: name,
         attrs: Vec::new(),
-        node: ItemKind::Trait(
+        node: ItemKind::Impl(
             Unsafety::Normal,
+            ImplPolarity::Positive,
+            generics,
+            Some(TraitRef {
+                path: params_path(tr, params),
+                ref_id: DUMMY_NODE_ID
+            }),
+            P(ty),
+            items
+        ),
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        vis: Visibility::Inherited
+    }
+}
+
+pub fn impl_type(name: Ident, ty: P<Ty>) -> ImplItem {
+    ImplItem {
+        id: DUMMY_NODE_ID,
+        ident: name,
+        vis: Visibility::Inherited,
+        defaultness: Defaultness::Final,
+        attrs: Vec::new(),
+        span: DUMMY_SP,
+        node: ImplItemKind::Type(ty)
+    }
+}
+
+pub fn create_trait(name: Ident, reqs: &Vec<Ident>, lifetimes: &Vec<Lifetime>, items: &Vec<TraitItem>) -> Item {
+    create_unsafety_trait(Unsafety::Normal, name, reqs, lifetimes, items)
+}
+
+pub fn create_unsafety_trait(unsafety: Unsafety, name: Ident, reqs: &Vec<Ident>, lifetimes: &Vec<Lifetime>, items: &Vec<TraitItem>) -> Item {
+    let mut bounds: Vec<TyParamBound> = reqs.iter().map(|req| trait_bound(*req, DUMMY_SP)).collect();
+    bounds.extend(lifetimes.iter().map(|lifetime| TyParamBound::RegionTyParamBound(*lifetime)));
+
+    Item {
+        ident: name,
+        attrs: Vec::new(),
+        node: ItemKind::Trait(
+            unsafety,
             Default::default(),
-            P::from_vec(reqs.iter().map(|req| TyParamBound::TraitTyParamBound(
-                PolyTraitRef {
-                    bound_lifetimes: Vec::new(),
-                    trait_ref: TraitRef {
-                        path: Path {
-                            span: DUMMY_SP,
-                            global: false,
-                            segments: vec![
-                                PathSegment {
-                                    identifier: *req,
-                                    parameters: PathParameters::none()
-                                }
-                            ]
-                        },
-                        ref_id: DUMMY_NODE_ID
-                    },
-                    span: DUMMY_SP
-                },
-                TraitBoundModifier::None
-            )).collect()),
+            P::from_vec(bounds),
             items.clone()
         ),
         id: DUMMY_NODE_ID,
@@ -939,6 +1510,146 @@ pub fn create_trait(name: Ident, reqs: &Vec<Ident>, items: &Vec<TraitItem>) -> I
     }
 }
 
+pub fn trait_bound(name: Ident, span: Span) -> TyParamBound {
+    TyParamBound::TraitTyParamBound(
+        PolyTraitRef {
+            bound_lifetimes: Vec::new(),
+            trait_ref: TraitRef {
+                path: Path {
+                    span: span,
+                    global: false,
+                    segments: vec![
+                        PathSegment {
+                            identifier: name,
+                            parameters: PathParameters::none()
+                        }
+                    ]
+                },
+                ref_id: DUMMY_NODE_ID
+            },
+            span: span
+        },
+        TraitBoundModifier::None
+    )
+}
+
+pub fn create_lifetime(name: &str) -> Lifetime {
+    Lifetime {
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        name: intern(name)
+    }
+}
+
+pub fn lifetime_bound(name: &str) -> TyParamBound {
+    TyParamBound::RegionTyParamBound(create_lifetime(name))
+}
+
+pub fn create_lifetime_generics(lifetimes: &Vec<Lifetime>) -> Generics {
+    Generics {
+        lifetimes: lifetime_defs(lifetimes),
+        ..Default::default()
+    }
+}
+
+pub fn create_fn(name: Ident, generics: Generics, args: Vec<Arg>, block: P<Block>) -> Item {
+    Item {
+        ident: name,
+        attrs: Vec::new(),
+        node: ItemKind::Fn(
+            P(FnDecl {
+                inputs: args,
+                output: FunctionRetTy::Default(DUMMY_SP),
+                variadic: false
+            }),
+            Unsafety::Normal,
+            Constness::NotConst,
+            Abi::Rust,
+            generics,
+            block
+        ),
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        vis: Visibility::Inherited
+    }
+}
+
+pub fn create_ty_param(name: Ident, bounds: Vec<TyParamBound>) -> TyParam {
+    TyParam {
+        ident: name,
+        id: DUMMY_NODE_ID,
+        bounds: P::from_vec(bounds),
+        default: None,
+        span: DUMMY_SP
+    }
+}
+
+pub fn create_generics(ty_params: Vec<TyParam>) -> Generics {
+    Generics {
+        ty_params: P::from_vec(ty_params),
+        ..Default::default()
+    }
+}
+
+pub fn fn_bound(name: Ident, inputs: Vec<P<Ty>>, output: Option<P<Ty>>) -> TyParamBound {
+    TyParamBound::TraitTyParamBound(
+        PolyTraitRef {
+            bound_lifetimes: Vec::new(),
+            trait_ref: TraitRef {
+                path: Path {
+                    span: DUMMY_SP,
+                    global: false,
+                    segments: vec![
+                        PathSegment {
+                            identifier: name,
+                            parameters: PathParameters::Parenthesized(ParenthesizedParameterData {
+                                span: DUMMY_SP,
+                                inputs: inputs,
+                                output: output
+                            })
+                        }
+                    ]
+                },
+                ref_id: DUMMY_NODE_ID
+            },
+            span: DUMMY_SP
+        },
+        TraitBoundModifier::None
+    )
+}
+
+pub fn binding_bound(name: Ident, item: Ident, ty: P<Ty>) -> TyParamBound {
+    TyParamBound::TraitTyParamBound(
+        PolyTraitRef {
+            bound_lifetimes: Vec::new(),
+            trait_ref: TraitRef {
+                path: Path {
+                    span: DUMMY_SP,
+                    global: false,
+                    segments: vec![
+                        PathSegment {
+                            identifier: name,
+                            parameters: PathParameters::AngleBracketed(AngleBracketedParameterData {
+                                lifetimes: Vec::new(),
+                                types: P::empty(),
+                                bindings: P::from_vec(vec![TypeBinding {
+                                    id: DUMMY_NODE_ID,
+                                    ident: item,
+                                    ty: ty,
+                                    span: DUMMY_SP
+                                }])
+                            })
+                        }
+                    ]
+                },
+                ref_id: DUMMY_NODE_ID
+            },
+            span: DUMMY_SP
+        },
+        TraitBoundModifier::None
+    )
+}
+
 pub fn create_field(name: Ident, value: P<Expr>) -> Field {
     Field {
         ident: respan(DUMMY_SP, name),
@@ -999,17 +1710,75 @@ pub fn create_stmt(expr: P<Expr>) -> Stmt {
     respan(DUMMY_SP, StmtKind::Semi(expr, DUMMY_NODE_ID))
 }
 
+fn create_use_item(view_path: ViewPath_, vis: Visibility) -> Item {
+    Item {
+        ident: str_to_ident(""),
+        attrs: Vec::new(),
+        node: ItemKind::Use(P(respan(DUMMY_SP, view_path))),
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        vis: vis
+    }
+}
+
+pub fn create_use_glob_item(names: Vec<Ident>, global: bool) -> Item {
+    create_use_item(ViewPath_::ViewPathGlob(Path {
+        span: DUMMY_SP,
+        global: global,
+        segments: names.into_iter().map(|name| PathSegment {
+            identifier: name,
+            parameters: PathParameters::none()
+        }).collect()
+    }), Visibility::Inherited)
+}
+
+pub fn create_pub_use_item(names: Vec<Ident>) -> Item {
+    create_use_item(ViewPath_::ViewPathSimple(*names.last().unwrap(), Path {
+        span: DUMMY_SP,
+        global: false,
+        segments: names.into_iter().map(|name| PathSegment {
+            identifier: name,
+            parameters: PathParameters::none()
+        }).collect()
+    }), Visibility::Public)
+}
+
+pub fn create_use_glob_stmt(names: Vec<Ident>) -> Stmt {
+    respan(DUMMY_SP, StmtKind::Decl(
+        P(respan(DUMMY_SP, DeclKind::Item(P(create_use_glob_item(names, true))))),
+        DUMMY_NODE_ID
+    ))
+}
+
+pub fn create_mod(name: Ident, items: Vec<P<Item>>) -> Item {
+    Item {
+        ident: name,
+        attrs: Vec::new(),
+        node: ItemKind::Mod(Mod {
+            inner: DUMMY_SP,
+            items: items
+        }),
+        id: DUMMY_NODE_ID,
+        span: DUMMY_SP,
+        vis: Visibility::Public
+    }
+}
+
 pub fn create_tuple_struct_pat(name: Ident, bindings: Vec<Ident>) -> Pat {
+    create_path_tuple_struct_pat(vec![name], bindings)
+}
+
+pub fn create_path_tuple_struct_pat(names: Vec<Ident>, bindings: Vec<Ident>) -> Pat {
     Pat {
         id: DUMMY_NODE_ID,
         node: PatKind::TupleStruct(
             Path {
                 span: DUMMY_SP,
                 global: false,
-                segments: vec![PathSegment {
+                segments: names.into_iter().map(|name| PathSegment {
                     identifier: name,
                     parameters: PathParameters::none()
-                }]
+                }).collect()
             },
             bindings.into_iter().map(|ident| P(Pat {
                 id: DUMMY_NODE_ID,
@@ -1027,13 +1796,97 @@ pub fn create_tuple_struct_pat(name: Ident, bindings: Vec<Ident>) -> Pat {
 }
 
 pub fn create_derive(items: Vec<InternedString>) -> Attribute {
+    create_list_attr(InternedString::new("derive"), items)
+}
+
+pub fn create_word_attr(name: InternedString) -> Attribute {
+    respan(DUMMY_SP, Attribute_ {
+        id: AttrId(0),
+        style: AttrStyle::Outer,
+        value: P(respan(DUMMY_SP, MetaItemKind::Word(name))),
+        is_sugared_doc: false
+    })
+}
+
+fn lifetime_defs(lifetimes: &Vec<Lifetime>) -> Vec<LifetimeDef> {
+    lifetimes.iter().map(|lifetime| LifetimeDef {
+        lifetime: *lifetime,
+        bounds: Vec::new()
+    }).collect()
+}
+
+pub fn with_lifetimes(mut item: ImplItem, lifetimes: &Vec<Lifetime>) -> ImplItem {
+    if let ImplItemKind::Method(ref mut sig, _) = item.node {
+        sig.generics.lifetimes.extend(lifetime_defs(lifetimes));
+    }
+
+    item
+}
+
+pub fn trait_with_lifetimes(mut item: TraitItem, lifetimes: &Vec<Lifetime>) -> TraitItem {
+    if let TraitItemKind::Method(ref mut sig, _) = item.node {
+        sig.generics.lifetimes.extend(lifetime_defs(lifetimes));
+    }
+
+    item
+}
+
+pub fn with_where_predicates(mut item: ImplItem, predicates: &Vec<WherePredicate>) -> ImplItem {
+    if let ImplItemKind::Method(ref mut sig, _) = item.node {
+        sig.generics.where_clause.predicates.extend(predicates.iter().cloned());
+    }
+
+    item
+}
+
+pub fn trait_with_where_predicates(mut item: TraitItem, predicates: &Vec<WherePredicate>) -> TraitItem {
+    if let TraitItemKind::Method(ref mut sig, _) = item.node {
+        sig.generics.where_clause.predicates.extend(predicates.iter().cloned());
+    }
+
+    item
+}
+
+pub fn must_use(mut item: ImplItem) -> ImplItem {
+    item.attrs.push(create_word_attr(InternedString::new("must_use")));
+    item
+}
+
+pub fn create_list_attr(name: InternedString, items: Vec<InternedString>) -> Attribute {
     respan(DUMMY_SP, Attribute_ {
         id: AttrId(0),
         style: AttrStyle::Outer,
         value: P(respan(DUMMY_SP, MetaItemKind::List(
-            InternedString::new("derive"),
+            name,
             items.into_iter().map(|item| P(respan(DUMMY_SP, MetaItemKind::Word(item)))).collect()
         ))),
         is_sugared_doc: false
     })
 }
+
+// #[cfg(flag)], or #[cfg(not(flag))] when the item is for builds without it
+pub fn create_cfg_attr(flag: &'static str, enabled: bool) -> Attribute {
+    let mut meta = P(respan(DUMMY_SP, MetaItemKind::Word(InternedString::new(flag))));
+    if !enabled {
+        meta = P(respan(DUMMY_SP, MetaItemKind::List(InternedString::new("not"), vec![meta])));
+    }
+
+    create_cfg_meta_attr(meta)
+}
+
+pub fn create_cfg_feature_attr(feature: InternedString) -> Attribute {
+    // feature = "name"
+    create_cfg_meta_attr(P(respan(DUMMY_SP, MetaItemKind::NameValue(
+        InternedString::new("feature"),
+        respan(DUMMY_SP, LitKind::Str(feature, StrStyle::Cooked))
+    ))))
+}
+
+fn create_cfg_meta_attr(meta: P<MetaItem>) -> Attribute {
+    respan(DUMMY_SP, Attribute_ {
+        id: AttrId(0),
+        style: AttrStyle::Outer,
+        value: P(respan(DUMMY_SP, MetaItemKind::List(InternedString::new("cfg"), vec![meta]))),
+        is_sugared_doc: false
+    })
+}
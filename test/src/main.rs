@@ -1,24 +1,231 @@
-#![feature(plugin, box_syntax)]
+#![feature(plugin, box_syntax, rustc_private, alloc, collections)]
 #![plugin(handlers)]
 
+extern crate alloc;
+extern crate collections;
+extern crate rayon;
+extern crate handlers;
+extern crate syntax;
+
+use std::any::Any;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+mod camera {
+    use super::scene_prelude::*;
+
+    pub struct Tripod;
+
+    impl Camera for Tripod {
+        fn on_look(&mut self) {
+            println!("Looking from the tripod");
+        }
+    }
+}
+
 pub trait Renderable {
     fn render(&self);
     fn update(&mut self, x: i64);
+    fn id(&self) -> u64;
+}
+
+pub trait Visitor {
+    fn visit(&mut self, n: i64);
+}
+
+pub struct Counter {
+    pub visited: usize
+}
+
+impl Visitor for Counter {
+    fn visit(&mut self, _: i64) {
+        self.visited += 1;
+    }
 }
 
 handlers_define_system! System {
-    * : Renderable;
+    * : Renderable, 'static;
+    id_fn: id;
+    downcast;
+    signals_trait;
 
     MouseHandler {
-        click(x: u64, y: u64) => on_click;
-        hover() => on_hover
+        /// Fired when the mouse is clicked at the given position.
+        click(x: u64, y: u64 = 0) => on_click;
+        hover() => on_hover;
+        label(text: String) => on_label { drop(text) }
     }
 
+    InputHandler {
+        input(input: char) => on_input;
+        feed(data: &[u8], weights: [i64; 2]) => on_feed;
+        focus(target: Option<SystemIndex>) => on_focus;
+        accept(visitor: &mut Visitor) => on_accept;
+        single deliver(message: Box<String>) => on_deliver;
+        reads log() => on_log;
+        pick<'a>(first: &'a str, second: &'a str) -> &'a str => on_pick { first };
+        outlive<'a, 'b>(long: &'a str, short: &'b str) -> &'b str => on_outlive where 'a: 'b { long };
+        echo(value: i64) -> i64 => on_echo { value };
+        drag((x, y): (i64, i64)) => on_drag { println!("Dragged to {}, {}", x, y) };
+        caption(text: String) => on_caption { drop(text) }
+    }
+
+    batch press { click; input }
+    batch annotate { label; caption }
+
+    phase gather { input }
+    phase settle { hover }
+}
+
+handlers_define_system! Empty {
+    builder;
+    from_object;
+}
+
+handlers_define_system! Prefixed {
+    prefix_handlers;
+
+    InputHandler {
+        input(&mut self, input: char) => on_prefixed_input;
+        peek(&self) -> i64 => on_peek
+    }
+}
+
+handlers_define_systems! {
+    system Idle {
+        Sleeper {
+            doze() => on_doze {}
+        }
+    }
+
+    system States {
+        disjoint: Active, Dormant;
+
+        Active {
+            wake() => on_wake
+        }
+
+        Dormant {
+            sleep() => on_sleep
+        }
+    }
+}
+
+pub trait Layer {
+    fn layer(&self) -> i64;
+}
+
+pub trait Duplicate {
+    fn duplicate(&self) -> Box<SceneObject>;
+}
+
+handlers_define_system! Scene {
+    * : Duplicate;
+    counted;
+    debug_checks;
+    combine: (Layered, Camera);
+    clone_fn: duplicate;
+
+    Layered: Layer {
+        order_by: layer;
+        paint() => on_paint
+    }
+
+    exclusive Camera {
+        look() => on_look
+    }
+
+    unsafe RawDraw {
+        draw(ptr: *const u8) => on_draw
+    }
+
+    #[cfg(not(debug_assertions))]
+    Profiler {
+        profile() => on_profile
+    }
+
+    #[cfg(debug_assertions)]
+    DebugHandler {
+        inspect() => on_inspect
+    }
+
+    Audio {
+        feature: "audio";
+        play() => on_play
+    }
+}
+
+handlers_define_system! Outer {
+    prefix_handlers;
+
     InputHandler {
         input(input: char) => on_input
     }
 }
 
+handlers_define_system! Modular {
+    module;
+    priority;
+    context: Vec<i64>;
+    userdata;
+
+    InputHandler {
+        input(input: char) => on_modular_input
+    }
+}
+
+pub trait Pokeable {
+    fn wants_poke(&self) -> bool;
+}
+
+handlers_define_system! Shared {
+    storage: Rc;
+    dispatch_trait;
+
+    SharedHandler: Pokeable {
+        poke(amount: i64) => on_poke if wants_poke;
+        weigh() -> i64 => on_weigh in total_weight
+    }
+}
+
+pub struct SharedGrid {
+    pub cells: Vec<Vec<Test>>
+}
+
+impl SharedDispatch for SharedGrid {
+    fn for_each_object<F: FnMut(&mut SharedObject)>(&mut self, mut f: F) {
+        for row in self.cells.iter_mut() {
+            for cell in row.iter_mut() {
+                f(cell);
+            }
+        }
+    }
+}
+
+pub trait Spawnable {
+    fn can_spawn(&self) -> bool;
+    fn spent(&self) -> bool;
+    fn rank(&self) -> i64;
+}
+
+handlers_define_system! Spawner {
+    * : Spawnable;
+    handler_bounds: Renderable;
+    commands;
+    capacity: 3;
+    can_add_fn: can_spawn;
+    cleanup_fn: spent;
+    sort_key: rank -> i64;
+
+    SpawnHandler {
+        spawn(n: i64) => on_spawn
+    }
+}
+
+pub struct Nested {
+    pub inner: Prefixed
+}
+
 pub struct Test {
     pub n: i64
 }
@@ -28,6 +235,127 @@ impl InputHandler for Test {
         println!("{}: {}", self.n, input);
         self.n = self.n + 1;
     }
+
+    fn on_feed(&mut self, data: &[u8], weights: [i64; 2]) {
+        self.n += data.len() as i64 * weights[0] + weights[1];
+    }
+
+    fn on_focus(&mut self, target: Option<SystemIndex>) {
+        if target.is_none() {
+            println!("{}: lost focus", self.n);
+        }
+    }
+
+    fn on_accept(&mut self, visitor: &mut Visitor) {
+        visitor.visit(self.n);
+    }
+
+    fn on_deliver(&mut self, message: Box<String>) {
+        println!("{} received {}", self.n, message);
+    }
+
+    fn on_log(&self) {
+        println!("Logging {}", self.n);
+    }
+}
+
+impl PrefixedInputHandler for Test {
+    fn on_prefixed_input(&mut self, input: char) {
+        println!("Prefixed {}: {}", self.n, input);
+    }
+
+    fn on_peek(&self) -> i64 {
+        self.n
+    }
+}
+
+impl modular::InputHandler for Test {
+    fn on_modular_input(&mut self, context: &mut Vec<i64>, data: &mut Any, input: char) {
+        println!("Modular {}: {}", self.n, input);
+        context.push(self.n);
+
+        if let Some(calls) = data.downcast_mut::<usize>() {
+            *calls += 1;
+        }
+    }
+}
+
+impl Active for Test {
+    fn on_wake(&mut self) {
+        println!("{} is awake", self.n);
+    }
+}
+
+impl Dormant for Test {
+    fn on_sleep(&mut self) {
+        println!("{} is asleep", self.n);
+    }
+}
+
+impl Active for Nested {
+    fn on_wake(&mut self) {
+        println!("Nested is awake");
+    }
+}
+
+impl SharedHandler for Test {
+    fn on_poke(&mut self, amount: i64) {
+        self.n += amount;
+    }
+
+    fn on_weigh(&mut self) -> i64 {
+        self.n
+    }
+}
+
+impl Pokeable for Test {
+    fn wants_poke(&self) -> bool {
+        self.n < 100
+    }
+}
+
+impl Spawnable for Test {
+    fn can_spawn(&self) -> bool {
+        self.n < 100
+    }
+
+    fn spent(&self) -> bool {
+        self.n > 80
+    }
+
+    fn rank(&self) -> i64 {
+        self.n
+    }
+}
+
+impl SpawnHandler for Test {
+    fn on_spawn(&mut self, commands: &mut Vec<SpawnerCommand>, n: i64) {
+        commands.push(SpawnerCommand::Add(box Test{n: self.n + n}));
+    }
+}
+
+impl Layer for Test {
+    fn layer(&self) -> i64 {
+        -self.n
+    }
+}
+
+impl Layered for Test {
+    fn on_paint(&mut self) {
+        println!("Painting layer {}", self.layer());
+    }
+}
+
+impl Duplicate for Test {
+    fn duplicate(&self) -> Box<SceneObject> {
+        Box::new(Test{n: self.n})
+    }
+}
+
+impl Camera for Test {
+    fn on_look(&mut self) {
+        println!("Looking from {}", self.n);
+    }
 }
 
 impl Renderable for Test {
@@ -38,12 +366,272 @@ impl Renderable for Test {
     fn update(&mut self, x: i64) {
         self.n += x;
     }
+
+    fn id(&self) -> u64 {
+        self.n as u64
+    }
 }
 
 handlers_impl_object! System { 
     Test: InputHandler 
 }
 
+handlers_impl_object! Empty {
+    Test:
+}
+
+handlers_impl_object! Outer {
+    Nested: InputHandler => inner
+}
+
+handlers_impl_object! Modular {
+    Test: InputHandler
+}
+
+handlers_impl_object! Shared {
+    Test: SharedHandler
+}
+
+handlers_impl_object! Spawner {
+    Test: SpawnHandler
+}
+
+handlers_impl_object! Scene {
+    Test: Camera, Layered
+}
+
+pub struct Canvas {
+    pub drawn: Rc<std::cell::Cell<u8>>
+}
+
+impl Duplicate for Canvas {
+    fn duplicate(&self) -> Box<SceneObject> {
+        Box::new(Canvas{drawn: self.drawn.clone()})
+    }
+}
+
+unsafe impl RawDraw for Canvas {
+    fn on_draw(&mut self, ptr: *const u8) {
+        self.drawn.set(unsafe { *ptr });
+    }
+}
+
+handlers_impl_object! Scene {
+    Canvas: RawDraw
+}
+
+pub struct Backdrop;
+
+impl Duplicate for Backdrop {
+    fn duplicate(&self) -> Box<SceneObject> {
+        Box::new(Backdrop)
+    }
+}
+
+impl Layer for Backdrop {
+    fn layer(&self) -> i64 {
+        100
+    }
+}
+
+impl Layered for Backdrop {
+    fn on_paint(&mut self) {
+        println!("Painting the backdrop");
+    }
+}
+
+handlers_impl_object! Scene {
+    Backdrop: Layered
+}
+
+handlers_impl_object! Prefixed {
+    Test: InputHandler
+}
+
+handlers_impl_object! States {
+    Test: Active, Dormant
+}
+
+handlers_impl_object! States {
+    Nested: Active
+}
+
+handlers_define_system! Workers {
+    * : Renderable, Send;
+
+    Worker: Send {
+        par work(x: i64) => on_work
+    }
+}
+
+impl Worker for Test {
+    fn on_work(&mut self, x: i64) {
+        self.update(x);
+    }
+}
+
+handlers_impl_object! Workers {
+    Test: Worker
+}
+
+handlers_define_system! Pipeline {
+    Stage {
+        prepare(tag: String) => on_prepare;
+        finish(tag: String) => on_finish
+    }
+
+    phase early { prepare }
+    phase late { finish }
+}
+
+pub struct Recorder {
+    pub name: &'static str,
+    pub log: Rc<RefCell<Vec<String>>>
+}
+
+impl Stage for Recorder {
+    fn on_prepare(&mut self, tag: String) {
+        self.log.borrow_mut().push(format!("{} prepare {}", self.name, tag));
+    }
+
+    fn on_finish(&mut self, tag: String) {
+        self.log.borrow_mut().push(format!("{} finish {}", self.name, tag));
+    }
+}
+
+handlers_impl_object! Pipeline {
+    Recorder: Stage
+}
+
+handlers_define_system! Guarded {
+    debug_checks;
+
+    Nested {
+        nest(system: *mut Guarded) => on_nest
+    }
+}
+
+pub struct Nester {
+    pub seen: Rc<std::cell::Cell<bool>>,
+    pub refused: Rc<std::cell::Cell<bool>>
+}
+
+impl Nested for Nester {
+    // Only the dispatching flag is read before the nested signal is refused
+    fn on_nest(&mut self, system: *mut Guarded) {
+        self.seen.set(unsafe { &*system }.is_dispatching());
+
+        let nested = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe { (*system).nest(system) }));
+        self.refused.set(nested.is_err());
+    }
+}
+
+handlers_impl_object! Guarded {
+    Nester: Nested
+}
+
+handlers_define_system! Bare {
+    no_std;
+
+    Beeper {
+        beep() -> u8 => on_beep
+    }
+}
+
+pub struct Buzzer;
+
+impl Beeper for Buzzer {
+    fn on_beep(&mut self) -> u8 {
+        3
+    }
+}
+
+handlers_impl_object! Bare {
+    Buzzer: Beeper
+}
+
+handlers_define_system! Outbox {
+    Letter {
+        move post(stamp: char) => on_post
+    }
+
+    Keepsake {
+        keep() => on_keep;
+        fold worth(bonus: u32) -> u32 => on_worth
+    }
+}
+
+pub struct Envelope {
+    pub posted: Rc<std::cell::Cell<usize>>
+}
+
+impl Letter for Envelope {
+    fn on_post(self: Box<Self>, _: char) {
+        self.posted.set(self.posted.get() + 1);
+    }
+}
+
+pub struct Draft;
+
+impl Keepsake for Draft {
+    fn on_keep(&mut self) {}
+
+    fn on_worth(&mut self, bonus: u32) -> u32 {
+        5 + bonus
+    }
+}
+
+handlers_impl_object! Outbox {
+    Envelope: Letter
+}
+
+handlers_impl_object! Outbox {
+    Draft: Keepsake
+}
+
+fn type_through(signals: &mut SystemSignals, input: char) {
+    signals.input(input);
+}
+
+fn generated_items() -> Vec<String> {
+    use handlers::system::{SystemInfo, HandlerInfo, HandlerFnInfo};
+    use syntax::codemap::DUMMY_SP;
+    use syntax::parse::token::str_to_ident;
+    use syntax::print::pprust;
+
+    let mut handler = HandlerInfo::new(str_to_ident("Ping"));
+    handler.fns.push(HandlerFnInfo::new(str_to_ident("ping"), str_to_ident("on_ping"), Vec::new(), None));
+
+    let mut system = SystemInfo::new(str_to_ident("Golden"), DUMMY_SP);
+    system.handlers.push(handler);
+
+    system.generate_items().iter().map(|item| pprust::item_to_string(item)).collect()
+}
+
+fn validation_messages() -> Vec<String> {
+    use handlers::system::SystemInfo;
+    use syntax::codemap::DUMMY_SP;
+    use syntax::parse::token::str_to_ident;
+
+    let mut system = SystemInfo::new(str_to_ident("Refusing"), DUMMY_SP);
+    system.from_object = true;
+    system.can_add_fn = Some(str_to_ident("can_add"));
+
+    handlers::validate_system(&system, DUMMY_SP).into_iter().map(|error| error.message).collect()
+}
+
+fn signal_messages() -> Vec<String> {
+    use handlers::system::HandlerFnInfo;
+    use syntax::codemap::DUMMY_SP;
+    use syntax::parse::token::str_to_ident;
+
+    let mut function = HandlerFnInfo::new(str_to_ident("total"), str_to_ident("on_total"), Vec::new(), None);
+    function.fold = true;
+    function.single = true;
+
+    handlers::validate_signal(&function, DUMMY_SP).into_iter().map(|error| error.message).collect()
+}
+
 fn main() {
     let mut system = System::new();
     let idx = system.add(box Test{n: 15});
@@ -53,7 +641,23 @@ fn main() {
     system.add(box Test{n: 20});
     for obj in system.iter() { obj.render(); }
     system.input('l');
+    type_through(&mut system, 'o');
     system.hover();
+    system.click_default(3);
+    system.press(1, 2, 'p');
+    system.annotate(String::from("note"));
+    system.gather('g');
+    system.run_phases('r');
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut pipeline = Pipeline::new();
+    pipeline.add(box Recorder{name: "a", log: log.clone()});
+    pipeline.add(box Recorder{name: "b", log: log.clone()});
+    pipeline.run_phases(String::from("x"));
+    assert_eq!(*log.borrow(), vec!["a prepare x", "b prepare x", "a finish x", "b finish x"]);
+    let data = [1, 2, 3];
+    system.feed(&data, [2, 1]);
+    system.feed(&data[..1], [0, 0]);
     system.input('l');
     let obj = system.remove(idx).unwrap();
     obj.render();
@@ -63,4 +667,282 @@ fn main() {
     system.add(box Test{n: 25});
     for obj in system.iter() { obj.render(); }
     for obj in system.iter_mut() { obj.update(-10); obj.render(); }
+    for (handle, obj) in system.iter_enumerated() {
+        assert!(system.get(handle).is_some());
+        obj.render();
+    }
+    for (_, obj) in system.iter_enumerated_mut() { obj.update(5); }
+    assert_eq!(system.iter_ordered().count(), 2);
+    assert_eq!(system.as_slice().len(), 2);
+    system.sort_by(|a, b| b.id().cmp(&a.id()));
+    assert!(system.as_slice()[0].id() > system.as_slice()[1].id());
+    assert_eq!(system.first().unwrap().id(), system.as_slice()[0].id());
+    system.last_mut().unwrap().update(1);
+    assert_eq!(system.last().unwrap().id(), system.as_slice()[1].id());
+    for obj in system.iter_ordered_mut() { obj.render(); }
+    assert_eq!(system.count_where(|obj| obj.as_input_handler().is_some()), 2);
+    assert_eq!(system.handlers_of(idx).len(), 0);
+
+    let id = system.iter().next().unwrap().id();
+    assert!(system.find_by_id(id).is_some());
+    assert!(system.find_by_id_mut(u64::max_value()).is_none());
+    assert_eq!(system.position_of(id), Some(0));
+    assert!(system.position_of(u64::max_value()).is_none());
+
+    let handle = system.iter_enumerated().next().unwrap().0;
+    assert_eq!(system.handlers_of(handle), vec!["InputHandler"]);
+    assert!(system.dump_membership()[0] == (handle, vec!["InputHandler"]));
+    assert!(system.as_input_handler_at(handle).is_some());
+    assert!(system.as_mouse_handler_mut_at(handle).is_none());
+    assert_eq!(system.input_handler_refs().count(), 2);
+    assert_eq!(system.mouse_handler_refs().count(), 0);
+    let inputs: InputHandlerIter = system.input_handler_iter();
+    assert_eq!(inputs.count(), 2);
+    assert_eq!(system.mouse_handler_iter().count(), 0);
+
+    assert_eq!(system.get(handle).unwrap().type_name(), "Test");
+    assert_eq!(system[handle].type_name(), "Test");
+    system[handle].update(0);
+    assert!(system.get_as::<Test>(handle).is_some());
+    assert!(system.get_as_mut::<Nested>(handle).is_none());
+
+    assert_eq!(system.echo(3), vec![3, 3]);
+    system.focus(Some(handle));
+    system.focus(None);
+    system.drag((4, 2));
+
+    let mut counter = Counter{visited: 0};
+    system.accept(&mut counter);
+    system.accept(&mut counter);
+    assert_eq!(counter.visited, 4);
+    assert_eq!(system.pick("first", "second"), vec!["first", "first"]);
+    assert_eq!(system.outlive("long", "short"), vec!["long", "long"]);
+    assert!(system.send_input_to(handle, 'T'));
+    assert!(!system.send_input_to(idx, 'X'));
+    assert!(system.send_deliver_to(handle, Box::new("a message".to_string())));
+    system.log();
+    {
+        let shared = &system;
+        shared.log();
+        assert!(shared.send_log_to(handle));
+    }
+
+    let old = system.replace(handle, box Test{n: 70}).ok().unwrap();
+    old.render();
+    assert_eq!(system.get(handle).unwrap().id(), 70);
+    assert!(system.replace(idx, box Test{n: 75}).is_err());
+
+    let snapshot = system.checkpoint();
+    system.add(box Test{n: 55});
+    assert_eq!(system.restore(snapshot).len(), 1);
+    assert_eq!(system.iter().count(), 2);
+
+    let golden = generated_items();
+    assert!(golden.iter().any(|item| item.contains("trait Ping")));
+    assert!(golden.iter().any(|item| item.contains("fn ping(&mut self)")));
+    assert_eq!(validation_messages(), vec!["from_object cannot be used with capacity, can_add_fn or disjoint, as the object could be refused"]);
+    assert_eq!(signal_messages(), vec![
+        "Folded signals must return a value",
+        "Single signals cannot be parallel, consuming, folded or accumulated"
+    ]);
+
+    let mut idle = Idle::new();
+    idle.doze();
+    assert_eq!(idle.iter().count(), 0);
+
+    let mut workers = Workers::new();
+    let first = workers.add(box Test{n: 1});
+    let second = workers.add(box Test{n: 2});
+    workers.work(10);
+    assert_eq!(workers.get(first).unwrap().id(), 11);
+    assert_eq!(workers.get(second).unwrap().id(), 12);
+
+    let mut empty = Empty::new();
+    assert!(empty.first().is_none());
+    let idx = empty.add(box Test{n: 30});
+    assert_eq!(empty.iter().count(), 1);
+    assert_eq!(empty.membership(), []);
+    assert!(empty.remove(idx).is_some());
+    assert_eq!(empty.iter().count(), 0);
+    empty.shrink_to_fit();
+    assert_eq!(empty.capacity(), 0);
+    empty.reserve(8);
+    assert!(empty.capacity() >= 8);
+    assert_eq!(empty.add_all(vec![Test{n: 31}, Test{n: 32}]).len(), 2);
+    let mut other = Empty::new();
+    other.add(box Test{n: 33});
+    assert_eq!(empty.merge(other).len(), 1);
+    assert_eq!(empty.iter().count(), 3);
+
+    let built = EmptyBuilder::new().add(box Test{n: 34}).add(box Test{n: 35}).build();
+    assert_eq!(built.iter().count(), 2);
+    let single: Box<EmptyObject> = box Test{n: 36};
+    assert_eq!(Empty::from(single).iter().count(), 1);
+
+    let mut prefixed = Prefixed::new();
+    prefixed.add(box Test{n: 35});
+    prefixed.input('P');
+    assert_eq!((&prefixed).peek(), vec![35]);
+
+    let mut outer = Outer::new();
+    outer.add(box Nested{inner: prefixed});
+    outer.input('N');
+
+    let mut states = States::new();
+    assert!(states.add(box Nested{inner: Prefixed::new()}).is_ok());
+    assert!(states.add(box Test{n: 36}).is_err());
+    states.wake();
+
+    let mut modular = Modular::new();
+    let idx: ModularIndex = modular.add(box Test{n: 50});
+    let front = modular.add_with_priority(box Test{n: 51}, -1);
+    let mut order = Vec::new();
+    let mut calls = 0usize;
+    modular.input(&mut order, &mut calls, 'M');
+    assert_eq!(order, vec![51, 50]);
+    assert_eq!(calls, 2);
+    assert!(modular.input_targets() == vec![front, idx]);
+    assert!(modular.remove(idx).is_some());
+
+    let object = Rc::new(RefCell::new(Test{n: 60}));
+    let mut shared = Shared::new();
+    shared.add(object.clone());
+    shared.poke(5);
+    assert_eq!(object.borrow().n, 65);
+    object.borrow_mut().n = 150;
+    shared.poke(5);
+    assert_eq!(object.borrow().n, 150);
+    object.borrow_mut().n = 65;
+    shared.weigh();
+    shared.weigh();
+    assert_eq!(shared.take_total_weight(), 130);
+    assert_eq!(*shared.total_weight(), 0);
+
+    let mut grid = SharedGrid{cells: vec![vec![Test{n: 1}, Test{n: 2}], vec![Test{n: 150}]]};
+    grid.poke(2);
+    assert_eq!(grid.cells[0][1].n, 4);
+    assert_eq!(grid.cells[1][0].n, 150);
+    SharedDispatch::poke(&mut shared, 1);
+    assert_eq!(object.borrow().n, 66);
+
+    let mut spawner = Spawner::new();
+    let first = spawner.add(box Test{n: 80}).unwrap();
+    assert!(spawner.add(box Test{n: 150}).is_err());
+    spawner.spawn(1);
+    assert_eq!(spawner.iter().count(), 2);
+    assert!(spawner.find(81).unwrap().spent());
+    assert!(spawner.find(79).is_none());
+    spawner.spawn(1);
+    assert_eq!(spawner.iter().count(), 3);
+    assert_eq!(spawner.take_rejected().len(), 1);
+    assert!(spawner.take_rejected().is_empty());
+    spawner.spawn(1);
+    assert_eq!(spawner.iter().count(), 3);
+    assert_eq!(spawner.take_rejected().len(), 3);
+    assert!(spawner.add(box Test{n: 90}).is_err());
+    assert!(spawner.remove(first).is_some());
+    assert_eq!(spawner.cleanup().len(), 2);
+    assert_eq!(spawner.iter().count(), 0);
+
+    let mut scene = Scene::new();
+    let back = scene.add(box Test{n: 40});
+    let camera = scene.add(box Test{n: 45});
+    assert_eq!(scene.look(), 1);
+    assert!(!scene.is_dispatching());
+    assert_eq!(scene.paint(), 2);
+    assert!(scene.paint_targets() == vec![camera, back]);
+    assert!(scene.look_targets() == vec![camera]);
+    assert_eq!(scene.iter_layered_and_camera().count(), 2);
+    scene.set_layered_enabled(false);
+    assert_eq!(scene.paint(), 0);
+    assert!(scene.paint_targets().is_empty());
+    assert!(!scene.will_dispatch_paint());
+    scene.set_layered_enabled(true);
+    assert_eq!(&scene.membership()[..4], &[2, 1, 0, 0]);
+    assert_eq!(scene.handler_counts()[1], ("Camera", 1));
+    scene.rebuild_indices();
+    assert_eq!(&scene.membership()[..4], &[2, 1, 0, 0]);
+    assert_eq!(scene.look(), 1);
+    let _buffer = [0u8; SCENE_NUM_HANDLERS];
+    #[cfg(feature = "audio")]
+    let num_handlers = 5;
+    #[cfg(not(feature = "audio"))]
+    let num_handlers = 4;
+    assert_eq!(SCENE_NUM_HANDLERS, num_handlers);
+    assert_eq!(scene.handler_counts().len(), num_handlers);
+    let mut capacities = [0; SCENE_NUM_HANDLERS];
+    capacities[0] = 2;
+    capacities[1] = 1;
+    let mut copy = Scene::with_handler_capacities(capacities);
+    copy.add_all(vec![Test{n: 1}, Test{n: 2}]);
+    assert!(scene.same_membership(&copy));
+    let cloned = scene.clone();
+    assert!(cloned.same_membership(&scene));
+    assert_eq!(scene.handler_mask_of(camera), Some(SCENE_LAYERED_MASK | SCENE_CAMERA_MASK));
+    assert_eq!(scene.handles_matching(SCENE_CAMERA_MASK).len(), 2);
+    assert_eq!(cloned.get(camera).unwrap().type_name(), "Test");
+    assert!(scene.the_camera().is_some());
+    scene.remove(camera);
+    assert!(scene.the_camera_mut().is_none());
+    assert_eq!(scene.handler_mask_of(camera), None);
+    assert!(!scene.will_dispatch_look());
+    assert_eq!(&scene.membership()[..4], &[1, 0, 0, 0]);
+    let mut lone = Scene::new();
+    lone.add(box Test{n: 60});
+    let gone = lone.add(box Test{n: 65});
+    lone.remove(gone);
+    assert!(scene.same_membership(&lone));
+    assert!(scene.paint_targets() == vec![back]);
+    let front = scene.add(box Test{n: 50});
+    assert!(scene.paint_targets() == vec![front, back]);
+    assert_eq!(scene.paint(), 2);
+
+    let drawn = Rc::new(std::cell::Cell::new(0));
+    let mut canvas = Scene::new();
+    canvas.add(box Canvas{drawn: drawn.clone()});
+    let byte = 7u8;
+    assert_eq!(canvas.draw(&byte), 1);
+    assert_eq!(drawn.get(), 7);
+    let backdrop = canvas.add(box Backdrop);
+    canvas.add(box Test{n: 5});
+    assert_eq!(canvas.paint(), 2);
+    assert_eq!(canvas.iter_layered_and_camera().count(), 1);
+    let snapshot = canvas.checkpoint();
+    assert!(canvas.replace(backdrop, box Canvas{drawn: drawn.clone()}).is_ok());
+    assert!(canvas.restore(snapshot).is_empty());
+    assert_eq!(canvas.paint(), 1);
+    assert_eq!(canvas.draw(&byte), 1);
+
+    let seen = Rc::new(std::cell::Cell::new(false));
+    let refused = Rc::new(std::cell::Cell::new(false));
+    let mut guarded = Guarded::new();
+    guarded.add(box Nester{seen: seen.clone(), refused: refused.clone()});
+    let raw: *mut Guarded = &mut guarded;
+    guarded.nest(raw);
+    assert!(seen.get());
+    assert_eq!(refused.get(), cfg!(debug_assertions));
+
+    let posted = Rc::new(std::cell::Cell::new(0));
+    let mut outbox = Outbox::new();
+    let sent = outbox.add(box Envelope{posted: posted.clone()});
+    outbox.add(box Envelope{posted: posted.clone()});
+    let draft = outbox.add(box Draft);
+    outbox.post('s');
+    assert_eq!(posted.get(), 2);
+    assert_eq!(outbox.iter().count(), 1);
+    assert!(outbox.get(sent).is_none());
+    assert!(outbox.get(draft).is_some());
+    outbox.post('s');
+    assert_eq!(posted.get(), 2);
+    outbox.add(box Draft);
+    assert_eq!(outbox.worth(1, 10, |total, worth| total + worth), 22);
+    outbox.set_keepsake_enabled(false);
+    assert_eq!(outbox.worth(1, 10, |total, worth| total + worth), 10);
+
+    let mut bare = Bare::new();
+    let buzzer = bare.add(box Buzzer);
+    bare.add(box Buzzer);
+    assert_eq!(bare.beep(), vec![3, 3]);
+    assert!(bare.remove(buzzer).is_some());
+    assert_eq!(bare.iter().count(), 1);
 }